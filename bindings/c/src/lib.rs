@@ -5,15 +5,17 @@ use std::io;
 use std::os::raw::{c_char, c_void};
 use std::path::Path;
 use std::ptr;
+use std::slice;
+use std::time::Duration;
 use std::{ffi::CStr, ops::Deref};
 
 use futures::prelude::*;
 use log::info;
-use speedupdate::link::{AutoRepository, RemoteRepository};
+use speedupdate::link::{AutoRepository, HttpsRepositoryBuilder, RemoteRepository};
 use speedupdate::metadata::v1::State;
 use speedupdate::metadata::{CleanName, Versions};
 use speedupdate::workspace::progress::SharedUpdateProgress;
-use speedupdate::workspace::{UpdateError, UpdateOptions, Workspace};
+use speedupdate::workspace::{BandwidthLimiter, UpdateError, UpdateOptions, Workspace};
 
 #[repr(C)]
 pub struct CLocalState {
@@ -93,8 +95,9 @@ pub extern "C" fn c_version_info(
         ))
     };
 
-    let res = AutoRepository::new(repository_url, auth).map_err(UpdateError::Repository).and_then(
-        |repository| {
+    let res = AutoRepository::new(repository_url, auth, None)
+        .map_err(UpdateError::Repository)
+        .and_then(|repository| {
             let version = if let Some(version) = version {
                 future::ok(CleanName::new(version.to_owned()).unwrap()).boxed_local()
             } else {
@@ -118,8 +121,7 @@ pub extern "C" fn c_version_info(
                     })
                     .map_err(UpdateError::Repository),
             )
-        },
-    );
+        });
     match res {
         Ok(Some(version)) => {
             let revision = CString::new(version.revision.deref()).unwrap();
@@ -180,6 +182,196 @@ pub extern "C" fn c_update_workspace(
     goal_version: *const c_char,
     progress_callback: extern "C" fn(*const c_char, *const CGlobalProgression, *mut c_void) -> u8,
     data: *mut c_void,
+) -> u8 {
+    let limiter = CBandwidthLimiter(BandwidthLimiter::default());
+    c_update_workspace_limited(
+        workspace_path,
+        repository_url,
+        username,
+        password,
+        goal_version,
+        &limiter,
+        progress_callback,
+        data,
+    )
+}
+
+/// Download bandwidth limiter, shared with running updates
+pub struct CBandwidthLimiter(BandwidthLimiter);
+
+/// Create a download bandwidth limiter of `limit` bytes per second, `0` means
+/// unlimited
+#[no_mangle]
+pub extern "C" fn c_bandwidth_limiter_new(limit: u64) -> *mut CBandwidthLimiter {
+    Box::into_raw(Box::new(CBandwidthLimiter(BandwidthLimiter::new(Some(limit)))))
+}
+
+/// Change the limit to `limit` bytes per second, `0` means unlimited
+///
+/// Can be called from any thread, even while an update is running.
+#[no_mangle]
+pub extern "C" fn c_bandwidth_limiter_set_limit(limiter: *const CBandwidthLimiter, limit: u64) {
+    let limiter = unsafe { &*limiter };
+    limiter.0.set_limit(Some(limit));
+}
+
+#[no_mangle]
+pub extern "C" fn c_bandwidth_limiter_free(limiter: *mut CBandwidthLimiter) {
+    if !limiter.is_null() {
+        drop(unsafe { Box::from_raw(limiter) });
+    }
+}
+
+/// Same as `c_update_workspace`, downloads are limited by `limiter`
+#[no_mangle]
+pub extern "C" fn c_update_workspace_limited(
+    workspace_path: *const c_char,
+    repository_url: *const c_char,
+    username: *const c_char,
+    password: *const c_char,
+    goal_version: *const c_char,
+    limiter: *const CBandwidthLimiter,
+    progress_callback: extern "C" fn(*const c_char, *const CGlobalProgression, *mut c_void) -> u8,
+    data: *mut c_void,
+) -> u8 {
+    let mut builder = CHttpsRepositoryBuilder(HttpsRepositoryBuilder::new());
+    if !username.is_null() && !password.is_null() {
+        c_https_repository_builder_set_basic_auth(&mut builder, username, password);
+    }
+    c_update_workspace_with_builder(
+        workspace_path,
+        repository_url,
+        goal_version,
+        &builder,
+        limiter,
+        progress_callback,
+        data,
+    )
+}
+
+/// HTTP(S) repositories authentication and network settings
+pub struct CHttpsRepositoryBuilder(HttpsRepositoryBuilder);
+
+#[no_mangle]
+pub extern "C" fn c_https_repository_builder_new() -> *mut CHttpsRepositoryBuilder {
+    Box::into_raw(Box::new(CHttpsRepositoryBuilder(HttpsRepositoryBuilder::new())))
+}
+
+#[no_mangle]
+pub extern "C" fn c_https_repository_builder_free(builder: *mut CHttpsRepositoryBuilder) {
+    if !builder.is_null() {
+        drop(unsafe { Box::from_raw(builder) });
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn c_https_repository_builder_set_basic_auth(
+    builder: *mut CHttpsRepositoryBuilder,
+    username: *const c_char,
+    password: *const c_char,
+) {
+    let builder = unsafe { &mut *builder };
+    let username = unsafe { CStr::from_ptr(username) }.to_str().unwrap();
+    let password = if password.is_null() {
+        None
+    } else {
+        Some(unsafe { CStr::from_ptr(password) }.to_str().unwrap())
+    };
+    builder.0.set_basic_auth(username, password);
+}
+
+#[no_mangle]
+pub extern "C" fn c_https_repository_builder_set_bearer_token(
+    builder: *mut CHttpsRepositoryBuilder,
+    token: *const c_char,
+) {
+    let builder = unsafe { &mut *builder };
+    let token = unsafe { CStr::from_ptr(token) }.to_str().unwrap();
+    builder.0.set_bearer_token(token);
+}
+
+/// Timeouts in milliseconds, `0` keeps the default timeout
+#[no_mangle]
+pub extern "C" fn c_https_repository_builder_set_timeouts(
+    builder: *mut CHttpsRepositoryBuilder,
+    connect_timeout_ms: u64,
+    read_timeout_ms: u64,
+) {
+    let builder = unsafe { &mut *builder };
+    if connect_timeout_ms > 0 {
+        builder.0.set_connect_timeout(Duration::from_millis(connect_timeout_ms));
+    }
+    if read_timeout_ms > 0 {
+        builder.0.set_read_timeout(Duration::from_millis(read_timeout_ms));
+    }
+}
+
+/// Returns 0 and calls `error_callback` if `proxy_url` is invalid
+#[no_mangle]
+pub extern "C" fn c_https_repository_builder_add_proxy(
+    builder: *mut CHttpsRepositoryBuilder,
+    proxy_url: *const c_char,
+    error_callback: extern "C" fn(*const c_char, *mut c_void),
+    data: *mut c_void,
+) -> u8 {
+    let builder = unsafe { &mut *builder };
+    let proxy_url = unsafe { CStr::from_ptr(proxy_url) }.to_str().unwrap();
+    c_result(builder.0.add_proxy(proxy_url), error_callback, data)
+}
+
+/// Returns 0 and calls `error_callback` if `pem` isn't a valid certificate
+/// bundle
+#[no_mangle]
+pub extern "C" fn c_https_repository_builder_add_root_certificates_pem(
+    builder: *mut CHttpsRepositoryBuilder,
+    pem: *const u8,
+    pem_len: usize,
+    error_callback: extern "C" fn(*const c_char, *mut c_void),
+    data: *mut c_void,
+) -> u8 {
+    let builder = unsafe { &mut *builder };
+    let pem = unsafe { slice::from_raw_parts(pem, pem_len) };
+    c_result(builder.0.add_root_certificates_pem(pem), error_callback, data)
+}
+
+/// Returns 0 and calls `error_callback` if `pem` doesn't contain a valid
+/// certificate and private key
+#[no_mangle]
+pub extern "C" fn c_https_repository_builder_set_client_identity_pem(
+    builder: *mut CHttpsRepositoryBuilder,
+    pem: *const u8,
+    pem_len: usize,
+    error_callback: extern "C" fn(*const c_char, *mut c_void),
+    data: *mut c_void,
+) -> u8 {
+    let builder = unsafe { &mut *builder };
+    let pem = unsafe { slice::from_raw_parts(pem, pem_len) };
+    c_result(builder.0.set_client_identity_pem(pem), error_callback, data)
+}
+
+fn c_result<E: std::fmt::Display>(
+    res: Result<(), E>,
+    error_callback: extern "C" fn(*const c_char, *mut c_void),
+    data: *mut c_void,
+) -> u8 {
+    if let Err(err) = &res {
+        let err = CString::new(format!("{}", err)).unwrap();
+        error_callback(err.as_ptr(), data);
+    }
+    u8::from(res.is_ok())
+}
+
+/// Same as `c_update_workspace_limited`, HTTP(S) repositories are built by
+/// `builder`, `limiter` is nullable
+#[no_mangle]
+pub extern "C" fn c_update_workspace_with_builder(
+    workspace_path: *const c_char,
+    repository_url: *const c_char,
+    goal_version: *const c_char,
+    builder: *const CHttpsRepositoryBuilder,
+    limiter: *const CBandwidthLimiter,
+    progress_callback: extern "C" fn(*const c_char, *const CGlobalProgression, *mut c_void) -> u8,
+    data: *mut c_void,
 ) -> u8 {
     let _ = env_logger::try_init();
     let workspace_path = unsafe { CStr::from_ptr(workspace_path) }.to_str().unwrap();
@@ -189,40 +381,20 @@ pub extern "C" fn c_update_workspace(
     } else {
         Some(unsafe { CStr::from_ptr(goal_version) }.to_str().unwrap())
     };
-    let auth = if username.is_null() || password.is_null() {
-        None
+    let builder = &unsafe { &*builder }.0;
+    let limiter = if limiter.is_null() {
+        BandwidthLimiter::default()
     } else {
-        Some((
-            unsafe { CStr::from_ptr(username) }.to_str().unwrap(),
-            unsafe { CStr::from_ptr(password) }.to_str().unwrap(),
-        ))
+        unsafe { &*limiter }.0.clone()
     };
-    let res = update_workspace(workspace_path, repository_url, auth, goal_version, |progress| {
-        let state = progress.borrow();
-        let progress = state.histogram.progress();
-        let speed = state.histogram.speed().progress_per_sec();
-        let cprogress = CGlobalProgression {
-            packages_start: state.downloading_package_idx,
-            packages_end: state.steps.len(),
-            downloaded_files_start: progress.downloaded_files,
-            downloaded_files_end: state.download_files,
-            downloaded_bytes_start: progress.downloaded_bytes,
-            downloaded_bytes_end: state.download_bytes,
-            applied_files_start: progress.applied_files,
-            applied_files_end: state.apply_files,
-            applied_input_bytes_start: progress.applied_input_bytes,
-            applied_input_bytes_end: state.apply_input_bytes,
-            applied_output_bytes_start: progress.applied_output_bytes,
-            applied_output_bytes_end: state.apply_output_bytes,
-            failed_files: progress.failed_files,
-            downloaded_files_per_sec: speed.downloaded_files_per_sec,
-            downloaded_bytes_per_sec: speed.downloaded_bytes_per_sec,
-            applied_files_per_sec: speed.applied_files_per_sec,
-            applied_input_bytes_per_sec: speed.applied_input_bytes_per_sec,
-            applied_output_bytes_per_sec: speed.applied_output_bytes_per_sec,
-        };
-        progress_callback(ptr::null(), &cprogress, data) != 0
-    });
+    let res = update_workspace(
+        workspace_path,
+        repository_url,
+        builder,
+        goal_version,
+        limiter,
+        |progress| progress_callback(ptr::null(), &c_global_progression(progress), data) != 0,
+    );
     if let Err(err) = &res {
         let err = CString::new(format!("{}", err)).unwrap();
         progress_callback(err.as_ptr(), ptr::null(), data);
@@ -230,11 +402,38 @@ pub extern "C" fn c_update_workspace(
     u8::from(res.is_ok())
 }
 
+fn c_global_progression(progress: SharedUpdateProgress) -> CGlobalProgression {
+    let state = progress.borrow();
+    let progress = state.histogram.progress();
+    let speed = state.histogram.speed().progress_per_sec();
+    CGlobalProgression {
+        packages_start: state.downloading_package_idx,
+        packages_end: state.steps.len(),
+        downloaded_files_start: progress.downloaded_files,
+        downloaded_files_end: state.download_files,
+        downloaded_bytes_start: progress.downloaded_bytes,
+        downloaded_bytes_end: state.download_bytes,
+        applied_files_start: progress.applied_files,
+        applied_files_end: state.apply_files,
+        applied_input_bytes_start: progress.applied_input_bytes,
+        applied_input_bytes_end: state.apply_input_bytes,
+        applied_output_bytes_start: progress.applied_output_bytes,
+        applied_output_bytes_end: state.apply_output_bytes,
+        failed_files: progress.failed_files,
+        downloaded_files_per_sec: speed.downloaded_files_per_sec,
+        downloaded_bytes_per_sec: speed.downloaded_bytes_per_sec,
+        applied_files_per_sec: speed.applied_files_per_sec,
+        applied_input_bytes_per_sec: speed.applied_input_bytes_per_sec,
+        applied_output_bytes_per_sec: speed.applied_output_bytes_per_sec,
+    }
+}
+
 fn update_workspace<F>(
     workspace_path: &str,
     repository_url: &str,
-    auth: Option<(&str, &str)>,
+    builder: &HttpsRepositoryBuilder,
     goal_version: Option<&str>,
+    limiter: BandwidthLimiter,
     mut progress_callback: F,
 ) -> Result<(), UpdateError>
 where
@@ -246,7 +445,8 @@ where
         repository_url,
         goal_version.unwrap_or("latest")
     );
-    let repository = AutoRepository::new(repository_url, auth).map_err(UpdateError::Repository)?;
+    let repository =
+        AutoRepository::with_builder(repository_url, builder).map_err(UpdateError::Repository)?;
     let mut workspace =
         Workspace::open(Path::new(workspace_path)).map_err(UpdateError::LocalWorkspaceError)?;
 
@@ -254,7 +454,7 @@ where
         .update(
             &repository,
             goal_version.map(|v| CleanName::new(v.to_string()).unwrap()),
-            UpdateOptions::default(),
+            UpdateOptions { download_limiter: limiter, ..UpdateOptions::default() },
         )
         .try_take_while(|progress| future::ready(Ok(progress_callback(progress.clone()))));
     let work = stream.try_for_each(|_| async { Ok(()) });
@@ -307,3 +507,75 @@ fn copy_dir_recursive(from: &Path, to: &Path) -> io::Result<()> {
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Self-signed `localhost` certificate followed by its private key
+    const IDENTITY_PEM: &[u8] = include_bytes!("../../../lib/tests/data/identity.pem");
+
+    /// Counts errors in the `usize` pointed by `data`
+    extern "C" fn count_error(err: *const c_char, data: *mut c_void) {
+        assert!(!unsafe { CStr::from_ptr(err) }.to_bytes().is_empty());
+        unsafe { *(data as *mut usize) += 1 };
+    }
+
+    #[test]
+    fn https_repository_builder_network_settings() {
+        let mut errors = 0usize;
+        let data = &mut errors as *mut usize as *mut c_void;
+        let invalid_pem = b"-----BEGIN CERTIFICATE-----\n!!!!\n-----END CERTIFICATE-----\n";
+        let builder = c_https_repository_builder_new();
+
+        let proxy_url = CString::new("not a proxy url").unwrap();
+        let res =
+            c_https_repository_builder_add_proxy(builder, proxy_url.as_ptr(), count_error, data);
+        assert_eq!(res, 0);
+        let proxy_url = CString::new("http://proxy.example.com:3128").unwrap();
+        let res =
+            c_https_repository_builder_add_proxy(builder, proxy_url.as_ptr(), count_error, data);
+        assert_eq!(res, 1);
+
+        let (pem, pem_len) = (invalid_pem.as_ptr(), invalid_pem.len());
+        let res = c_https_repository_builder_add_root_certificates_pem(
+            builder,
+            pem,
+            pem_len,
+            count_error,
+            data,
+        );
+        assert_eq!(res, 0);
+        let res = c_https_repository_builder_set_client_identity_pem(
+            builder,
+            pem,
+            pem_len,
+            count_error,
+            data,
+        );
+        assert_eq!(res, 0);
+        let (pem, pem_len) = (IDENTITY_PEM.as_ptr(), IDENTITY_PEM.len());
+        let res = c_https_repository_builder_add_root_certificates_pem(
+            builder,
+            pem,
+            pem_len,
+            count_error,
+            data,
+        );
+        assert_eq!(res, 1);
+        let res = c_https_repository_builder_set_client_identity_pem(
+            builder,
+            pem,
+            pem_len,
+            count_error,
+            data,
+        );
+        assert_eq!(res, 1);
+        c_https_repository_builder_set_timeouts(builder, 5000, 0);
+        assert_eq!(errors, 3);
+
+        let url = "https://cdn.example.com/game/".parse().unwrap();
+        assert!(unsafe { &*builder }.0.build(url).is_ok());
+        c_https_repository_builder_free(builder);
+    }
+}
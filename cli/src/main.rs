@@ -1,10 +1,13 @@
 use std::io::Write;
 use std::path::{Path, PathBuf};
-use std::{env, io, process};
+use std::{env, fs, io, process};
 
 use clap::{crate_authors, crate_description, crate_name, crate_version, Arg, ArgAction, Command};
 use console::{style, Color};
 use indicatif::WeakProgressBar;
+use libspeedupdate::link::{HttpsRepositoryBuilder, S3Config, S3Repository};
+use libspeedupdate::repository::CoderOptions;
+use libspeedupdate::signature;
 use libspeedupdate::workspace::Workspace;
 use libspeedupdate::Repository;
 use log::{error, warn};
@@ -114,13 +117,75 @@ async fn main() {
                         .num_args(1)
                         .help("Repository path (defaults to current directory)"),
                 )
+                .arg(
+                    Arg::new("signing_key")
+                        .long("signing-key")
+                        .num_args(1)
+                        .help("File containing the base64 ed25519 key used to sign metadata"),
+                )
+                .arg(
+                    Arg::new("binary_metadata")
+                        .long("binary-metadata")
+                        .action(ArgAction::SetTrue)
+                        .help("Also write CBOR encoded package metadata when registering packages"),
+                )
+                .arg(
+                    Arg::new("compress_metadata")
+                        .long("compress-metadata")
+                        .num_args(1)
+                        .help("Also write metadata compressed with this coder (zstd or brotli)"),
+                )
+                .arg(
+                    Arg::new("publish_to")
+                        .long("publish-to")
+                        .num_args(1)
+                        .help("Also upload written files to this S3 bucket (s3://bucket/prefix)"),
+                )
                 .subcommand(
                     Command::new("status")
                         .about("Show the repository status (current version & stats"),
                 )
                 .subcommand(Command::new("init").about("Initialize repository"))
                 .subcommand(
-                    Command::new("current_version").about("Show the repository current version"),
+                    Command::new("verify")
+                        .about("Check packages data and metadata and the version graph"),
+                )
+                .subcommand(
+                    Command::new("publish")
+                        .about("Upload every repository file to the --publish-to bucket"),
+                )
+                .subcommand(
+                    Command::new("export_bundle")
+                        .about("Write an offline bundle (open it with bundle://path)")
+                        .arg(Arg::new("bundle").num_args(1).required(true).help("Bundle path"))
+                        .arg(
+                            Arg::new("from")
+                                .long("from")
+                                .num_args(1)
+                                .help("Installed revision (default: empty workspace)"),
+                        )
+                        .arg(
+                            Arg::new("to")
+                                .long("to")
+                                .num_args(1)
+                                .help("Target revision (default: current version)"),
+                        )
+                        .arg(
+                            Arg::new("platform")
+                                .long("platform")
+                                .num_args(1)
+                                .help("Platform of the updated workspaces (i.e. win64)"),
+                        ),
+                )
+                .subcommand(
+                    Command::new("current_version")
+                        .about("Show the repository current version")
+                        .arg(
+                            Arg::new("channel")
+                                .long("channel")
+                                .num_args(1)
+                                .help("Release channel (i.e. beta)"),
+                        ),
                 )
                 .subcommand(
                     Command::new("log")
@@ -138,7 +203,13 @@ async fn main() {
                 .subcommand(
                     Command::new("set_current_version")
                         .about("Set the repository current version")
-                        .arg(Arg::new("version").num_args(1).required(true).help("Version to set")),
+                        .arg(Arg::new("version").num_args(1).required(true).help("Version to set"))
+                        .arg(
+                            Arg::new("channel")
+                                .long("channel")
+                                .num_args(1)
+                                .help("Release channel (i.e. beta)"),
+                        ),
                 )
                 .subcommand(
                     Command::new("register_version")
@@ -155,6 +226,17 @@ async fn main() {
                 .subcommand(Command::new("unregister_version").about("unregister version").arg(
                     Arg::new("version").num_args(1).required(true).help("Version to unregister"),
                 ))
+                .subcommand(
+                    Command::new("yank_version")
+                        .about("Withdraw a version, register it again to undo")
+                        .arg(Arg::new("version").num_args(1).required(true).help("Version to yank"))
+                        .arg(
+                            Arg::new("reason")
+                                .num_args(1)
+                                .required(true)
+                                .help("Why this version is withdrawn"),
+                        ),
+                )
                 .subcommand(
                     Command::new("register_package").about("Register or update package").arg(
                         Arg::new("package_metadata_name")
@@ -163,6 +245,21 @@ async fn main() {
                             .help("Name of the package metadata file"),
                     ),
                 )
+                .subcommand(
+                    Command::new("set_package_hints")
+                        .about("Publish package hints used to select the update path")
+                        .arg(
+                            Arg::new("package_metadata_name")
+                                .num_args(1)
+                                .required(true)
+                                .help("Name of the package metadata file"),
+                        )
+                        .arg(
+                            Arg::new("cached").long("cached").action(ArgAction::SetTrue).help(
+                                "The package data is cached close to clients (i.e. by a CDN)",
+                            ),
+                        ),
+                )
                 .subcommand(
                     Command::new("unregister_package").about("Unregister package").arg(
                         Arg::new("package_metadata_name")
@@ -210,6 +307,42 @@ async fn main() {
                                 .num_args(1)
                                 .help("Patcher options (i.e. \"zstd:level=3;minsize=32MB\")"),
                         )
+                        .arg(
+                            Arg::new("hash")
+                                .long("hash")
+                                .num_args(1)
+                                .value_parser(["sha1", "sha256", "blake3"])
+                                .help("Content hash algorithm (default: sha1)"),
+                        )
+                        .arg(
+                            Arg::new("platform")
+                                .long("platform")
+                                .num_args(1)
+                                .help("Platform the package is built for (i.e. win64)"),
+                        )
+                        .arg(
+                            Arg::new("mtime")
+                                .long("mtime")
+                                .action(ArgAction::SetTrue)
+                                .help("Record files modification time"),
+                        )
+                        .arg(
+                            Arg::new("detect_moves")
+                                .long("detect-moves")
+                                .action(ArgAction::SetTrue)
+                                .help("Copy moved files from their previous path"),
+                        )
+                        .arg(
+                            Arg::new("dedup_files")
+                                .long("dedup-files")
+                                .action(ArgAction::SetTrue)
+                                .help("Copy duplicate files instead of storing them again"),
+                        )
+                        .arg(
+                            Arg::new("chunk_size").long("chunk-size").num_args(1).help(
+                                "Chunk modified files instead of patching them (i.e. \"64KB\")",
+                            ),
+                        )
                         .arg(
                             Arg::new("num_threads")
                                 .long("num-threads")
@@ -242,6 +375,54 @@ async fn main() {
                         .num_args(1)
                         .help("Workspace directory"),
                 )
+                .arg(
+                    Arg::new("public_key").long("public-key").num_args(1).global(true).help(
+                        "Base64 ed25519 public key the repository metadata must be signed with",
+                    ),
+                )
+                .arg(
+                    Arg::new("retries")
+                        .long("retries")
+                        .num_args(1)
+                        .global(true)
+                        .help("Number of consecutive retries of an interrupted download"),
+                )
+                .arg(
+                    Arg::new("timeout")
+                        .long("timeout")
+                        .num_args(1)
+                        .global(true)
+                        .help("Connect and read timeout in seconds"),
+                )
+                .arg(
+                    Arg::new("proxy")
+                        .long("proxy")
+                        .num_args(1)
+                        .global(true)
+                        .help("HTTP(S) or SOCKS5 proxy URL (i.e. socks5://host:1080)"),
+                )
+                .arg(
+                    Arg::new("cacert")
+                        .long("cacert")
+                        .num_args(1)
+                        .global(true)
+                        .help("PEM file of additional trusted root certificates"),
+                )
+                .arg(
+                    Arg::new("cert")
+                        .long("cert")
+                        .num_args(1)
+                        .global(true)
+                        .help("PEM file of the client certificate and private key"),
+                )
+                .arg(
+                    Arg::new("mirror")
+                        .long("mirror")
+                        .num_args(1)
+                        .action(ArgAction::Append)
+                        .global(true)
+                        .help("Mirror URL of the repository, tried in order if it fails"),
+                )
                 .subcommand(
                     Command::new("status")
                         .about("Show the workspace status")
@@ -252,6 +433,38 @@ async fn main() {
                         .about("Update workspace")
                         .arg(Arg::new("repository").num_args(1).help("Repository URL"))
                         .arg(Arg::new("to").num_args(1).help("Target revision"))
+                        .arg(
+                            Arg::new("channel")
+                                .long("channel")
+                                .num_args(1)
+                                .help("Release channel to follow (i.e. beta)"),
+                        )
+                        .arg(
+                            Arg::new("platform")
+                                .long("platform")
+                                .num_args(1)
+                                .help("Workspace platform (i.e. win64)"),
+                        )
+                        .arg(
+                            Arg::new("package_cost").long("package-cost").num_args(1).help(
+                                "Fixed cost of each package of the update path (i.e. \"4MB\")",
+                            ),
+                        )
+                        .arg(
+                            Arg::new("cached_percent")
+                                .long("cached-percent")
+                                .num_args(1)
+                                .help("Cost of cached packages bytes in percent (default: 100)"),
+                        )
+                        .arg(Arg::new("concurrency").long("concurrency").num_args(1).help(
+                            "Number of package ranges downloaded at the same time (default: 1)",
+                        ))
+                        .arg(
+                            Arg::new("limit_rate")
+                                .long("limit-rate")
+                                .num_args(1)
+                                .help("Download bandwidth limit per second (i.e. \"2MB\")"),
+                        )
                         .arg(
                             Arg::new("--check")
                                 .help("Integrity check of all files, not just affected ones"),
@@ -292,6 +505,38 @@ async fn main() {
             };
             eprintln!("repository: {}", repository_path);
             let mut repository = Repository::new(PathBuf::from(&repository_path));
+            repository.set_binary_metadata(sub_matches.get_flag("binary_metadata"));
+            if let Some(compression) = sub_matches.get_one::<String>("compress_metadata") {
+                let res = CoderOptions::from_static_str(compression)
+                    .and_then(|options| repository.set_metadata_compression(Some(options)));
+                if let Err(err) = res {
+                    error!("invalid metadata compression: {}", err);
+                    process::exit(1)
+                }
+            }
+            if let Some(signing_key_path) = sub_matches.get_one::<String>("signing_key") {
+                let signing_key = fs::read_to_string(signing_key_path)
+                    .and_then(|key| signature::parse_signing_key(&key));
+                match signing_key {
+                    Ok(signing_key) => repository.set_signing_key(signing_key),
+                    Err(err) => {
+                        error!("unable to load signing key: {}", err);
+                        process::exit(1)
+                    }
+                }
+            }
+            if let Some(s3_url) = sub_matches.get_one::<String>("publish_to") {
+                let bucket = S3Config::from_env().and_then(|config| {
+                    S3Repository::new(s3_url, &config, &HttpsRepositoryBuilder::new())
+                });
+                match bucket {
+                    Ok(bucket) => repository.set_publish_bucket(Some(bucket)),
+                    Err(err) => {
+                        error!("unable to open publish bucket: {}", err);
+                        process::exit(1)
+                    }
+                }
+            }
 
             match sub_matches.subcommand() {
                 Some(("status", sub_matches)) => {
@@ -300,6 +545,15 @@ async fn main() {
                 Some(("init", sub_matches)) => {
                     repository::do_init(sub_matches, &mut repository).await
                 }
+                Some(("verify", sub_matches)) => {
+                    repository::do_verify(sub_matches, &mut repository).await
+                }
+                Some(("publish", sub_matches)) => {
+                    repository::do_publish(sub_matches, &mut repository).await
+                }
+                Some(("export_bundle", sub_matches)) => {
+                    repository::do_export_bundle(sub_matches, &mut repository).await
+                }
                 Some(("current_version", sub_matches)) => {
                     repository::do_current_version(sub_matches, &mut repository).await
                 }
@@ -315,12 +569,18 @@ async fn main() {
                 Some(("unregister_version", sub_matches)) => {
                     repository::do_unregister_version(sub_matches, &mut repository).await
                 }
+                Some(("yank_version", sub_matches)) => {
+                    repository::do_yank_version(sub_matches, &mut repository).await
+                }
                 Some(("packages", sub_matches)) => {
                     repository::do_packages(sub_matches, &mut repository).await
                 }
                 Some(("register_package", sub_matches)) => {
                     repository::do_register_package(sub_matches, &mut repository).await
                 }
+                Some(("set_package_hints", sub_matches)) => {
+                    repository::do_set_package_hints(sub_matches, &mut repository).await
+                }
                 Some(("unregister_package", sub_matches)) => {
                     repository::do_unregister_package(sub_matches, &mut repository).await
                 }
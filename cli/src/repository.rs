@@ -9,8 +9,8 @@ use clap::ArgMatches;
 use console::{style, Term};
 use futures::prelude::*;
 use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
-use libspeedupdate::metadata::{self, CleanName, Operation};
-use libspeedupdate::repository::{BuildOptions, CoderOptions, PackageBuilder};
+use libspeedupdate::metadata::{self, v1::PackageHints, CleanName, Operation};
+use libspeedupdate::repository::{BuildOptions, ChunkingOptions, CoderOptions, PackageBuilder};
 use libspeedupdate::workspace::{UpdateOptions, Workspace};
 use libspeedupdate::Repository;
 use log::{error, info};
@@ -37,12 +37,30 @@ fn try_<T, E: Display>(res: Result<T, E>, ctx: &str) -> T {
     }
 }
 
-fn current_version(repository: &mut Repository) -> metadata::Current {
-    try_(repository.current_version(), "load repository current version")
+fn current_version(repository: &mut Repository, channel: Option<&CleanName>) -> metadata::Current {
+    try_(repository.current_version(channel), "load repository current version")
+}
+
+fn arg_channel(matches: &ArgMatches) -> Option<CleanName> {
+    matches.get_one::<String>("channel").map(|channel| {
+        try_(
+            CleanName::new(channel.to_string()),
+            "convert channel to clean name (i.e. [A-Za-Z0-9_.-]+)",
+        )
+    })
+}
+
+fn arg_platform(matches: &ArgMatches) -> Option<CleanName> {
+    matches.get_one::<String>("platform").map(|platform| {
+        try_(
+            CleanName::new(platform.to_string()),
+            "convert platform to clean name (i.e. [A-Za-Z0-9_.-]+)",
+        )
+    })
 }
 
 pub async fn do_status(_matches: &ArgMatches, repository: &mut Repository) {
-    let current_version = current_version(repository);
+    let current_version = current_version(repository, None);
     let versions = try_(repository.versions(), "load repository versions");
     let packages = try_(repository.packages(), "load repository versions");
     println!("current_version: {}", current_version.version());
@@ -52,6 +70,46 @@ pub async fn do_status(_matches: &ArgMatches, repository: &mut Repository) {
     println!("size: {}", size);
 }
 
+pub async fn do_verify(_matches: &ArgMatches, repository: &mut Repository) {
+    let errors = try_(repository.verify(), "verify repository");
+    for err in &errors {
+        error!("{}", err);
+    }
+    if !errors.is_empty() {
+        error!("{} errors found", errors.len());
+        std::process::exit(1);
+    }
+    info!("repository is consistent");
+}
+
+pub async fn do_publish(_matches: &ArgMatches, repository: &mut Repository) {
+    try_(repository.publish(), "publish repository");
+    info!("repository published");
+}
+
+pub async fn do_export_bundle(matches: &ArgMatches, repository: &mut Repository) {
+    let bundle_path: &_ = some_(matches.get_one::<String>("bundle"), "no bundle path provided");
+    let revision = |name: &str| {
+        matches.get_one::<String>(name).map(|revision| {
+            try_(
+                CleanName::new(revision.to_string()),
+                "convert revision to clean name (i.e. [A-Za-Z0-9_.-]+)",
+            )
+        })
+    };
+    let from = revision("from");
+    let to = match revision("to") {
+        Some(to) => to,
+        None => current_version(repository, None).version().clone(),
+    };
+    let platform = arg_platform(matches);
+    try_(
+        repository.export_bundle(from.as_ref(), &to, platform.as_ref(), Path::new(bundle_path)),
+        "export bundle",
+    );
+    info!("bundle {} written", bundle_path);
+}
+
 pub async fn do_init(_matches: &ArgMatches, repository: &mut Repository) {
     try_(repository.init(), "initialize repository");
     println!("repository initialized !");
@@ -63,11 +121,13 @@ pub async fn do_set_current_version(matches: &ArgMatches, repository: &mut Repos
         CleanName::new(version.to_string()),
         "convert version to clean name (i.e. [A-Za-Z0-9_.-]+)",
     );
-    try_(repository.set_current_version(&version), "set current version");
+    let channel = arg_channel(matches);
+    try_(repository.set_current_version(channel.as_ref(), &version), "set current version");
 }
 
-pub async fn do_current_version(_matches: &ArgMatches, repository: &mut Repository) {
-    let current_version = current_version(repository);
+pub async fn do_current_version(matches: &ArgMatches, repository: &mut Repository) {
+    let channel = arg_channel(matches);
+    let current_version = current_version(repository, channel.as_ref());
     println!("{}", current_version.version());
 }
 
@@ -98,7 +158,7 @@ pub async fn do_register_version(matches: &ArgMatches, repository: &mut Reposito
             std::process::exit(1);
         }
     };
-    let version = metadata::v1::Version { revision: version, description };
+    let version = metadata::v1::Version { revision: version, description, yanked: None };
     try_(repository.register_version(&version), "register version");
 }
 
@@ -111,6 +171,16 @@ pub async fn do_unregister_version(matches: &ArgMatches, repository: &mut Reposi
     try_(repository.unregister_version(&version), "unregister version");
 }
 
+pub async fn do_yank_version(matches: &ArgMatches, repository: &mut Repository) {
+    let version: &_ = some_(matches.get_one::<String>("version"), "no version provided");
+    let version = try_(
+        CleanName::new(version.to_string()),
+        "convert version to clean name (i.e. [A-Za-Z0-9_.-]+)",
+    );
+    let reason = some_(matches.get_one::<String>("reason"), "no reason provided");
+    try_(repository.yank_version(&version, reason), "yank version");
+}
+
 pub async fn do_packages(_matches: &ArgMatches, repository: &mut Repository) {
     let packages = try_(repository.packages(), "load repository packages");
     println!("packages: {}", packages.iter().count());
@@ -124,6 +194,15 @@ pub async fn do_register_package(matches: &ArgMatches, repository: &mut Reposito
     try_(repository.register_package(package_metadata_name), "register package");
 }
 
+pub async fn do_set_package_hints(matches: &ArgMatches, repository: &mut Repository) {
+    let package_metadata_name = some_(
+        matches.get_one::<String>("package_metadata_name"),
+        "no package metadata file name provided",
+    );
+    let hints = PackageHints { cached: matches.get_flag("cached") };
+    try_(repository.set_package_hints(package_metadata_name, hints), "set package hints");
+}
+
 pub async fn do_unregister_package(matches: &ArgMatches, repository: &mut Repository) {
     let package_metadata_name = some_(
         matches.get_one::<String>("package_metadata_name"),
@@ -135,7 +214,7 @@ pub async fn do_unregister_package(matches: &ArgMatches, repository: &mut Reposi
 pub async fn do_log(matches: &ArgMatches, repository: &mut Repository) {
     let from = matches.get_one::<String>("from");
     let to: String = match matches.get_one::<String>("to") {
-        None => current_version(repository).version().to_string(),
+        None => current_version(repository, None).version().to_string(),
         Some(to) => to.to_string(),
     };
     let versions = try_(repository.versions(), "load repository versions");
@@ -151,14 +230,19 @@ pub async fn do_log(matches: &ArgMatches, repository: &mut Repository) {
     };
     let oneline = matches.get_flag("oneline");
     for version in versions.iter().skip(skip_n) {
+        let yanked = match version.yanked() {
+            Some(reason) => format!(" {}", style(format!("(yanked: {})", reason)).red()),
+            None => String::new(),
+        };
         if oneline {
             println!(
-                "{}: {}",
+                "{}{}: {}",
                 style(&version.revision()).bold(),
+                yanked,
                 version.description().lines().next().unwrap_or("")
             );
         } else {
-            println!("{}", style(&version.revision()).bold());
+            println!("{}{}", style(&version.revision()).bold(), yanked);
             if !version.description().is_empty() {
                 println!();
                 println!("{}", version.description());
@@ -191,6 +275,7 @@ pub async fn do_build_package(matches: &ArgMatches, repository: &mut Repository)
         None => repository.dir().join(".build"),
     };
     let mut builder = PackageBuilder::new(build_directory, source_version, source_directory);
+    builder.set_platform(arg_platform(matches));
     if let Some(num_threads) = matches.get_one::<String>("num_threads") {
         let num_threads =
             try_(usize::from_str_radix(num_threads, 1), "convert --num-threads to integer");
@@ -207,6 +292,16 @@ pub async fn do_build_package(matches: &ArgMatches, repository: &mut Repository)
             .map(|s| try_(CoderOptions::from_static_str(s), "load patcher options"))
             .collect();
     }
+    if let Some(hash) = matches.get_one::<String>("hash") {
+        options.hash = try_(hash.parse(), "load hash algorithm");
+    }
+    options.mtime = matches.get_flag("mtime");
+    options.detect_moves = matches.get_flag("detect_moves");
+    options.dedup_files = matches.get_flag("dedup_files");
+    if let Some(chunk_size) = matches.get_one::<String>("chunk_size") {
+        let chunk_size = try_(Byte::parse_str(chunk_size, true), "convert --chunk-size to size");
+        options.chunking = Some(ChunkingOptions::new(chunk_size.as_u64()));
+    }
     if let Some(from) = matches.get_one::<String>("from") {
         let prev_directory = builder.build_directory.join(".from");
         try_(fs::create_dir_all(&prev_directory), "create from directory");
@@ -218,7 +313,9 @@ pub async fn do_build_package(matches: &ArgMatches, repository: &mut Repository)
         let link = repository.link();
         let mut workspace = Workspace::open(&prev_directory).unwrap();
         let goal_version = Some(prev_version.clone());
-        let mut update_stream = workspace.update(&link, goal_version, UpdateOptions::default());
+        let update_options =
+            UpdateOptions { platform: builder.platform.clone(), ..UpdateOptions::default() };
+        let mut update_stream = workspace.update(&link, goal_version, update_options);
 
         let state = match update_stream.next().await {
             Some(Ok(state)) => state,
@@ -1,23 +1,100 @@
 use std::ops::Deref;
 use std::path::Path;
 use std::process;
+use std::sync::Arc;
+use std::time::Duration;
 
+use byte_unit::Byte;
 use clap::ArgMatches;
 use console::{style, Term};
 use futures::prelude::*;
-use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
-use libspeedupdate::link::{AutoRepository, RemoteRepository};
-use libspeedupdate::metadata::{self, v1::State, CleanName, Operation};
+use indicatif::{HumanBytes, MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use libspeedupdate::link::{
+    AutoRepository, HttpsRepositoryBuilder, RemoteRepository, RepositoryError, RetryOptions,
+};
+use libspeedupdate::metadata::{self, v1::State, CleanName, Operation, WeightedPathCost};
+use libspeedupdate::signature;
 use libspeedupdate::workspace::{UpdateOptions, Workspace};
 use log::error;
 
 use crate::LOGGER;
 
+fn arg_public_key(matches: &ArgMatches) -> Option<signature::VerifyingKey> {
+    let public_key = matches.get_one::<String>("public_key")?;
+    match signature::parse_verifying_key(public_key) {
+        Ok(public_key) => Some(public_key),
+        Err(err) => {
+            error!("invalid public key: {}", err);
+            process::exit(1)
+        }
+    }
+}
+
+fn arg_retry_options(matches: &ArgMatches) -> RetryOptions {
+    let mut retry_options = RetryOptions::default();
+    if let Some(retries) = matches.get_one::<String>("retries") {
+        match retries.parse() {
+            Ok(retries) => retry_options.max_retries = retries,
+            Err(err) => {
+                error!("invalid retries: {} ({})", retries, err);
+                process::exit(1)
+            }
+        }
+    }
+    if let Some(timeout) = matches.get_one::<String>("timeout") {
+        match timeout.parse() {
+            Ok(timeout) => {
+                retry_options.connect_timeout = Duration::from_secs(timeout);
+                retry_options.read_timeout = Duration::from_secs(timeout);
+            }
+            Err(err) => {
+                error!("invalid timeout: {} ({})", timeout, err);
+                process::exit(1)
+            }
+        }
+    }
+    retry_options
+}
+
+fn read_pem(path: &str) -> Vec<u8> {
+    match std::fs::read(path) {
+        Ok(pem) => pem,
+        Err(err) => {
+            error!("unable to read {}: {}", path, err);
+            process::exit(1)
+        }
+    }
+}
+
+fn arg_https_builder(matches: &ArgMatches) -> Result<HttpsRepositoryBuilder, RepositoryError> {
+    let mut builder = HttpsRepositoryBuilder::new();
+    if let Some(public_key) = arg_public_key(matches) {
+        builder.set_public_key(public_key);
+    }
+    builder.set_retry_options(arg_retry_options(matches));
+    if let Some(proxy) = matches.get_one::<String>("proxy") {
+        builder.add_proxy(proxy)?;
+    }
+    if let Some(cacert) = matches.get_one::<String>("cacert") {
+        builder.add_root_certificates_pem(&read_pem(cacert))?;
+    }
+    if let Some(cert) = matches.get_one::<String>("cert") {
+        builder.set_client_identity_pem(&read_pem(cert))?;
+    }
+    Ok(builder)
+}
+
 pub fn arg_repository(matches: &ArgMatches) -> Option<AutoRepository> {
     match matches.get_one::<String>("repository") {
         Some(url) => {
             println!("repository: {}", url);
-            match AutoRepository::new(url, None) {
+            let mut urls = vec![url.as_str()];
+            urls.extend(
+                matches.get_many::<String>("mirror").into_iter().flatten().map(String::as_str),
+            );
+            let res = arg_https_builder(matches)
+                .and_then(|builder| AutoRepository::mirrors(&urls, &builder));
+            match res {
                 Ok(r) => Some(r),
                 Err(err) => {
                     error!("{}", err);
@@ -135,6 +212,64 @@ pub async fn do_update(
     };
     let mut update_options = UpdateOptions::default();
     update_options.check = matches.get_flag("check");
+    update_options.channel = match matches.get_one::<String>("channel") {
+        Some(channel) => match CleanName::new(channel.to_string()) {
+            Ok(channel) => Some(channel),
+            Err(_) => {
+                error!("invalid channel: {} (must match [A-Za-Z0-9_.-]+)", channel);
+                std::process::exit(1)
+            }
+        },
+        None => None,
+    };
+    update_options.platform = match matches.get_one::<String>("platform") {
+        Some(platform) => match CleanName::new(platform.to_string()) {
+            Ok(platform) => Some(platform),
+            Err(_) => {
+                error!("invalid platform: {} (must match [A-Za-Z0-9_.-]+)", platform);
+                std::process::exit(1)
+            }
+        },
+        None => None,
+    };
+    let mut path_cost = WeightedPathCost::default();
+    if let Some(package_cost) = matches.get_one::<String>("package_cost") {
+        match Byte::parse_str(package_cost, true) {
+            Ok(package_cost) => path_cost.per_package = package_cost.as_u64(),
+            Err(err) => {
+                error!("invalid package cost: {} ({})", package_cost, err);
+                std::process::exit(1)
+            }
+        }
+    }
+    if let Some(cached_percent) = matches.get_one::<String>("cached_percent") {
+        match cached_percent.parse() {
+            Ok(cached_percent) => path_cost.cached_byte_percent = cached_percent,
+            Err(err) => {
+                error!("invalid cached percent: {} ({})", cached_percent, err);
+                std::process::exit(1)
+            }
+        }
+    }
+    update_options.path_cost = Arc::new(path_cost);
+    if let Some(concurrency) = matches.get_one::<String>("concurrency") {
+        match concurrency.parse() {
+            Ok(concurrency) => update_options.download_concurrency = concurrency,
+            Err(err) => {
+                error!("invalid concurrency: {} ({})", concurrency, err);
+                std::process::exit(1)
+            }
+        }
+    }
+    if let Some(limit_rate) = matches.get_one::<String>("limit_rate") {
+        match Byte::parse_str(limit_rate, true) {
+            Ok(limit_rate) => update_options.download_limiter.set_limit(Some(limit_rate.as_u64())),
+            Err(err) => {
+                error!("invalid limit rate: {} ({})", limit_rate, err);
+                std::process::exit(1)
+            }
+        }
+    }
     let mut stream = workspace.update(repository, goal_version, update_options);
 
     let state = match stream.next().await {
@@ -194,9 +329,17 @@ pub async fn do_update(
                 let progress = state.histogram.progress();
                 dl_bytes.set_position(progress.downloaded_bytes);
                 dl_bytes.set_length(state.download_bytes);
-                dl_bytes.set_message(op_file_name(
-                    state.current_step_operation(state.downloading_operation_idx),
-                ));
+                let mut dl_message =
+                    op_file_name(state.current_step_operation(state.downloading_operation_idx));
+                if state.download_retries > 0 {
+                    dl_message.push_str(&format!(" ({} retries)", state.download_retries));
+                }
+                if state.redownloaded_bytes > 0 {
+                    let redownloaded = HumanBytes(state.redownloaded_bytes);
+                    dl_message
+                        .push_str(&format!(" ({} corrupted, downloaded again)", redownloaded));
+                }
+                dl_bytes.set_message(dl_message);
 
                 apply_input_bytes.set_position(progress.applied_input_bytes);
                 apply_input_bytes.set_length(state.apply_input_bytes);
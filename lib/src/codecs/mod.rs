@@ -18,6 +18,16 @@ pub mod vcdiff;
 #[cfg(feature = "zstd")]
 pub mod zstd;
 
+/// Compressed metadata files extensions and their coder name
+///
+/// i.e. `versions.zst` is `versions` compressed with zstd.
+pub const METADATA_COMPRESSIONS: &[(&str, &str)] = &[
+    #[cfg(feature = "zstd")]
+    ("zst", "zstd"),
+    #[cfg(feature = "brotli")]
+    ("br", "brotli"),
+];
+
 pub trait Coder<W>: io::Write {
     /// Acquires a mutable reference to the underlying writer
     ///
@@ -30,8 +40,10 @@ pub trait Coder<W>: io::Write {
     fn finish_boxed(self: Box<Self>) -> io::Result<W>;
 }
 
-/// Coder adaptor which compute for input sha1, output sha1, count read bytes
+/// Coder adaptor which compute for input hash, output hash, count read bytes
 /// and count written bytes.
+///
+/// `checks` is cloned to check both the input and the output.
 pub struct CheckCoder<'a, W, C> {
     writer: io::CheckWriter<Box<dyn Coder<io::CheckWriter<W, C>> + 'a>, C>,
 }
@@ -39,19 +51,19 @@ pub struct CheckCoder<'a, W, C> {
 impl<'a, W, C> CheckCoder<'a, W, C>
 where
     W: io::Write + 'a,
-    C: io::Check + Default + 'a,
+    C: io::Check + Clone + 'a,
 {
-    pub fn decoder(decompressor_name: &str, writer: W) -> io::Result<Self> {
-        let output_writer = io::CheckWriter::new(writer);
+    pub fn decoder(decompressor_name: &str, writer: W, checks: C) -> io::Result<Self> {
+        let output_writer = io::CheckWriter::with_check(writer, checks.clone());
         let transform_writer = decoder(decompressor_name, output_writer)?;
-        let input_writer = io::CheckWriter::new(transform_writer);
+        let input_writer = io::CheckWriter::with_check(transform_writer, checks);
         Ok(Self { writer: input_writer })
     }
 
-    pub fn encoder(encoder_options: &CoderOptions, writer: W) -> io::Result<Self> {
-        let output_writer = io::CheckWriter::new(writer);
+    pub fn encoder(encoder_options: &CoderOptions, writer: W, checks: C) -> io::Result<Self> {
+        let output_writer = io::CheckWriter::with_check(writer, checks.clone());
         let transform_writer = encoder(encoder_options, output_writer)?;
-        let input_writer = io::CheckWriter::new(transform_writer);
+        let input_writer = io::CheckWriter::with_check(transform_writer, checks);
         Ok(Self { writer: input_writer })
     }
 }
@@ -59,36 +71,47 @@ where
 impl<'a, W, C> CheckCoder<'a, W, C>
 where
     W: io::Write + io::ReadSlice + 'a,
-    C: io::Check + Default + 'a,
+    C: io::Check + Clone + 'a,
 {
     pub fn patch_decoder<L>(
         patcher_name: &str,
         decompressor_name: &str,
         local: L,
         writer: W,
+        checks: C,
     ) -> io::Result<Self>
     where
         L: io::Read + io::Seek + 'a,
     {
-        let output_writer = io::CheckWriter::new(writer);
+        let output_writer = io::CheckWriter::with_check(writer, checks.clone());
         let transform_writer =
             patch_decoder(decompressor_name, patcher_name, local, output_writer)?;
-        let input_writer = io::CheckWriter::new(transform_writer);
+        let input_writer = io::CheckWriter::with_check(transform_writer, checks);
         Ok(Self { writer: input_writer })
     }
 
-    pub fn patch_encoder<L>(patcher_options: &CoderOptions, local: L, writer: W) -> io::Result<Self>
+    pub fn patch_encoder<L>(
+        patcher_options: &CoderOptions,
+        local: L,
+        writer: W,
+        checks: C,
+    ) -> io::Result<Self>
     where
         L: io::Read + io::Seek + 'a,
     {
-        let output_writer = io::CheckWriter::new(writer);
+        let output_writer = io::CheckWriter::with_check(writer, checks.clone());
         let transform_writer = patch_encoder(patcher_options, local, output_writer)?;
-        let input_writer = io::CheckWriter::new(transform_writer);
+        let input_writer = io::CheckWriter::with_check(transform_writer, checks);
         Ok(Self { writer: input_writer })
     }
 }
 
 impl<W, C> CheckCoder<'_, W, C> {
+    /// Acquires a mutable reference to the underlying output writer
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.writer.writer.get_mut().writer
+    }
+
     pub fn input_checks(&mut self) -> &mut C {
         &mut self.writer.check
     }
@@ -381,6 +404,48 @@ where
     Err(io::Error::other(format!("encoder {} isn't supported!", encoder_options.name())))
 }
 
+/// Encode the whole `input` buffer
+pub fn encode_all(encoder_options: &CoderOptions, input: &[u8]) -> io::Result<Vec<u8>> {
+    let mut encoder = encoder(encoder_options, Vec::new())?;
+    encoder.write_all(input)?;
+    encoder.finish_boxed()
+}
+
+/// Decode the whole `input` buffer
+///
+/// Fails as soon as the decoded content is bigger than `max_size`, before
+/// allocating more.
+pub fn decode_all(decompressor_name: &str, input: &[u8], max_size: u64) -> io::Result<Vec<u8>> {
+    let output = LimitedBuffer { buffer: Vec::new(), max_size };
+    let mut decoder = decoder(decompressor_name, output)?;
+    decoder.write_all(input)?;
+    decoder.flush()?;
+    Ok(decoder.finish_boxed()?.buffer)
+}
+
+/// Buffer refusing writes beyond `max_size` bytes
+struct LimitedBuffer {
+    buffer: Vec<u8>,
+    max_size: u64,
+}
+
+impl io::Write for LimitedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if (self.buffer.len() + buf.len()) as u64 > self.max_size {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("decoded content is bigger than {} bytes", self.max_size),
+            ));
+        }
+        self.buffer.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
 pub fn decoder<'a, W>(
     decompressor_name: &str,
     output: W,
@@ -488,3 +553,16 @@ where
 
     Err(io::Error::other("not implemented!"))
 }
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn decode_all_max_size() {
+        let options = super::CoderOptions::from_static_str("zstd").unwrap();
+        let encoded = super::encode_all(&options, &[0u8; 64 * 1024]).unwrap();
+        assert_eq!(super::decode_all("zstd", &encoded, 64 * 1024).unwrap().len(), 64 * 1024);
+        let err = super::decode_all("zstd", &encoded, 1024).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    }
+}
@@ -1,6 +1,9 @@
-use std::{fs, path::PathBuf};
+use std::fs;
+use std::path::{Path, PathBuf};
 
-use super::{Applier, CheckApplier, HandlerContext, WriteApplier};
+use super::{
+    Applier, CheckApplier, ChunkWriter, ChunkedApplier, CopyApplier, HandlerContext, WriteApplier,
+};
 use crate::codecs::CheckCoder;
 use crate::io;
 use crate::metadata;
@@ -23,27 +26,29 @@ impl super::ApplyHandler for Handler<'_> {
         false
     }
 
-    fn add(&mut self, op: &metadata::v1::Add) -> io::Result<Option<Box<dyn Applier>>> {
+    fn add(&mut self, op: &metadata::v2::Add) -> io::Result<Option<Box<dyn Applier>>> {
         let tmp_path = self.ctx.tmp_operation_path();
-        let final_path = self.ctx.final_path(&op.common.path);
+        let final_path = self.ctx.final_path(&op.common.path)?;
         let tmp_file =
             fs::OpenOptions::new().write(true).create(true).truncate(true).open(&tmp_path)?;
-        io::set_exe_permission(&tmp_file, op.common.exe)?;
-        let decoder = CheckCoder::decoder(&op.data_compression, tmp_file)?;
+        io::set_permissions(&tmp_file, op.common.exe, op.common.mode)?;
+        let checks = io::CheckHashSize::new(op.final_hash.algorithm());
+        let decoder = CheckCoder::decoder(&op.data_compression, tmp_file, checks)?;
         let applier = WriteApplier {
             data_size_expected: op.data_size,
-            data_sha1_expected: op.data_sha1.clone(),
+            data_hash_expected: op.data_hash.clone(),
             final_size_expected: op.final_size,
-            final_sha1_expected: op.final_sha1.clone(),
+            final_hash_expected: op.final_hash.clone(),
             final_path,
             tmp_path,
+            mtime: op.common.mtime,
             decoder,
         };
         Ok(Some(Box::new(applier)))
     }
 
-    fn patch(&mut self, op: &metadata::v1::Patch) -> io::Result<Option<Box<dyn Applier>>> {
-        let final_path = self.ctx.final_path(&op.common.path);
+    fn patch(&mut self, op: &metadata::v2::Patch) -> io::Result<Option<Box<dyn Applier>>> {
+        let final_path = self.ctx.final_path(&op.common.path)?;
         let current_local_size = fs::metadata(&final_path).map(|m| m.len())?;
 
         io::assert_eq(current_local_size, op.local_size, "local size")?;
@@ -56,37 +61,100 @@ impl super::ApplyHandler for Handler<'_> {
             .create(true)
             .truncate(true)
             .open(&tmp_path)?;
-        io::set_exe_permission(&tmp_file, op.common.exe)?;
-        let decoder =
-            CheckCoder::patch_decoder(&op.data_compression, &op.patch_type, local_file, tmp_file)?;
+        io::set_permissions(&tmp_file, op.common.exe, op.common.mode)?;
+        let checks = io::CheckHashSize::new(op.final_hash.algorithm());
+        let decoder = CheckCoder::patch_decoder(
+            &op.data_compression,
+            &op.patch_type,
+            local_file,
+            tmp_file,
+            checks,
+        )?;
         let applier = WriteApplier {
             data_size_expected: op.data_size,
-            data_sha1_expected: op.data_sha1.clone(),
+            data_hash_expected: op.data_hash.clone(),
             final_size_expected: op.final_size,
-            final_sha1_expected: op.final_sha1.clone(),
+            final_hash_expected: op.final_hash.clone(),
             final_path,
             tmp_path,
+            mtime: op.common.mtime,
             decoder,
         };
         Ok(Some(Box::new(applier)))
     }
 
-    fn check(&mut self, op: &metadata::v1::Check) -> io::Result<Option<Box<dyn Applier>>> {
+    /// Reassemble the file from local chunks and package data chunks
+    ///
+    /// Local chunks are read from the workspace before the final file is
+    /// replaced, so a file can be rebuilt from its own previous content.
+    fn chunked(&mut self, op: &metadata::v2::Chunked) -> io::Result<Option<Box<dyn Applier>>> {
+        let tmp_path = self.ctx.tmp_operation_path();
+        let final_path = self.ctx.final_path(&op.common.path)?;
+        let tmp_file =
+            fs::OpenOptions::new().write(true).create(true).truncate(true).open(&tmp_path)?;
+        io::set_permissions(&tmp_file, op.common.exe, op.common.mode)?;
+        let writer = ChunkWriter::new(self.ctx.file_manager.dir().to_path_buf(), op, tmp_file);
+        let checks = io::CheckHashSize::new(op.final_hash.algorithm());
+        let decoder = CheckCoder::decoder(&op.data_compression, writer, checks)?;
+        let applier = ChunkedApplier {
+            data_size_expected: op.data_size,
+            data_hash_expected: op.data_hash.clone(),
+            final_size_expected: op.final_size,
+            final_hash_expected: op.final_hash.clone(),
+            final_path,
+            tmp_path,
+            mtime: op.common.mtime,
+            decoder,
+        };
+        Ok(Some(Box::new(applier)))
+    }
+
+    /// Copy another workspace file
+    ///
+    /// The parent directory is created if needed, as copies of moved files are
+    /// applied before the destination directory is created.
+    fn copy(&mut self, op: &metadata::v2::Copy) -> io::Result<Option<Box<dyn Applier>>> {
+        let from_file = fs::File::open(self.ctx.final_path(&op.from_path)?)?;
+        let final_path = self.ctx.final_path(&op.common.path)?;
+        if let Some(parent) = final_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let tmp_path = self.ctx.tmp_operation_path();
+        let tmp_file =
+            fs::OpenOptions::new().write(true).create(true).truncate(true).open(&tmp_path)?;
+        io::set_permissions(&tmp_file, op.common.exe, op.common.mode)?;
+        let checks = io::CheckHashSize::new(op.final_hash.algorithm());
+        let applier = CopyApplier {
+            final_size_expected: op.final_size,
+            final_hash_expected: op.final_hash.clone(),
+            final_path,
+            tmp_path,
+            mtime: op.common.mtime,
+            r: io::CheckReader::with_check(from_file, checks),
+            w: io::BufWriter::new(tmp_file),
+        };
+        Ok(Some(Box::new(applier)))
+    }
+
+    fn check(&mut self, op: &metadata::v2::Check) -> io::Result<Option<Box<dyn Applier>>> {
         if !self.ctx.update_options.check {
             return Ok(None);
         }
 
-        let path = self.ctx.final_path(&op.common.path);
+        let path = self.ctx.final_path(&op.common.path)?;
         let file = fs::OpenOptions::new().read(true).open(path)?;
         let size = file.metadata()?.len();
         io::assert_eq(size, op.local_size, "local size")?;
-        io::set_exe_permission(&file, op.common.exe)?;
-        let applier = CheckApplier::new(op.local_size, op.local_sha1.clone(), file);
+        match op.common.mode {
+            Some(mode) => io::assert_mode_eq(&file, mode)?,
+            None => io::set_permissions(&file, op.common.exe, None)?,
+        }
+        let applier = CheckApplier::new(op.local_size, op.local_hash.clone(), file);
         Ok(Some(Box::new(applier)))
     }
 
-    fn rm(&mut self, op: &metadata::v1::Rm) -> io::Result<Option<Box<dyn Applier>>> {
-        io::remove_file(self.ctx.final_path(&op.path))?;
+    fn rm(&mut self, op: &metadata::v2::Rm) -> io::Result<Option<Box<dyn Applier>>> {
+        io::remove_file(self.ctx.final_path(&op.path)?)?;
         Ok(None)
     }
 
@@ -95,7 +163,7 @@ impl super::ApplyHandler for Handler<'_> {
     }
 
     fn mkdir(&mut self, path: &metadata::CleanPath) -> io::Result<Option<Box<dyn Applier>>> {
-        fs::create_dir_all(self.ctx.final_path(path)).map(|_| None).or_else(|err| {
+        fs::create_dir_all(self.ctx.final_path(path)?).map(|_| None).or_else(|err| {
             match err.kind() {
                 io::ErrorKind::AlreadyExists => Ok(None),
                 _ => Err(err),
@@ -104,11 +172,40 @@ impl super::ApplyHandler for Handler<'_> {
     }
 
     fn rmdir(&mut self, path: &metadata::CleanPath) -> io::Result<Option<Box<dyn Applier>>> {
-        if let Err(err) = fs::remove_dir(self.ctx.final_path(path)) {
+        if let Err(err) = fs::remove_dir(self.ctx.final_path(path)?) {
             if err.kind() != io::ErrorKind::NotFound {
                 self.ctx.warn_fs(&format!("unable to remove directory {}", path), err)?;
             }
         }
         Ok(None)
     }
+
+    /// Create or replace the symbolic link at `path`
+    ///
+    /// Like mkdir, this is also how symbolic links are checked: a missing or
+    /// wrong link is replaced.
+    fn symlink(
+        &mut self,
+        path: &metadata::CleanPath,
+        target: &str,
+    ) -> io::Result<Option<Box<dyn Applier>>> {
+        let final_path = self.ctx.final_path(path)?;
+        if !metadata::is_contained_symlink(path, target)
+            || !self.ctx.is_contained_symlink(&final_path, target)?
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("symlink {} target {} escapes the workspace", path, target),
+            ));
+        }
+        if fs::read_link(&final_path).is_ok_and(|current| current == Path::new(target)) {
+            return Ok(None);
+        }
+
+        let tmp_path = self.ctx.tmp_operation_path();
+        io::remove_file(&tmp_path)?;
+        io::symlink(target, &tmp_path)?;
+        fs::rename(&tmp_path, &final_path)?;
+        Ok(None)
+    }
 }
@@ -1,9 +1,9 @@
 mod direct;
 mod sliced;
 
-use std::fs;
-use std::io::{Read, Write};
-use std::path::PathBuf;
+use std::io::{Read, Seek, Write};
+use std::path::{Path, PathBuf};
+use std::{cmp, fs};
 
 pub use direct::Handler as DefaultHandler;
 use tracing::warn;
@@ -21,8 +21,67 @@ pub struct HandlerContext<'a> {
 }
 
 impl HandlerContext<'_> {
-    pub fn final_path(&self, path: &metadata::CleanPath) -> PathBuf {
-        self.file_manager.dir().join(path)
+    /// Returns the workspace path of `path`
+    ///
+    /// Parent directories of `path` must not be symbolic links, writing
+    /// through them could escape the workspace.
+    pub fn final_path(&self, path: &metadata::CleanPath) -> io::Result<PathBuf> {
+        let dir = self.file_manager.dir();
+        let mut parent = dir.to_path_buf();
+        let mut components = path.split('/').filter(|c| !c.is_empty()).peekable();
+        while let Some(component) = components.next() {
+            if components.peek().is_none() {
+                break;
+            }
+            parent.push(component);
+            match fs::symlink_metadata(&parent) {
+                Ok(metadata) if metadata.is_symlink() => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("{} parent {} is a symbolic link", path, parent.display()),
+                    ));
+                }
+                Ok(_) => {}
+                Err(err) if err.kind() == io::ErrorKind::NotFound => break,
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(dir.join(path))
+    }
+
+    /// Returns true if the symbolic link `target` of `final_path` stays inside
+    /// the workspace once resolved through the links already on disk
+    ///
+    /// `..` can't be resolved after a missing component, it might become a
+    /// link later, so such targets are refused.
+    pub fn is_contained_symlink(&self, final_path: &Path, target: &str) -> io::Result<bool> {
+        let root = fs::canonicalize(self.file_manager.dir())?;
+        let mut resolved = match final_path.parent() {
+            Some(parent) => fs::canonicalize(parent)?,
+            None => return Ok(false),
+        };
+        let mut missing = false;
+        for component in target.split('/') {
+            match component {
+                "" | "." => {}
+                ".." if missing => return Ok(false),
+                ".." => {
+                    resolved.pop();
+                }
+                _ => {
+                    resolved.push(component);
+                    match fs::canonicalize(&resolved) {
+                        Ok(path) => resolved = path,
+                        Err(err) if err.kind() == io::ErrorKind::NotFound => missing = true,
+                        Err(err) => return Err(err),
+                    }
+                }
+            }
+            if !resolved.starts_with(&root) {
+                return Ok(false);
+            }
+        }
+        Ok(true)
     }
 
     pub fn tmp_operation_path(&self) -> PathBuf {
@@ -67,18 +126,25 @@ pub trait ApplyOperation: Operation {
 ///
 /// Some handlers might not support every operation or slices. For example, the
 /// direct handler doesn't support slices and the slice handler doesn't support
-/// mkdir and rmdir.
+/// mkdir, rmdir and symlink.
 #[allow(dead_code)]
 pub trait ApplyHandler {
     fn download_operation_path(&self) -> PathBuf;
     fn try_still_compatible(&mut self, path: &metadata::CleanPath, operation_idx: usize) -> bool;
 
-    fn add(&mut self, op: &metadata::v1::Add) -> io::Result<Option<Box<dyn Applier + '_>>>;
-    fn patch(&mut self, op: &metadata::v1::Patch) -> io::Result<Option<Box<dyn Applier + '_>>>;
-    fn check(&mut self, op: &metadata::v1::Check) -> io::Result<Option<Box<dyn Applier + '_>>>;
-    fn rm(&mut self, op: &metadata::v1::Rm) -> io::Result<Option<Box<dyn Applier + '_>>>;
+    fn add(&mut self, op: &metadata::v2::Add) -> io::Result<Option<Box<dyn Applier + '_>>>;
+    fn patch(&mut self, op: &metadata::v2::Patch) -> io::Result<Option<Box<dyn Applier + '_>>>;
+    fn check(&mut self, op: &metadata::v2::Check) -> io::Result<Option<Box<dyn Applier + '_>>>;
+    fn rm(&mut self, op: &metadata::v2::Rm) -> io::Result<Option<Box<dyn Applier + '_>>>;
     fn mkdir(&mut self, path: &metadata::CleanPath) -> io::Result<Option<Box<dyn Applier + '_>>>;
     fn rmdir(&mut self, path: &metadata::CleanPath) -> io::Result<Option<Box<dyn Applier + '_>>>;
+    fn symlink(
+        &mut self,
+        path: &metadata::CleanPath,
+        target: &str,
+    ) -> io::Result<Option<Box<dyn Applier + '_>>>;
+    fn chunked(&mut self, op: &metadata::v2::Chunked) -> io::Result<Option<Box<dyn Applier + '_>>>;
+    fn copy(&mut self, op: &metadata::v2::Copy) -> io::Result<Option<Box<dyn Applier + '_>>>;
     fn finalize(self: Box<Self>) -> io::Result<Option<Box<dyn Applier>>>;
 }
 
@@ -130,12 +196,13 @@ pub trait Applier {
 /// Simple write Applier
 struct WriteApplier<'a, W> {
     data_size_expected: u64,
-    data_sha1_expected: metadata::Sha1Hash,
+    data_hash_expected: metadata::Hash,
     final_size_expected: u64,
-    final_sha1_expected: metadata::Sha1Hash,
+    final_hash_expected: metadata::Hash,
     final_path: PathBuf,
     tmp_path: PathBuf,
-    decoder: codecs::CheckCoder<'a, W, io::CheckSha1Size>,
+    mtime: Option<u64>,
+    decoder: codecs::CheckCoder<'a, W, io::CheckHashSize>,
 }
 
 impl<W: io::Write + io::Seek + io::Read> Applier for WriteApplier<'_, W> {
@@ -161,19 +228,239 @@ impl<W: io::Write + io::Seek + io::Read> Applier for WriteApplier<'_, W> {
         self.decoder.flush()?;
 
         let input_checks = self.decoder.input_checks();
-        let data_sha1 = input_checks.sha1();
-        io::assert_eq(&data_sha1, &self.data_sha1_expected, "data sha1")?;
+        let data_hash = input_checks.hash();
+        io::assert_eq(&data_hash, &self.data_hash_expected, "data hash")?;
         let data_size = input_checks.bytes;
         io::assert_eq(data_size, self.data_size_expected, "data size")?;
 
         let mut output_checks = self.decoder.finish()?.check;
-        let final_sha1 = output_checks.sha1();
-        io::assert_eq(&final_sha1, &self.final_sha1_expected, "final sha1")?;
+        let final_hash = output_checks.hash();
+        io::assert_eq(&final_hash, &self.final_hash_expected, "final hash")?;
         let final_size = output_checks.bytes;
         io::assert_eq(final_size, self.final_size_expected, "final size")?;
 
         io::remove_file(&self.final_path)?;
         fs::rename(&self.tmp_path, &self.final_path)?;
+        if let Some(mtime) = self.mtime {
+            io::set_mtime(&self.final_path, mtime)?;
+        }
+        Ok(())
+    }
+}
+
+/// Reassemble a chunked file from local chunks and package data chunks
+///
+/// Written bytes are the decoded package data chunks, local chunks are copied
+/// in between them. Every chunk hash is checked.
+struct ChunkWriter {
+    dir: PathBuf,
+    chunks: std::vec::IntoIter<metadata::v2::Chunk>,
+    /// Remaining size of the package data chunk being written
+    data_chunk: Option<(u64, metadata::v2::Chunk)>,
+    chunk_hasher: metadata::Hasher,
+    local_file: Option<(metadata::CleanPath, fs::File)>,
+    output: io::CheckWriter<fs::File, io::CheckHashSize>,
+}
+
+impl ChunkWriter {
+    fn new(dir: PathBuf, op: &metadata::v2::Chunked, output: fs::File) -> Self {
+        let algorithm = op.final_hash.algorithm();
+        Self {
+            dir,
+            chunks: op.chunks.clone().into_iter(),
+            data_chunk: None,
+            chunk_hasher: algorithm.hasher(),
+            local_file: None,
+            output: io::CheckWriter::with_check(output, io::CheckHashSize::new(algorithm)),
+        }
+    }
+
+    /// Copy local chunks until the next package data chunk
+    ///
+    /// Returns false if there is no package data chunk left.
+    fn next_data_chunk(&mut self) -> io::Result<bool> {
+        while self.data_chunk.is_none() {
+            let chunk = match self.chunks.next() {
+                Some(chunk) => chunk,
+                None => return Ok(false),
+            };
+            match &chunk.local_path {
+                Some(local_path) => self.copy_local_chunk(local_path, &chunk)?,
+                None => self.data_chunk = Some((chunk.size, chunk)),
+            }
+        }
+        Ok(true)
+    }
+
+    fn copy_local_chunk(
+        &mut self,
+        local_path: &metadata::CleanPath,
+        chunk: &metadata::v2::Chunk,
+    ) -> io::Result<()> {
+        let file = match &mut self.local_file {
+            Some((path, file)) if path == local_path => file,
+            local_file => {
+                let file = fs::File::open(self.dir.join(local_path))?;
+                &mut local_file.insert((local_path.clone(), file)).1
+            }
+        };
+        file.seek(io::SeekFrom::Start(chunk.local_offset))?;
+        let mut reader = file.take(chunk.size);
+        let mut buffer = [0u8; io::BUFFER_SIZE];
+        let mut copied = 0u64;
+        loop {
+            let read = reader.read(&mut buffer)?;
+            if read == 0 {
+                break;
+            }
+            self.chunk_hasher.update(&buffer[..read]);
+            self.output.write_all(&buffer[..read])?;
+            copied += read as u64;
+        }
+        io::assert_eq(copied, chunk.size, "local chunk size")?;
+        io::assert_eq(&self.chunk_hasher.finalize_reset(), &chunk.hash, "local chunk hash")
+    }
+
+    /// Copy the remaining local chunks and returns the final file checks
+    fn finish(mut self) -> io::Result<io::CheckHashSize> {
+        if self.next_data_chunk()? {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "missing package data chunks"));
+        }
+        self.output.flush()?;
+        Ok(self.output.check)
+    }
+}
+
+impl io::Write for ChunkWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if !self.next_data_chunk()? {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "unexpected package data"));
+        }
+        let (remaining, _) = self.data_chunk.as_mut().expect("a package data chunk");
+        let len = cmp::min(*remaining, buf.len() as u64) as usize;
+        self.output.write_all(&buf[..len])?;
+        self.chunk_hasher.update(&buf[..len]);
+        *remaining -= len as u64;
+        if *remaining == 0 {
+            let (_, chunk) = self.data_chunk.take().expect("a package data chunk");
+            io::assert_eq(&self.chunk_hasher.finalize_reset(), &chunk.hash, "data chunk hash")?;
+        }
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.output.flush()
+    }
+}
+
+/// Chunked file Applier
+struct ChunkedApplier<'a> {
+    data_size_expected: u64,
+    data_hash_expected: metadata::Hash,
+    final_size_expected: u64,
+    final_hash_expected: metadata::Hash,
+    final_path: PathBuf,
+    tmp_path: PathBuf,
+    mtime: Option<u64>,
+    decoder: codecs::CheckCoder<'a, ChunkWriter, io::CheckHashSize>,
+}
+
+impl Applier for ChunkedApplier<'_> {
+    fn expected_input_bytes(&self) -> u64 {
+        self.data_size_expected
+    }
+
+    fn apply_input_bytes(&mut self, buf: &[u8]) -> io::Result<u64> {
+        self.decoder.write_all(buf)?;
+        let output_bytes = self.decoder.get_mut().output.check.bytes;
+        Ok(output_bytes)
+    }
+
+    fn expected_check_bytes(&mut self) -> u64 {
+        0
+    }
+
+    fn check_bytes(&mut self, _buf: &mut [u8]) -> io::Result<u64> {
+        unreachable!()
+    }
+
+    fn commit(mut self: Box<Self>) -> io::Result<()> {
+        self.decoder.flush()?;
+
+        let input_checks = self.decoder.input_checks();
+        let data_hash = input_checks.hash();
+        io::assert_eq(&data_hash, &self.data_hash_expected, "data hash")?;
+        let data_size = input_checks.bytes;
+        io::assert_eq(data_size, self.data_size_expected, "data size")?;
+
+        let mut output_checks = self.decoder.finish()?.writer.finish()?;
+        let final_hash = output_checks.hash();
+        io::assert_eq(&final_hash, &self.final_hash_expected, "final hash")?;
+        let final_size = output_checks.bytes;
+        io::assert_eq(final_size, self.final_size_expected, "final size")?;
+
+        io::remove_file(&self.final_path)?;
+        fs::rename(&self.tmp_path, &self.final_path)?;
+        if let Some(mtime) = self.mtime {
+            io::set_mtime(&self.final_path, mtime)?;
+        }
+        Ok(())
+    }
+}
+
+/// Copy Applier
+///
+/// The source file is copied to the temporary file while checking its content.
+struct CopyApplier {
+    final_size_expected: u64,
+    final_hash_expected: metadata::Hash,
+    final_path: PathBuf,
+    tmp_path: PathBuf,
+    mtime: Option<u64>,
+    r: io::CheckReader<fs::File, io::CheckHashSize>,
+    w: io::BufWriter<fs::File>,
+}
+
+impl Applier for CopyApplier {
+    fn expected_input_bytes(&self) -> u64 {
+        0
+    }
+
+    fn apply_input_bytes(&mut self, _buf: &[u8]) -> io::Result<u64> {
+        unreachable!()
+    }
+
+    fn expected_check_bytes(&mut self) -> u64 {
+        self.final_size_expected
+    }
+
+    fn check_bytes(&mut self, buf: &mut [u8]) -> io::Result<u64> {
+        let read = self.r.read(buf)?;
+        if read == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "final size mismatch, found: {}, expected: {}",
+                    self.r.read_bytes(),
+                    self.final_size_expected
+                ),
+            ));
+        }
+        self.w.write_all(&buf[..read])?;
+
+        Ok(read as u64)
+    }
+
+    fn commit(mut self: Box<Self>) -> io::Result<()> {
+        self.w.flush()?;
+        io::assert_eq(self.r.read_bytes(), self.final_size_expected, "final size")?;
+        io::assert_eq(&self.r.hash(), &self.final_hash_expected, "final hash")?;
+
+        io::remove_file(&self.final_path)?;
+        fs::rename(&self.tmp_path, &self.final_path)?;
+        if let Some(mtime) = self.mtime {
+            io::set_mtime(&self.final_path, mtime)?;
+        }
         Ok(())
     }
 }
@@ -181,16 +468,17 @@ impl<W: io::Write + io::Seek + io::Read> Applier for WriteApplier<'_, W> {
 /// Simple write Applier
 pub struct CheckApplier<R> {
     final_size_expected: u64,
-    final_sha1_expected: metadata::Sha1Hash,
-    r: io::CheckReader<R, io::CheckSha1Size>,
+    final_hash_expected: metadata::Hash,
+    r: io::CheckReader<R, io::CheckHashSize>,
 }
 
 impl<R> CheckApplier<R> {
-    pub fn new(final_size: u64, final_sha1: metadata::Sha1Hash, r: R) -> Self {
+    pub fn new(final_size: u64, final_hash: metadata::Hash, r: R) -> Self {
+        let check = io::CheckHashSize::new(final_hash.algorithm());
         Self {
             final_size_expected: final_size,
-            final_sha1_expected: final_sha1,
-            r: io::CheckReader::new(r),
+            final_hash_expected: final_hash,
+            r: io::CheckReader::with_check(r, check),
         }
     }
 }
@@ -226,17 +514,17 @@ impl<R: io::Read> Applier for CheckApplier<R> {
 
     fn commit(mut self: Box<Self>) -> io::Result<()> {
         io::assert_eq(self.r.read_bytes(), self.final_size_expected, "final size")?;
-        io::assert_eq(&self.r.sha1(), &self.final_sha1_expected, "final sha1")?;
+        io::assert_eq(&self.r.hash(), &self.final_hash_expected, "final hash")?;
 
         Ok(())
     }
 }
 
-impl ApplyOperation for metadata::v1::Operation {
+impl ApplyOperation for metadata::v2::Operation {
     fn apply_handler<'a>(&self, ctx: HandlerContext<'a>) -> io::Result<Box<dyn ApplyHandler + 'a>> {
         if let Some(handler_name) = self.slice_handler() {
             if handler_name.as_str() == "sliced" {
-                let handler = sliced::Handler::from_v1_operation(ctx, self)?;
+                let handler = sliced::Handler::from_operation(ctx, self)?;
                 return Ok(Box::new(handler));
             }
 
@@ -254,12 +542,15 @@ impl ApplyOperation for metadata::v1::Operation {
         handler: &'a mut dyn ApplyHandler,
     ) -> io::Result<Option<Box<dyn Applier + 'a>>> {
         match self {
-            metadata::v1::Operation::Add(op) => handler.add(op),
-            metadata::v1::Operation::Patch(op) => handler.patch(op),
-            metadata::v1::Operation::Check(op) => handler.check(op),
-            metadata::v1::Operation::MkDir { path, .. } => handler.mkdir(path),
-            metadata::v1::Operation::RmDir { path, .. } => handler.rmdir(path),
-            metadata::v1::Operation::Rm(op) => handler.rm(op),
+            metadata::v2::Operation::Add(op) => handler.add(op),
+            metadata::v2::Operation::Patch(op) => handler.patch(op),
+            metadata::v2::Operation::Check(op) => handler.check(op),
+            metadata::v2::Operation::MkDir { path, .. } => handler.mkdir(path),
+            metadata::v2::Operation::RmDir { path, .. } => handler.rmdir(path),
+            metadata::v2::Operation::Rm(op) => handler.rm(op),
+            metadata::v2::Operation::Symlink { path, target } => handler.symlink(path, target),
+            metadata::v2::Operation::Chunked(op) => handler.chunked(op),
+            metadata::v2::Operation::Copy(op) => handler.copy(op),
         }
     }
 }
@@ -3,9 +3,7 @@ pub use std::io::*;
 use std::path::Path;
 use std::{fmt, fs};
 
-use sha1::{Digest, Sha1};
-
-use crate::metadata::Sha1Hash;
+use crate::metadata::{Hash, HashAlgorithm, Hasher};
 
 /// Buffer size to use in the whole library
 pub const BUFFER_SIZE: usize = 128 * 1024;
@@ -48,45 +46,49 @@ impl Check for CheckSize {
     }
 }
 
-#[derive(Default)]
-pub struct CheckSha1Size {
-    pub sha1: Sha1,
+#[derive(Clone)]
+pub struct CheckHashSize {
+    pub hasher: Hasher,
     pub bytes: u64,
 }
 
-impl CheckSha1Size {
-    pub fn sha1(&mut self) -> Sha1Hash {
-        Sha1Hash::new(self.sha1.finalize_reset().into())
+impl CheckHashSize {
+    pub fn new(algorithm: HashAlgorithm) -> Self {
+        Self { hasher: algorithm.hasher(), bytes: 0 }
+    }
+
+    pub fn hash(&mut self) -> Hash {
+        self.hasher.finalize_reset()
     }
 }
 
-impl Check for CheckSha1Size {
+impl Check for CheckHashSize {
     #[inline]
     fn check(&mut self, buf: &[u8]) {
-        self.sha1.update(buf);
+        self.hasher.update(buf);
         self.bytes += buf.len() as u64;
     }
 }
 
-/// Reader adaptor which compute sha1 and count read bytes.
+/// Reader adaptor which compute hash and count read bytes.
 pub struct CheckReader<R, C> {
     pub reader: R,
     pub check: C,
 }
 
-impl<R, C: Default> CheckReader<R, C> {
-    pub fn new(reader: R) -> Self {
-        CheckReader { reader, check: C::default() }
+impl<R, C> CheckReader<R, C> {
+    pub fn with_check(reader: R, check: C) -> Self {
+        CheckReader { reader, check }
     }
 }
 
-impl<R> CheckReader<R, CheckSha1Size> {
+impl<R> CheckReader<R, CheckHashSize> {
     pub fn read_bytes(&self) -> u64 {
         self.check.bytes
     }
 
-    pub fn sha1(&mut self) -> Sha1Hash {
-        self.check.sha1()
+    pub fn hash(&mut self) -> Hash {
+        self.check.hash()
     }
 }
 
@@ -102,7 +104,7 @@ where
     }
 }
 
-/// Writer adaptor which compute sha1 and count written bytes.
+/// Writer adaptor which compute hash and count written bytes.
 pub struct CheckWriter<W, C> {
     pub writer: W,
     pub check: C,
@@ -121,6 +123,12 @@ impl<W, C: Default> CheckWriter<W, C> {
     }
 }
 
+impl<W, C> CheckWriter<W, C> {
+    pub fn with_check(writer: W, check: C) -> Self {
+        CheckWriter { writer, check }
+    }
+}
+
 impl<T, C> Write for CheckWriter<T, C>
 where
     T: Write,
@@ -176,12 +184,16 @@ pub fn atomic_write_json<P: AsRef<Path>, T>(path: P, value: &T) -> Result<()>
 where
     T: serde::Serialize,
 {
+    atomic_write(path, &serde_json::to_vec_pretty(value)?)
+}
+
+pub fn atomic_write<P: AsRef<Path>>(path: P, content: &[u8]) -> Result<()> {
     let path = path.as_ref();
     let mut tmp_path = path.as_os_str().to_owned();
     tmp_path.push(".tmp");
     {
         let mut file = fs::File::create(&tmp_path)?;
-        serde_json::to_writer_pretty(&mut file, value)?;
+        file.write_all(content)?;
         file.flush()?;
     }
     let res = atomic_rename(&tmp_path, path);
@@ -283,21 +295,77 @@ impl<T: Read> Read for Slice<T> {
     }
 }
 
+/// Set `file` permission bits to `mode` if known, otherwise only make it
+/// executable if `exe`
 #[cfg(unix)]
-pub fn set_exe_permission(file: &fs::File, exe: bool) -> Result<()> {
+pub fn set_permissions(file: &fs::File, exe: bool, mode: Option<u32>) -> Result<()> {
     use std::os::unix::fs::PermissionsExt;
-    if exe {
-        let mut perms = file.metadata()?.permissions();
-        let mode = perms.mode();
-        if (mode & 0o111) != 0o111 {
-            perms.set_mode(mode | 0o111);
-            file.set_permissions(perms)?;
-        }
+    let mut perms = file.metadata()?.permissions();
+    let current = perms.mode() & 0o7777;
+    let expected = match mode {
+        // setuid, setgid and sticky bits are never restored
+        Some(mode) => mode & 0o777,
+        None if exe => current | 0o111,
+        None => current,
+    };
+    if current != expected {
+        perms.set_mode(expected);
+        file.set_permissions(perms)?;
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn set_permissions(_file: &fs::File, _exe: bool, _mode: Option<u32>) -> Result<()> {
+    Ok(())
+}
+
+/// Fails if `file` permission bits aren't `mode`
+#[cfg(unix)]
+pub fn assert_mode_eq(file: &fs::File, mode: u32) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    let current = file.metadata()?.permissions().mode() & 0o7777;
+    if current != mode & 0o777 {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("mode mismatch, found: {:o}, expected: {:o}", current, mode & 0o777),
+        ));
     }
     Ok(())
 }
 
 #[cfg(not(unix))]
-pub fn set_exe_permission(_file: &fs::File, _exe: bool) -> Result<()> {
+pub fn assert_mode_eq(_file: &fs::File, _mode: u32) -> Result<()> {
     Ok(())
 }
+
+/// Set `path` modification time (seconds since the Unix epoch)
+pub fn set_mtime<P: AsRef<Path>>(path: P, mtime: u64) -> Result<()> {
+    // owners don't need write access to change times (the file can be read only)
+    #[cfg(unix)]
+    let file = fs::File::open(path)?;
+    #[cfg(not(unix))]
+    let file = fs::OpenOptions::new().write(true).open(path)?;
+    file.set_modified(std::time::UNIX_EPOCH + std::time::Duration::from_secs(mtime))
+}
+
+/// Create a symbolic link at `link` pointing to `target`
+#[cfg(unix)]
+pub fn symlink<P: AsRef<Path>>(target: &str, link: P) -> Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+/// Create a symbolic link at `link` pointing to `target`
+///
+/// Windows distinguishes file and directory links, the kind is the one of the
+/// existing target (file if it doesn't exist yet).
+#[cfg(windows)]
+pub fn symlink<P: AsRef<Path>>(target: &str, link: P) -> Result<()> {
+    let link = link.as_ref();
+    let resolved = link.parent().unwrap_or(Path::new("")).join(target);
+    if resolved.is_dir() {
+        std::os::windows::fs::symlink_dir(target, link)
+    } else {
+        std::os::windows::fs::symlink_file(target, link)
+    }
+}
@@ -5,6 +5,7 @@ mod io;
 pub mod link;
 pub mod metadata;
 pub mod repository;
+pub mod signature;
 mod sync;
 pub mod workspace;
 
@@ -20,8 +21,15 @@ pub mod tests {
         path::{Path, PathBuf},
     };
 
+    use futures::TryStreamExt;
     use tracing::log;
 
+    use crate::link::RemoteRepository;
+    use crate::metadata::{self, CleanName};
+    use crate::repository::{BuildOptions, PackageBuilder};
+    use crate::workspace::{UpdateError, UpdateOptions};
+    use crate::{Repository, Workspace};
+
     pub fn init() {
         let _ =
             env_logger::builder().filter_level(log::LevelFilter::Debug).is_test(true).try_init();
@@ -54,9 +62,17 @@ pub mod tests {
 
     #[track_caller]
     pub fn assert_fs_eq(path0: &Path, path1: &Path) {
-        let m0 = fs::metadata(path0).unwrap();
-        let m1 = fs::metadata(path1).unwrap();
-        if m0.is_file() && m1.is_file() {
+        let m0 = fs::symlink_metadata(path0).unwrap();
+        let m1 = fs::symlink_metadata(path1).unwrap();
+        if m0.is_symlink() && m1.is_symlink() {
+            assert_eq!(
+                fs::read_link(path0).unwrap(),
+                fs::read_link(path1).unwrap(),
+                "{:?} and {:?} targets differ",
+                path0,
+                path1
+            );
+        } else if m0.is_file() && m1.is_file() {
             assert_eq!(
                 Bytes(&fs::read(path0).unwrap()),
                 Bytes(&fs::read(path1).unwrap()),
@@ -119,4 +135,67 @@ pub mod tests {
     pub fn data(name: &str) -> PathBuf {
         PathBuf::from("tests/data").join(name)
     }
+
+    /// Build the `builder` package, add it to `repository` and make its
+    /// version the current one
+    pub fn publish_package(repository: &mut Repository, builder: &PackageBuilder) {
+        let rt = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        rt.block_on(builder.build().try_for_each(|_| async { Ok(()) })).unwrap();
+        builder.add_to_repository(repository).unwrap();
+        register_version(repository, &builder.source_version);
+        repository.set_current_version(None, &builder.source_version).unwrap();
+    }
+
+    /// Empty repository in `<path>/repository`
+    pub fn init_repository(path: &Path) -> Repository {
+        let mut repository = Repository::new(path.join("repository"));
+        fs::create_dir_all(repository.dir()).unwrap();
+        repository.init().unwrap();
+        repository
+    }
+
+    /// Publish each `(version, source)` of `versions` in order, built in
+    /// `<path>/build` as a patch of the previous one, and return the builder of
+    /// the last version
+    pub fn publish_versions(
+        repository: &mut Repository,
+        path: &Path,
+        versions: &[(&CleanName, &Path)],
+        options: impl Fn() -> BuildOptions,
+    ) -> PackageBuilder {
+        let mut previous: Option<(&CleanName, &Path)> = None;
+        let mut last_builder = None;
+        for &(version, source) in versions {
+            let mut builder =
+                PackageBuilder::new(path.join("build"), version.clone(), source.to_owned());
+            if let Some((previous_version, previous_source)) = previous {
+                builder.set_previous(previous_version.clone(), previous_source.to_owned());
+            }
+            builder.set_options(options());
+            publish_package(repository, &builder);
+            previous = Some((version, source));
+            last_builder = Some(builder);
+        }
+        last_builder.expect("at least one version")
+    }
+
+    /// Update `workspace` to `version` of `link`
+    pub fn update_workspace(
+        workspace: &mut Workspace,
+        link: &impl RemoteRepository,
+        version: &CleanName,
+    ) -> Result<(), UpdateError> {
+        let rt = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        let update_stream = workspace.update(link, Some(version.clone()), UpdateOptions::default());
+        rt.block_on(update_stream.try_for_each(|_| async { Ok(()) }))
+    }
+
+    pub fn register_version(repository: &Repository, revision: &CleanName) {
+        let version = metadata::v1::Version {
+            revision: revision.clone(),
+            description: String::new(),
+            yanked: None,
+        };
+        repository.register_version(&version).unwrap();
+    }
 }
@@ -0,0 +1,363 @@
+//! Repository files packed in a single file, for offline updates
+//!
+//! ## Bundle layout :
+//!
+//! - `SPDBNDL1`: magic bytes
+//! - index size: 64 bits little endian
+//! - index: a JSON object listing the name and size of each file
+//! - content of each file, in index order
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Read, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::prelude::*;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+use crate::io;
+use crate::link::{RemoteRepository, RepositoryError, RepositoryStream};
+use crate::metadata;
+use crate::signature::{self, SignedVersions, VerifyingKey};
+
+const MAGIC: &[u8; 8] = b"SPDBNDL1";
+/// Bigger indexes are corrupted, a repository has a few files per package
+const MAX_INDEX_SIZE: u64 = 64 * 1024 * 1024;
+
+#[derive(Serialize, Deserialize)]
+struct BundleIndex {
+    files: Vec<BundleFile>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct BundleFile {
+    name: String,
+    size: u64,
+}
+
+/// Content of a file to bundle
+pub(crate) enum BundleSource {
+    Raw(Vec<u8>),
+    File(PathBuf),
+}
+
+/// Write `files` to the bundle at `path`
+pub(crate) fn write_bundle(path: &Path, files: &[(String, BundleSource)]) -> std::io::Result<()> {
+    let mut index = BundleIndex { files: Vec::with_capacity(files.len()) };
+    for (name, source) in files {
+        let size = match source {
+            BundleSource::Raw(raw) => raw.len() as u64,
+            BundleSource::File(path) => fs::metadata(path)?.len(),
+        };
+        index.files.push(BundleFile { name: name.clone(), size });
+    }
+
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    let res = (|| {
+        let raw_index = serde_json::to_vec(&index)?;
+        let mut bundle = std::io::BufWriter::new(fs::File::create(&tmp_path)?);
+        bundle.write_all(MAGIC)?;
+        bundle.write_all(&(raw_index.len() as u64).to_le_bytes())?;
+        bundle.write_all(&raw_index)?;
+        for ((_, source), file) in files.iter().zip(&index.files) {
+            match source {
+                BundleSource::Raw(raw) => bundle.write_all(raw)?,
+                BundleSource::File(path) => {
+                    let mut reader = fs::File::open(path)?.take(file.size);
+                    if std::io::copy(&mut reader, &mut bundle)? != file.size {
+                        return Err(std::io::Error::new(
+                            std::io::ErrorKind::UnexpectedEof,
+                            format!("{:?} was truncated while bundled", path),
+                        ));
+                    }
+                }
+            }
+        }
+        bundle.into_inner().map_err(|err| err.into_error())?.sync_all()?;
+        io::atomic_rename(&tmp_path, path)
+    })();
+    if res.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    res
+}
+
+/// Repository packed in a bundle file (see
+/// [`Repository::export_bundle`](crate::repository::Repository::export_bundle))
+///
+/// Files are read by seeking inside the bundle.
+pub struct BundleRepository {
+    path: PathBuf,
+    files: HashMap<String, Range<u64>>,
+    public_key: Option<VerifyingKey>,
+    signed_versions: Arc<SignedVersions>,
+}
+
+impl BundleRepository {
+    /// Open the bundle at `path` and read its index
+    pub fn open(path: PathBuf) -> Result<Self, RepositoryError> {
+        let files = read_index(&path).map_err(|err| RepositoryError::file(&path, err))?;
+        Ok(BundleRepository { path, files, public_key: None, signed_versions: Arc::default() })
+    }
+
+    /// Require metadata files to be signed by the owner of `public_key`
+    pub fn set_public_key(&mut self, public_key: VerifyingKey) {
+        self.public_key = Some(public_key);
+    }
+
+    pub(super) fn set_signed_versions(&mut self, signed_versions: Arc<SignedVersions>) {
+        self.signed_versions = signed_versions;
+    }
+
+    fn file_range(&self, file_name: &str) -> Result<Range<u64>, RepositoryError> {
+        self.files.get(file_name).cloned().ok_or_else(|| {
+            let err = std::io::Error::new(std::io::ErrorKind::NotFound, "not in the bundle");
+            RepositoryError::file(&self.path.join(file_name), err)
+        })
+    }
+
+    async fn read(&self, file_name: &str) -> Result<Vec<u8>, RepositoryError> {
+        let range = self.file_range(file_name)?;
+        let mut file = open_at(&self.path, range.start).await?;
+        let mut raw = vec![0u8; (range.end - range.start) as usize];
+        file.read_exact(&mut raw).await.map_err(|err| RepositoryError::file(&self.path, err))?;
+        Ok(raw)
+    }
+
+    async fn get<T>(
+        &self,
+        file_name: &str,
+        encoding: metadata::Encoding,
+    ) -> Result<T, RepositoryError>
+    where
+        T: for<'de> serde::Deserialize<'de>,
+    {
+        let raw = self.read(file_name).await?;
+        if let Some(public_key) = &self.public_key {
+            let signature = self.read(&signature::signature_filename(file_name)).await?;
+            super::verify_signature(
+                public_key,
+                &self.signed_versions,
+                file_name,
+                &raw,
+                &signature,
+            )?;
+        }
+        super::decode(&self.path.join(file_name), encoding, &raw)
+    }
+}
+
+/// Bundled files and their position in the bundle
+fn read_index(path: &Path) -> std::io::Result<HashMap<String, Range<u64>>> {
+    let invalid = |reason: &str| std::io::Error::new(std::io::ErrorKind::InvalidData, reason);
+    let mut file = fs::File::open(path)?;
+    let mut header = [0u8; 16];
+    file.read_exact(&mut header)?;
+    if &header[..8] != MAGIC {
+        return Err(invalid("not a bundle"));
+    }
+    let index_size = u64::from_le_bytes(header[8..].try_into().expect("8 bytes"));
+    if index_size > MAX_INDEX_SIZE {
+        return Err(invalid("bundle index is too big"));
+    }
+    let mut index = vec![0u8; index_size as usize];
+    file.read_exact(&mut index)?;
+    let index: BundleIndex = serde_json::from_slice(&index)?;
+
+    let mut offset = header.len() as u64 + index_size;
+    let mut files = HashMap::with_capacity(index.files.len());
+    for file in index.files {
+        let end = offset.checked_add(file.size).ok_or_else(|| invalid("invalid file size"))?;
+        files.insert(file.name, offset..end);
+        offset = end;
+    }
+    if offset > file.metadata()?.len() {
+        return Err(invalid("bundle is truncated"));
+    }
+    Ok(files)
+}
+
+/// `range` of the file at `file_range` in the bundle at `path`
+async fn read_range(
+    path: PathBuf,
+    file_range: Range<u64>,
+    range: Range<u64>,
+) -> Result<RepositoryStream<Bytes>, RepositoryError> {
+    let start = file_range.start.saturating_add(range.start).min(file_range.end);
+    let end = file_range.start.saturating_add(range.end).min(file_range.end);
+    let file = open_at(&path, start).await?;
+    let stream = tokio_util::io::ReaderStream::new(file.take(end - start))
+        .map_err(move |err| RepositoryError::file(&path, err))
+        .boxed_local();
+    Ok(stream)
+}
+
+async fn open_at(path: &Path, offset: u64) -> Result<tokio::fs::File, RepositoryError> {
+    let mut file =
+        tokio::fs::File::open(path).await.map_err(|err| RepositoryError::file(path, err))?;
+    file.seek(std::io::SeekFrom::Start(offset))
+        .await
+        .map_err(|err| RepositoryError::file(path, err))?;
+    Ok(file)
+}
+
+#[async_trait]
+impl RemoteRepository for BundleRepository {
+    async fn current_version(&self) -> Result<metadata::Current, RepositoryError> {
+        self.get(metadata::Current::filename(), metadata::Encoding::Json).await
+    }
+
+    async fn current_version_for(
+        &self,
+        channel: &metadata::CleanName,
+    ) -> Result<metadata::Current, RepositoryError> {
+        let filename = metadata::Current::channel_filename(Some(channel));
+        self.get(&filename, metadata::Encoding::Json).await
+    }
+
+    async fn versions(&self) -> Result<metadata::Versions, RepositoryError> {
+        self.get(metadata::Versions::filename(), metadata::Encoding::Json).await
+    }
+
+    async fn packages(&self) -> Result<metadata::Packages, RepositoryError> {
+        self.get(metadata::Packages::filename(), metadata::Encoding::Json).await
+    }
+
+    async fn package_metadata(
+        &self,
+        package_name: metadata::CleanName,
+    ) -> Result<metadata::PackageMetadata, RepositoryError> {
+        let cbor_name = metadata::Encoding::Cbor.filename(&package_name);
+        match self.get(&cbor_name, metadata::Encoding::Cbor).await {
+            Err(err) if err.is_missing_sibling() => {
+                self.get(&package_name, metadata::Encoding::Json).await
+            }
+            res => res,
+        }
+    }
+
+    async fn package(
+        &self,
+        package_name: metadata::CleanName,
+        range: Range<u64>,
+    ) -> Result<RepositoryStream<Bytes>, RepositoryError> {
+        read_range(self.path.clone(), self.file_range(&package_name)?, range).await
+    }
+
+    /// Read each range separately, no byte between ranges is read
+    async fn package_ranges(
+        &self,
+        package_name: metadata::CleanName,
+        ranges: Vec<Range<u64>>,
+    ) -> Result<RepositoryStream<(u64, Bytes)>, RepositoryError> {
+        let (path, file_range) = (self.path.clone(), self.file_range(&package_name)?);
+        let stream = stream::iter(ranges)
+            .then(move |range| {
+                read_range(path.clone(), file_range.clone(), range.clone())
+                    .map_ok(move |stream| super::with_offsets(stream, range))
+            })
+            .try_flatten();
+        Ok(stream.boxed_local())
+    }
+    fn signed_versions(&self) -> HashMap<String, u64> {
+        self.signed_versions.to_map()
+    }
+
+    fn expect_signed_versions(&self, versions: &HashMap<String, u64>) {
+        self.signed_versions.extend(versions);
+    }
+
+    fn public_key(&self) -> Option<VerifyingKey> {
+        self.public_key
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use futures::TryStreamExt;
+
+    use crate::metadata::{self, CleanName};
+    use crate::repository::{BuildOptions, PackageBuilder};
+    use crate::signature;
+    use crate::workspace::UpdateOptions;
+    use crate::{Repository, Workspace};
+
+    #[test]
+    fn offline_bundle() {
+        use crate::link::{AutoRepository, RemoteRepository};
+
+        crate::tests::init();
+        let rt = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        let v1 = CleanName::from_static_str("1");
+        let v2 = CleanName::from_static_str("2");
+        let path = crate::tests::tmp_dir("offline_bundle");
+        let source_v1 = path.join("source_v1");
+        fs::create_dir_all(&source_v1).unwrap();
+        fs::write(source_v1.join("a.txt"), "air-gapped").unwrap();
+        fs::write(source_v1.join("b.bin"), vec![3u8; 128 * 1024]).unwrap();
+        let source_v2 = path.join("source_v2");
+        fs::create_dir_all(&source_v2).unwrap();
+        fs::write(source_v2.join("a.txt"), "air-gapped, updated").unwrap();
+        fs::write(source_v2.join("c.bin"), vec![5u8; 64 * 1024]).unwrap();
+
+        let signing_key = signature::SigningKey::from_bytes(&[9u8; 32]);
+        let mut repository = Repository::new(path.join("repository"));
+        fs::create_dir_all(repository.dir()).unwrap();
+        repository.set_signing_key(signing_key.clone());
+        repository.set_binary_metadata(true);
+        repository.init().unwrap();
+        let mut builder = PackageBuilder::new(path.join("build"), v1.clone(), source_v1.clone());
+        builder.set_options(BuildOptions::raw());
+        crate::tests::publish_package(&mut repository, &builder);
+        let mut builder = PackageBuilder::new(path.join("build"), v2.clone(), source_v2.clone());
+        builder.set_previous(v1.clone(), source_v1.clone());
+        builder.set_options(BuildOptions::raw());
+        crate::tests::publish_package(&mut repository, &builder);
+        repository.set_current_version(None, &v1).unwrap();
+
+        let workspace_dir = path.join("workspace");
+        let mut workspace = Workspace::open(&workspace_dir).unwrap();
+        let link = repository.link();
+        let update_stream = workspace.update(&link, Some(v1.clone()), UpdateOptions::default());
+        rt.block_on(update_stream.try_for_each(|_| async { Ok(()) })).unwrap();
+
+        let bundle_path = path.join("v1_to_v2.bundle");
+        repository.export_bundle(Some(&v1), &v2, None, &bundle_path).unwrap();
+        let unknown = CleanName::from_static_str("3");
+        assert!(repository.export_bundle(Some(&v1), &unknown, None, &bundle_path).is_err());
+
+        let url = format!("bundle://{}", bundle_path.display());
+        let link = AutoRepository::new(&url, None, Some(signing_key.verifying_key())).unwrap();
+        assert!(matches!(link, AutoRepository::Bundle(_)));
+        assert_eq!(rt.block_on(link.current_version()).unwrap().version(), &v2);
+        let packages = rt.block_on(link.packages()).unwrap();
+        assert_eq!(packages.iter().map(|p| p.from()).collect::<Vec<_>>(), vec![Some(&v1)]);
+
+        let update_stream = workspace.update(&link, None, UpdateOptions::default());
+        rt.block_on(update_stream.try_for_each(|_| async { Ok(()) })).unwrap();
+        workspace.remove_metadata().unwrap();
+        crate::tests::assert_fs_eq(&source_v2, &workspace_dir);
+
+        // bundles missing metadata aren't exported
+        let versions_path = repository.dir().join(metadata::Versions::filename());
+        let moved_versions_path = path.join(metadata::Versions::filename());
+        fs::rename(&versions_path, &moved_versions_path).unwrap();
+        let err = repository.export_bundle(Some(&v1), &v2, None, &bundle_path).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
+        fs::rename(&moved_versions_path, &versions_path).unwrap();
+        let package_metadata_name = packages.iter().next().unwrap().package_metadata_name();
+        let cbor_name = metadata::Encoding::Cbor.filename(&package_metadata_name);
+        for filename in [package_metadata_name.to_string(), cbor_name] {
+            let _ = fs::remove_file(repository.dir().join(filename));
+        }
+        let err = repository.export_bundle(Some(&v1), &v2, None, &bundle_path).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
+    }
+}
@@ -1,56 +1,195 @@
+use std::collections::HashMap;
 use std::ops::Range;
 use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
 
 use async_trait::async_trait;
 use bytes::Bytes;
 use futures::prelude::*;
-use serde_json;
 use tokio::io::AsyncSeekExt;
 
-use crate::link::{RemoteRepository, RepositoryError, RepositoryStream};
+use crate::link::{Conditional, RemoteRepository, RepositoryError, RepositoryStream, Validator};
 use crate::metadata;
+use crate::signature::{self, SignedVersions, VerifyingKey};
 
 pub struct FileRepository {
     dir: PathBuf,
+    public_key: Option<VerifyingKey>,
+    signed_versions: Arc<SignedVersions>,
 }
 
 impl FileRepository {
     pub fn new(dir: PathBuf) -> FileRepository {
-        FileRepository { dir }
+        FileRepository { dir, public_key: None, signed_versions: Arc::default() }
     }
 
-    async fn get<T>(&self, file_name: &str) -> Result<T, RepositoryError>
+    /// Require metadata files to be signed by the owner of `public_key`
+    pub fn set_public_key(&mut self, public_key: VerifyingKey) {
+        self.public_key = Some(public_key);
+    }
+
+    pub(super) fn set_signed_versions(&mut self, signed_versions: Arc<SignedVersions>) {
+        self.signed_versions = signed_versions;
+    }
+
+    async fn read(&self, file_name: &str) -> Result<Vec<u8>, RepositoryError> {
+        let path = self.dir.join(file_name);
+        tokio::fs::read(&path).await.map_err(|err| RepositoryError::file(&path, err))
+    }
+
+    /// Read `file_name` unless `validator` was issued for its current
+    /// modification time and size
+    async fn read_if_modified(
+        &self,
+        file_name: &str,
+        validator: Option<&Validator>,
+    ) -> Result<Conditional<Vec<u8>>, RepositoryError> {
+        let path = self.dir.join(file_name);
+        let metadata =
+            tokio::fs::metadata(&path).await.map_err(|err| RepositoryError::file(&path, err))?;
+        let tag = metadata.modified().ok().and_then(|mtime| {
+            let mtime = mtime.duration_since(UNIX_EPOCH).ok()?;
+            Some(format!("{}.{:09}-{}", mtime.as_secs(), mtime.subsec_nanos(), metadata.len()))
+        });
+        if tag.is_some() && tag.as_deref() == Validator::tag_for(validator, file_name) {
+            return Ok(Conditional::NotModified);
+        }
+        let value = self.read(file_name).await?;
+        let validator = tag.map(|tag| Validator::new(file_name, &tag));
+        Ok(Conditional::Modified { value, validator })
+    }
+
+    /// Read `file_name` content, from its compressed sibling if there is one
+    async fn read_metadata(
+        &self,
+        file_name: &str,
+        validator: Option<&Validator>,
+    ) -> Result<Conditional<Vec<u8>>, RepositoryError> {
+        for (compressed_name, decompressor_name) in super::compressed_siblings(file_name) {
+            match self.read_if_modified(&compressed_name, validator).await {
+                Ok(Conditional::Modified { value: raw, validator }) => {
+                    let path = self.dir.join(compressed_name);
+                    let value = super::decompress(&path, decompressor_name, &raw)?;
+                    return Ok(Conditional::Modified { value, validator });
+                }
+                Ok(Conditional::NotModified) => return Ok(Conditional::NotModified),
+                Err(err) if err.is_missing_sibling() => {}
+                Err(err) => return Err(err),
+            }
+        }
+        self.read_if_modified(file_name, validator).await
+    }
+
+    async fn get_if_modified<T>(
+        &self,
+        file_name: &str,
+        encoding: metadata::Encoding,
+        validator: Option<&Validator>,
+    ) -> Result<Conditional<T>, RepositoryError>
     where
         T: for<'de> serde::Deserialize<'de>,
     {
         println!("one more from file");
         let path = self.dir.join(file_name);
-        let raw = tokio::fs::read(&path).await.map_err(|err| RepositoryError::file(&path, err))?;
-        let decoded =
-            serde_json::from_slice::<T>(&raw).map_err(|err| RepositoryError::json(&path, err))?;
-        Ok(decoded)
+        let res = super::retry_bad_signature(|| self.read_verified(file_name, validator)).await;
+        let (raw, validator) = match res? {
+            Conditional::Modified { value, validator } => (value, validator),
+            Conditional::NotModified => return Ok(Conditional::NotModified),
+        };
+        let value = super::decode(&path, encoding, &raw)?;
+        Ok(Conditional::Modified { value, validator })
+    }
+
+    /// Read `file_name` content and check its signature
+    async fn read_verified(
+        &self,
+        file_name: &str,
+        validator: Option<&Validator>,
+    ) -> Result<Conditional<Vec<u8>>, RepositoryError> {
+        let res = self.read_metadata(file_name, validator).await?;
+        if let (Some(public_key), Conditional::Modified { value: raw, .. }) =
+            (&self.public_key, &res)
+        {
+            let signature_path = self.dir.join(signature::signature_filename(file_name));
+            let signature = tokio::fs::read(&signature_path)
+                .await
+                .map_err(|err| RepositoryError::file(&signature_path, err))?;
+            super::verify_signature(public_key, &self.signed_versions, file_name, raw, &signature)?;
+        }
+        Ok(res)
+    }
+
+    async fn get<T>(
+        &self,
+        file_name: &str,
+        encoding: metadata::Encoding,
+    ) -> Result<T, RepositoryError>
+    where
+        T: for<'de> serde::Deserialize<'de>,
+    {
+        Ok(self.get_if_modified(file_name, encoding, None).await?.into_value())
     }
 }
 
 #[async_trait]
 impl RemoteRepository for FileRepository {
     async fn current_version(&self) -> Result<metadata::Current, RepositoryError> {
-        self.get(metadata::Current::filename()).await
+        self.get(metadata::Current::filename(), metadata::Encoding::Json).await
+    }
+
+    async fn current_version_for(
+        &self,
+        channel: &metadata::CleanName,
+    ) -> Result<metadata::Current, RepositoryError> {
+        let filename = metadata::Current::channel_filename(Some(channel));
+        self.get(&filename, metadata::Encoding::Json).await
     }
 
     async fn versions(&self) -> Result<metadata::Versions, RepositoryError> {
-        self.get(metadata::Versions::filename()).await
+        self.get(metadata::Versions::filename(), metadata::Encoding::Json).await
     }
 
     async fn packages(&self) -> Result<metadata::Packages, RepositoryError> {
-        self.get(metadata::Packages::filename()).await
+        self.get(metadata::Packages::filename(), metadata::Encoding::Json).await
+    }
+
+    async fn current_version_if_modified(
+        &self,
+        channel: Option<&metadata::CleanName>,
+        validator: Option<&Validator>,
+    ) -> Result<Conditional<metadata::Current>, RepositoryError> {
+        let filename = metadata::Current::channel_filename(channel);
+        self.get_if_modified(&filename, metadata::Encoding::Json, validator).await
+    }
+
+    async fn versions_if_modified(
+        &self,
+        validator: Option<&Validator>,
+    ) -> Result<Conditional<metadata::Versions>, RepositoryError> {
+        let filename = metadata::Versions::filename();
+        self.get_if_modified(filename, metadata::Encoding::Json, validator).await
+    }
+
+    async fn packages_if_modified(
+        &self,
+        validator: Option<&Validator>,
+    ) -> Result<Conditional<metadata::Packages>, RepositoryError> {
+        let filename = metadata::Packages::filename();
+        self.get_if_modified(filename, metadata::Encoding::Json, validator).await
     }
 
     async fn package_metadata(
         &self,
         package_name: metadata::CleanName,
     ) -> Result<metadata::PackageMetadata, RepositoryError> {
-        self.get(&package_name).await
+        let cbor_name = metadata::Encoding::Cbor.filename(&package_name);
+        match self.get(&cbor_name, metadata::Encoding::Cbor).await {
+            Err(err) if err.is_missing_sibling() => {
+                self.get(&package_name, metadata::Encoding::Json).await
+            }
+            res => res,
+        }
     }
 
     async fn package(
@@ -58,25 +197,57 @@ impl RemoteRepository for FileRepository {
         package_name: metadata::CleanName,
         range: Range<u64>,
     ) -> Result<RepositoryStream<Bytes>, RepositoryError> {
+        read_range(self.dir.join(&package_name), range.start).await
+    }
+
+    /// Read each range separately, no byte between ranges is read
+    async fn package_ranges(
+        &self,
+        package_name: metadata::CleanName,
+        ranges: Vec<Range<u64>>,
+    ) -> Result<RepositoryStream<(u64, Bytes)>, RepositoryError> {
         let path = self.dir.join(&package_name);
-        let mut file =
-            tokio::fs::File::open(&path).map_err(|err| RepositoryError::file(&path, err)).await?;
-
-        let new_pos = file
-            .seek(tokio::io::SeekFrom::Start(range.start))
-            .map_err(|err| RepositoryError::file(&path, err))
-            .await?;
-        if new_pos != range.start {
-            return Err(RepositoryError::file(
-                &path,
-                std::io::Error::other("failed to seek at the correct position"),
-            ));
-        }
+        let stream = stream::iter(ranges)
+            .then(move |range| {
+                read_range(path.clone(), range.start)
+                    .map_ok(move |stream| super::with_offsets(stream, range))
+            })
+            .try_flatten();
+        Ok(stream.boxed_local())
+    }
 
-        let stream = tokio_util::io::ReaderStream::new(file)
-            .map_err(move |err| RepositoryError::file(&path, err))
-            .boxed_local();
+    fn signed_versions(&self) -> HashMap<String, u64> {
+        self.signed_versions.to_map()
+    }
+
+    fn expect_signed_versions(&self, versions: &HashMap<String, u64>) {
+        self.signed_versions.extend(versions);
+    }
 
-        Ok(stream)
+    fn public_key(&self) -> Option<VerifyingKey> {
+        self.public_key
     }
 }
+
+/// Read `path` from `start` to the end of the file
+async fn read_range(path: PathBuf, start: u64) -> Result<RepositoryStream<Bytes>, RepositoryError> {
+    let mut file =
+        tokio::fs::File::open(&path).map_err(|err| RepositoryError::file(&path, err)).await?;
+
+    let new_pos = file
+        .seek(tokio::io::SeekFrom::Start(start))
+        .map_err(|err| RepositoryError::file(&path, err))
+        .await?;
+    if new_pos != start {
+        return Err(RepositoryError::file(
+            &path,
+            std::io::Error::other("failed to seek at the correct position"),
+        ));
+    }
+
+    let stream = tokio_util::io::ReaderStream::new(file)
+        .map_err(move |err| RepositoryError::file(&path, err))
+        .boxed_local();
+
+    Ok(stream)
+}
@@ -0,0 +1,265 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::ops::Range;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use parking_lot::Mutex;
+use tracing::{info, warn};
+
+use crate::link::{Conditional, RemoteRepository, RepositoryError, RepositoryStream, Validator};
+use crate::metadata;
+use crate::signature::VerifyingKey;
+
+type MirrorFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T, RepositoryError>> + Send + 'a>>;
+
+/// Repository served by several mirrors, in priority order
+///
+/// Each request is sent to the mirror that answered the last request and
+/// fails over to the next mirrors if it can't be sent. Once the first mirror
+/// failed, it is tried again after a delay (see
+/// [`set_primary_retry_delay`](Self::set_primary_retry_delay)).
+///
+/// Failover only happens when a request is sent: a package stream interrupted
+/// midway fails, HTTP(S) mirrors resume it themselves (see
+/// [`RetryOptions`](super::RetryOptions)).
+pub struct MirrorRepository<R> {
+    mirrors: Vec<R>,
+    current: AtomicUsize,
+    /// Last failure of the first mirror
+    primary_failed_at: Mutex<Option<Instant>>,
+    primary_retry_delay: Duration,
+}
+
+impl<R> MirrorRepository<R>
+where
+    R: RemoteRepository + Send + Sync,
+{
+    pub fn new(mirrors: Vec<R>) -> Self {
+        MirrorRepository {
+            mirrors,
+            current: AtomicUsize::new(0),
+            primary_failed_at: Mutex::new(None),
+            primary_retry_delay: Duration::from_secs(5 * 60),
+        }
+    }
+
+    pub fn mirrors(&self) -> &[R] {
+        &self.mirrors
+    }
+
+    pub fn mirrors_mut(&mut self) -> &mut [R] {
+        &mut self.mirrors
+    }
+
+    /// Delay before sending requests to the first mirror again once it failed
+    ///
+    /// Default to 5 minutes.
+    pub fn set_primary_retry_delay(&mut self, delay: Duration) {
+        self.primary_retry_delay = delay;
+    }
+
+    /// Mirror to send the next request to
+    fn first_mirror(&self) -> usize {
+        let current = self.current.load(Ordering::Relaxed);
+        let failed_at = *self.primary_failed_at.lock();
+        match failed_at {
+            Some(failed_at) if current != 0 && failed_at.elapsed() >= self.primary_retry_delay => {
+                info!("retry mirror #0");
+                0
+            }
+            _ => current,
+        }
+    }
+
+    async fn failover<'a, T, F>(&'a self, request: F) -> Result<T, RepositoryError>
+    where
+        F: Fn(&'a R) -> MirrorFuture<'a, T>,
+    {
+        let first = self.first_mirror();
+        let mut last_err = None;
+        for offset in 0..self.mirrors.len() {
+            let idx = (first + offset) % self.mirrors.len();
+            match request(&self.mirrors[idx]).await {
+                Ok(value) => {
+                    self.current.store(idx, Ordering::Relaxed);
+                    return Ok(value);
+                }
+                Err(err) => {
+                    warn!("mirror #{} failed: {}", idx, err);
+                    if idx == 0 {
+                        *self.primary_failed_at.lock() = Some(Instant::now());
+                    }
+                    last_err = Some(err);
+                }
+            }
+        }
+        Err(last_err
+            .unwrap_or_else(|| RepositoryError::InvalidUrl { reason: "no mirror".to_string() }))
+    }
+}
+
+#[async_trait]
+impl<R> RemoteRepository for MirrorRepository<R>
+where
+    R: RemoteRepository + Send + Sync,
+{
+    async fn current_version(&self) -> Result<metadata::Current, RepositoryError> {
+        self.failover(|r| r.current_version()).await
+    }
+
+    async fn current_version_for(
+        &self,
+        channel: &metadata::CleanName,
+    ) -> Result<metadata::Current, RepositoryError> {
+        self.failover(|r| r.current_version_for(channel)).await
+    }
+
+    async fn versions(&self) -> Result<metadata::Versions, RepositoryError> {
+        self.failover(|r| r.versions()).await
+    }
+
+    async fn packages(&self) -> Result<metadata::Packages, RepositoryError> {
+        self.failover(|r| r.packages()).await
+    }
+
+    async fn current_version_if_modified(
+        &self,
+        channel: Option<&metadata::CleanName>,
+        validator: Option<&Validator>,
+    ) -> Result<Conditional<metadata::Current>, RepositoryError> {
+        self.failover(|r| r.current_version_if_modified(channel, validator)).await
+    }
+
+    async fn versions_if_modified(
+        &self,
+        validator: Option<&Validator>,
+    ) -> Result<Conditional<metadata::Versions>, RepositoryError> {
+        self.failover(|r| r.versions_if_modified(validator)).await
+    }
+
+    async fn packages_if_modified(
+        &self,
+        validator: Option<&Validator>,
+    ) -> Result<Conditional<metadata::Packages>, RepositoryError> {
+        self.failover(|r| r.packages_if_modified(validator)).await
+    }
+
+    async fn package_metadata(
+        &self,
+        package_name: metadata::CleanName,
+    ) -> Result<metadata::PackageMetadata, RepositoryError> {
+        self.failover(|r| r.package_metadata(package_name.clone())).await
+    }
+
+    async fn package(
+        &self,
+        package_name: metadata::CleanName,
+        range: Range<u64>,
+    ) -> Result<RepositoryStream<Bytes>, RepositoryError> {
+        self.failover(|r| r.package(package_name.clone(), range.clone())).await
+    }
+
+    async fn package_ranges(
+        &self,
+        package_name: metadata::CleanName,
+        ranges: Vec<Range<u64>>,
+    ) -> Result<RepositoryStream<(u64, Bytes)>, RepositoryError> {
+        self.failover(|r| r.package_ranges(package_name.clone(), ranges.clone())).await
+    }
+
+    fn download_retries(&self) -> u64 {
+        self.mirrors.iter().map(|r| r.download_retries()).sum()
+    }
+
+    /// Highest version verified by any mirror
+    fn signed_versions(&self) -> HashMap<String, u64> {
+        let mut versions = HashMap::new();
+        for mirror in &self.mirrors {
+            for (name, version) in mirror.signed_versions() {
+                let seen = versions.entry(name).or_insert(version);
+                *seen = (*seen).max(version);
+            }
+        }
+        versions
+    }
+
+    fn expect_signed_versions(&self, versions: &HashMap<String, u64>) {
+        for mirror in &self.mirrors {
+            mirror.expect_signed_versions(versions);
+        }
+    }
+
+    /// Key of the first mirror, mirrors are expected to share it
+    fn public_key(&self) -> Option<VerifyingKey> {
+        self.mirrors.first()?.public_key()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::time::Duration;
+
+    use futures::TryStreamExt;
+
+    use crate::metadata::CleanName;
+    use crate::repository::{BuildOptions, PackageBuilder};
+    use crate::workspace::UpdateOptions;
+    use crate::{Repository, Workspace};
+
+    #[test]
+    fn mirror_failover() {
+        use crate::link::{AutoRepository, HttpsRepositoryBuilder, RemoteRepository};
+
+        crate::tests::init();
+        let rt = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        let v1 = CleanName::from_static_str("1");
+        let v2 = CleanName::from_static_str("2");
+        let path = crate::tests::tmp_dir("mirror_failover");
+        let source_directory = path.join("source");
+        fs::create_dir_all(&source_directory).unwrap();
+        fs::write(source_directory.join("file.txt"), "mirrored content").unwrap();
+
+        let mut repository = Repository::new(path.join("mirrored repository"));
+        fs::create_dir_all(repository.dir()).unwrap();
+        repository.init().unwrap();
+        let mut builder = PackageBuilder::new(path.join("build"), v1.clone(), source_directory);
+        builder.set_options(BuildOptions::raw());
+        crate::tests::publish_package(&mut repository, &builder);
+
+        let missing_url = format!("file://{}", path.join("missing").display());
+        let url = format!("file://{}", repository.dir().display());
+        let link = AutoRepository::new(&url, None, None).unwrap();
+        assert!(matches!(link, AutoRepository::File(_)));
+        let builder = HttpsRepositoryBuilder::new();
+        let mut link = AutoRepository::mirrors(&[&missing_url, &url], &builder).unwrap();
+        assert!(matches!(link, AutoRepository::Mirror(_)));
+        assert_eq!(rt.block_on(link.current_version()).unwrap().version(), &v1);
+
+        let workspace_dir = path.join("workspace");
+        let mut workspace = Workspace::open(&workspace_dir).unwrap();
+        let update_stream = workspace.update(&link, None, UpdateOptions::default());
+        rt.block_on(update_stream.try_for_each(|_| async { Ok(()) })).unwrap();
+        workspace.remove_metadata().unwrap();
+        crate::tests::assert_fs_eq(&path.join("source"), &workspace_dir);
+
+        // the first mirror is back with a new current version, it is only
+        // used again after the retry delay
+        let mut options = fs_extra::dir::CopyOptions::new();
+        options.content_only = true;
+        fs_extra::dir::copy(repository.dir(), path.join("missing"), &options).unwrap();
+        let mut primary = Repository::new(path.join("missing"));
+        crate::tests::register_version(&primary, &v2);
+        primary.set_current_version(None, &v2).unwrap();
+        assert_eq!(rt.block_on(link.current_version()).unwrap().version(), &v1);
+        match &mut link {
+            AutoRepository::Mirror(link) => link.set_primary_retry_delay(Duration::ZERO),
+            _ => unreachable!(),
+        }
+        assert_eq!(rt.block_on(link.current_version()).unwrap().version(), &v2);
+    }
+}
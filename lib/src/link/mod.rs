@@ -1,21 +1,36 @@
 //! Link to remote repository
+mod bundle;
 mod file;
 mod https;
+mod mirror;
+mod multipart;
+mod s3;
 
 use std::{
+    collections::HashMap,
     fmt,
     ops::Range,
     path::{Path, PathBuf},
     pin::Pin,
+    time::Duration,
 };
 
 use async_trait::async_trait;
 use bytes::Bytes;
-use futures::Stream;
+use futures::prelude::*;
+use serde::{Deserialize, Serialize};
+use tracing::info;
 
+pub use self::bundle::BundleRepository;
+pub(crate) use self::bundle::{write_bundle, BundleSource};
 pub use self::file::FileRepository;
-pub use self::https::HttpsRepository;
-use crate::metadata;
+pub use self::https::{
+    HttpsRepository, HttpsRepositoryBuilder, RetryOptions, TokenRefresh, TokenRefreshError,
+};
+pub use self::mirror::MirrorRepository;
+pub use self::s3::{S3Config, S3Credentials, S3Repository};
+use crate::signature::{self, SignedVersions, VerifyingKey};
+use crate::{codecs, metadata};
 
 #[derive(Debug)]
 pub enum RepositoryError {
@@ -23,7 +38,13 @@ pub enum RepositoryError {
     Https(reqwest::Error),
     HttpsNotPartialContent(reqwest::StatusCode),
     Json { path: PathBuf, err: serde_json::Error },
+    Cbor { path: PathBuf, err: ciborium::de::Error<std::io::Error> },
+    Decompress { path: PathBuf, err: std::io::Error },
     InvalidUrl { reason: String },
+    BadSignature { name: String },
+    StaleSignature { name: String, version: u64 },
+    TokenRefresh(https::TokenRefreshError),
+    InvalidResponse { reason: String },
 }
 
 impl RepositoryError {
@@ -34,6 +55,52 @@ impl RepositoryError {
     pub fn json(path: &Path, err: serde_json::Error) -> Self {
         RepositoryError::Json { path: path.to_owned(), err }
     }
+
+    pub fn cbor(path: &Path, err: ciborium::de::Error<std::io::Error>) -> Self {
+        RepositoryError::Cbor { path: path.to_owned(), err }
+    }
+
+    /// Returns true if the requested file doesn't exist in the repository
+    pub fn is_not_found(&self) -> bool {
+        match self {
+            RepositoryError::File { err, .. } => err.kind() == std::io::ErrorKind::NotFound,
+            RepositoryError::Https(err) => err.status() == Some(reqwest::StatusCode::NOT_FOUND),
+            _ => false,
+        }
+    }
+
+    /// Returns true if an optional sibling file (i.e. a compressed or CBOR
+    /// copy) doesn't exist in the repository
+    ///
+    /// S3 buckets and CDNs commonly answer `403 Forbidden` for missing files.
+    pub(crate) fn is_missing_sibling(&self) -> bool {
+        match self {
+            RepositoryError::Https(err) => matches!(
+                err.status(),
+                Some(reqwest::StatusCode::NOT_FOUND | reqwest::StatusCode::FORBIDDEN)
+            ),
+            _ => self.is_not_found(),
+        }
+    }
+
+    /// Returns true if the same request might succeed later (i.e. connection
+    /// reset, timeout, server overloaded)
+    ///
+    /// Invalid requests and undecodable answers fail the same way each time,
+    /// they aren't transient.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            RepositoryError::Https(err) => match err.status() {
+                Some(status) => {
+                    status.is_server_error()
+                        || status == reqwest::StatusCode::REQUEST_TIMEOUT
+                        || status == reqwest::StatusCode::TOO_MANY_REQUESTS
+                }
+                None => err.is_timeout() || err.is_connect() || err.is_body(),
+            },
+            _ => false,
+        }
+    }
 }
 
 impl From<reqwest::Error> for RepositoryError {
@@ -53,22 +120,237 @@ impl fmt::Display for RepositoryError {
             RepositoryError::Json { path, err } => {
                 write!(f, "metadata  {:?} error: {}", path, err)
             }
+            RepositoryError::Cbor { path, err } => {
+                write!(f, "metadata {:?} error: {}", path, err)
+            }
+            RepositoryError::Decompress { path, err } => {
+                write!(f, "metadata {:?} decompression error: {}", path, err)
+            }
             RepositoryError::InvalidUrl { reason } => {
                 write!(f, "invalid repository url: {}", reason)
             }
+            RepositoryError::BadSignature { name } => {
+                write!(f, "metadata {} signature verification failed", name)
+            }
+            RepositoryError::StaleSignature { name, version } => {
+                // validly signed, but a more recent version was already verified
+                write!(f, "metadata {} signature version {} is stale", name, version)
+            }
+            RepositoryError::TokenRefresh(err) => write!(f, "bearer token refresh failed: {}", err),
+            RepositoryError::InvalidResponse { reason } => {
+                write!(f, "invalid repository response: {}", reason)
+            }
         }
     }
 }
 
 impl std::error::Error for RepositoryError {}
 
+/// Compressed siblings of `file_name` to try first, with their decompressor name
+///
+/// `current` files are tiny and often polled, they are never compressed.
+fn compressed_siblings(file_name: &str) -> impl Iterator<Item = (String, &'static str)> + '_ {
+    codecs::METADATA_COMPRESSIONS
+        .iter()
+        .filter(move |_| !metadata::Current::is_filename(file_name))
+        .map(move |&(extension, decompressor_name)| {
+            (format!("{}.{}", file_name, extension), decompressor_name)
+        })
+}
+
+/// Maximum decompressed metadata file size, compressed files are decompressed
+/// before their signature is verified
+const MAX_DECOMPRESSED_METADATA_SIZE: u64 = 256 * 1024 * 1024;
+
+fn decompress(
+    path: &Path,
+    decompressor_name: &str,
+    raw: &[u8],
+) -> Result<Vec<u8>, RepositoryError> {
+    codecs::decode_all(decompressor_name, raw, MAX_DECOMPRESSED_METADATA_SIZE)
+        .map_err(|err| RepositoryError::Decompress { path: path.to_owned(), err })
+}
+
+/// Decode `raw` metadata file content read from `path`
+fn decode<T>(path: &Path, encoding: metadata::Encoding, raw: &[u8]) -> Result<T, RepositoryError>
+where
+    T: for<'de> serde::Deserialize<'de>,
+{
+    match encoding {
+        metadata::Encoding::Json => {
+            serde_json::from_slice(raw).map_err(|err| RepositoryError::json(path, err))
+        }
+        metadata::Encoding::Cbor => {
+            ciborium::from_reader(raw).map_err(|err| RepositoryError::cbor(path, err))
+        }
+    }
+}
+
+/// Check `raw` content of `file_name` is signed by `public_key`, with a
+/// version at least as recent as the last one in `signed_versions`
+fn verify_signature(
+    public_key: &VerifyingKey,
+    signed_versions: &SignedVersions,
+    file_name: &str,
+    raw: &[u8],
+    signature: &[u8],
+) -> Result<(), RepositoryError> {
+    match signature::verify(public_key, file_name, raw, signature) {
+        Some(version) if signed_versions.check(file_name, version) => Ok(()),
+        Some(version) => {
+            Err(RepositoryError::StaleSignature { name: file_name.to_string(), version })
+        }
+        None => Err(RepositoryError::BadSignature { name: file_name.to_string() }),
+    }
+}
+
+/// Delays before reading again a file whose signature doesn't match
+///
+/// Repositories replace a file before its signature, a file read meanwhile
+/// matches once the new signature is there.
+const BAD_SIGNATURE_DELAYS: [Duration; 3] =
+    [Duration::from_millis(50), Duration::from_millis(200), Duration::from_secs(1)];
+
+/// Run `read` again after a short delay while it fails with a bad signature
+async fn retry_bad_signature<F, Fut, T>(mut read: F) -> Result<T, RepositoryError>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, RepositoryError>>,
+{
+    let mut delays = BAD_SIGNATURE_DELAYS.iter();
+    loop {
+        match (read().await, delays.next()) {
+            (Err(RepositoryError::BadSignature { name }), Some(&delay)) => {
+                info!("bad signature of {}, read it again in {:?}", name, delay);
+                tokio::time::sleep(delay).await;
+            }
+            (res, _) => return res,
+        }
+    }
+}
+
+/// Version of a repository metadata file (i.e. HTTP `ETag`, file
+/// modification time), so it is downloaded again only if it changed
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Validator {
+    /// File actually downloaded (i.e. the compressed sibling)
+    file_name: String,
+    tag: String,
+}
+
+impl Validator {
+    pub fn new(file_name: &str, tag: &str) -> Self {
+        Validator { file_name: file_name.to_string(), tag: tag.to_string() }
+    }
+
+    pub fn file_name(&self) -> &str {
+        &self.file_name
+    }
+
+    pub fn tag(&self) -> &str {
+        &self.tag
+    }
+
+    /// Tag of `validator` if it was issued for `file_name`
+    fn tag_for<'a>(validator: Option<&'a Validator>, file_name: &str) -> Option<&'a str> {
+        validator.filter(|validator| validator.file_name == file_name).map(Validator::tag)
+    }
+}
+
+/// Result of a conditional metadata request
+#[derive(Debug)]
+pub enum Conditional<T> {
+    /// The file changed, `validator` is `None` if the repository can't tell
+    /// whether it changes again
+    Modified { value: T, validator: Option<Validator> },
+    /// The file didn't change since the validator was issued
+    NotModified,
+}
+
+impl<T> Conditional<T> {
+    /// Value of a request without validator, which is always modified
+    fn into_value(self) -> T {
+        match self {
+            Conditional::Modified { value, .. } => value,
+            Conditional::NotModified => unreachable!("requests without validator are modified"),
+        }
+    }
+}
+
 pub type RepositoryStream<Item> = Pin<Box<dyn Stream<Item = Result<Item, RepositoryError>>>>;
 
+/// Chunks of `stream`, the content of `range`, with their package offset
+///
+/// Bytes after `range` are dropped.
+fn with_offsets(
+    stream: RepositoryStream<Bytes>,
+    range: Range<u64>,
+) -> RepositoryStream<(u64, Bytes)> {
+    stream
+        .scan(range.start, move |offset, chunk| {
+            let chunk = match chunk {
+                Ok(_) if *offset >= range.end => return future::ready(None),
+                Ok(mut chunk) => {
+                    let chunk_offset = *offset;
+                    chunk.truncate((range.end - chunk_offset).min(chunk.len() as u64) as usize);
+                    *offset += chunk.len() as u64;
+                    Ok((chunk_offset, chunk))
+                }
+                Err(err) => Err(err),
+            };
+            future::ready(Some(chunk))
+        })
+        .boxed_local()
+}
+
 #[async_trait]
-pub trait RemoteRepository {
+pub trait RemoteRepository: Sync {
     async fn current_version(&self) -> Result<metadata::Current, RepositoryError>;
+    /// Current version of the release `channel` (i.e. `beta`)
+    async fn current_version_for(
+        &self,
+        channel: &metadata::CleanName,
+    ) -> Result<metadata::Current, RepositoryError>;
     async fn versions(&self) -> Result<metadata::Versions, RepositoryError>;
     async fn packages(&self) -> Result<metadata::Packages, RepositoryError>;
+    /// Current version of `channel` (`None` is the default channel), unless
+    /// it didn't change since `validator` was issued
+    ///
+    /// The default implementation always downloads it, without validator.
+    async fn current_version_if_modified(
+        &self,
+        channel: Option<&metadata::CleanName>,
+        validator: Option<&Validator>,
+    ) -> Result<Conditional<metadata::Current>, RepositoryError> {
+        let _ = validator;
+        let value = match channel {
+            Some(channel) => self.current_version_for(channel).await?,
+            None => self.current_version().await?,
+        };
+        Ok(Conditional::Modified { value, validator: None })
+    }
+    /// Same as [`versions`](Self::versions), unless it didn't change since
+    /// `validator` was issued
+    async fn versions_if_modified(
+        &self,
+        validator: Option<&Validator>,
+    ) -> Result<Conditional<metadata::Versions>, RepositoryError> {
+        let _ = validator;
+        Ok(Conditional::Modified { value: self.versions().await?, validator: None })
+    }
+    /// Same as [`packages`](Self::packages), unless it didn't change since
+    /// `validator` was issued
+    async fn packages_if_modified(
+        &self,
+        validator: Option<&Validator>,
+    ) -> Result<Conditional<metadata::Packages>, RepositoryError> {
+        let _ = validator;
+        Ok(Conditional::Modified { value: self.packages().await?, validator: None })
+    }
+    /// Package metadata named `package_name`
+    ///
+    /// Implementors should prefer the binary encoded sibling (see
+    /// [`metadata::Encoding`]) and fall back to JSON if it doesn't exist.
     async fn package_metadata(
         &self,
         package_name: metadata::CleanName,
@@ -78,32 +360,159 @@ pub trait RemoteRepository {
         package_name: metadata::CleanName,
         range: Range<u64>,
     ) -> Result<RepositoryStream<Bytes>, RepositoryError>;
+    /// Download several `ranges` of package `package_name`
+    ///
+    /// Chunks come with the package offset of their first byte, in ascending
+    /// order and starting at or before the next byte still required. Bytes
+    /// between ranges might be included.
+    ///
+    /// The default implementation downloads everything from the first range
+    /// start to the last range end with [`package`](Self::package),
+    /// repositories should override it if downloading bytes between ranges is
+    /// expensive.
+    async fn package_ranges(
+        &self,
+        package_name: metadata::CleanName,
+        ranges: Vec<Range<u64>>,
+    ) -> Result<RepositoryStream<(u64, Bytes)>, RepositoryError> {
+        let start = ranges.iter().map(|range| range.start).min().unwrap_or(0);
+        let end = ranges.iter().map(|range| range.end).max().unwrap_or(0);
+        let stream = self.package(package_name, start..end).await?;
+        Ok(with_offsets(stream, start..end))
+    }
+    /// Number of interrupted package downloads retried so far
+    fn download_retries(&self) -> u64 {
+        0
+    }
+    /// Highest signature version of each metadata file verified so far
+    fn signed_versions(&self) -> HashMap<String, u64> {
+        HashMap::new()
+    }
+    /// Reject metadata files signed with an older version than `versions`
+    /// (i.e. the versions verified by a previous update)
+    fn expect_signed_versions(&self, versions: &HashMap<String, u64>) {
+        let _ = versions;
+    }
+    /// Key metadata file signatures are verified with, `None` if they aren't
+    fn public_key(&self) -> Option<VerifyingKey> {
+        None
+    }
 }
 
 pub enum AutoRepository {
     Https(https::HttpsRepository),
     File(file::FileRepository),
+    S3(s3::S3Repository),
+    Bundle(bundle::BundleRepository),
+    Mirror(mirror::MirrorRepository<AutoRepository>),
 }
 
 impl AutoRepository {
-    pub fn new(repository_url: &str, auth: Option<(&str, &str)>) -> Result<Self, RepositoryError> {
+    /// Open the repository at `repository_url`
+    ///
+    /// If `public_key` is provided, every metadata file must be signed by the
+    /// matching signing key.
+    pub fn new(
+        repository_url: &str,
+        auth: Option<(&str, &str)>,
+        public_key: Option<VerifyingKey>,
+    ) -> Result<Self, RepositoryError> {
+        let mut builder = https::HttpsRepositoryBuilder::new();
+        if let Some((username, password)) = auth {
+            builder.set_basic_auth(username, Some(password));
+        }
+        if let Some(public_key) = public_key {
+            builder.set_public_key(public_key);
+        }
+        Self::with_builder(repository_url, &builder)
+    }
+
+    /// Open the repository at `repository_url`, HTTP(S) repositories are
+    /// built by `builder`
+    ///
+    /// `bundle://path` opens the offline bundle at `path` (see
+    /// [`BundleRepository`]). `s3://bucket/prefix` repositories are
+    /// configured by the AWS environment variables (see
+    /// [`S3Config::from_env`]) and share the network settings of `builder`.
+    /// The public key of `builder` is also required by other repositories.
+    pub fn with_builder(
+        repository_url: &str,
+        builder: &HttpsRepositoryBuilder,
+    ) -> Result<Self, RepositoryError> {
         if repository_url.starts_with("https://") || repository_url.starts_with("http://") {
-            let mut remote_url = reqwest::Url::parse(repository_url)
+            let remote_url = reqwest::Url::parse(repository_url)
                 .map_err(|err| RepositoryError::InvalidUrl { reason: err.to_string() })?;
-            if let Some((username, password)) = auth {
-                let _ = remote_url.set_username(username);
-                let _ = remote_url.set_password(Some(password));
+            return Ok(AutoRepository::Https(builder.build(remote_url)?));
+        }
+
+        if repository_url.starts_with("s3://") {
+            let config = s3::S3Config::from_env()?;
+            return Ok(AutoRepository::S3(s3::S3Repository::new(
+                repository_url,
+                &config,
+                builder,
+            )?));
+        }
+
+        if let Some(bundle_path) = repository_url.strip_prefix("bundle://") {
+            let mut repository = bundle::BundleRepository::open(bundle_path.into())?;
+            if let Some(public_key) = builder.public_key() {
+                repository.set_public_key(public_key);
             }
-            return Ok(AutoRepository::Https(https::HttpsRepository::new(remote_url)?));
+            repository.set_signed_versions(builder.signed_versions());
+            return Ok(AutoRepository::Bundle(repository));
         }
 
         if let Some(repo_url) = repository_url.strip_prefix("file://") {
             let dir = repo_url.into();
-            return Ok(AutoRepository::File(file::FileRepository::new(dir)));
+            let mut repository = file::FileRepository::new(dir);
+            if let Some(public_key) = builder.public_key() {
+                repository.set_public_key(public_key);
+            }
+            repository.set_signed_versions(builder.signed_versions());
+            return Ok(AutoRepository::File(repository));
         }
 
         Err(RepositoryError::InvalidUrl { reason: "unsupported scheme".to_string() })
     }
+
+    /// Open the repository served by the `repository_urls` mirrors, in
+    /// priority order (see [`MirrorRepository`])
+    ///
+    /// Each URL is opened like [`with_builder`](Self::with_builder) does.
+    pub fn mirrors(
+        repository_urls: &[&str],
+        builder: &HttpsRepositoryBuilder,
+    ) -> Result<Self, RepositoryError> {
+        match repository_urls {
+            [] => Err(RepositoryError::InvalidUrl { reason: "no mirror url".to_string() }),
+            [repository_url] => Self::with_builder(repository_url, builder),
+            _ => {
+                let mirrors = repository_urls
+                    .iter()
+                    .map(|url| Self::with_builder(url, builder))
+                    .collect::<Result<_, _>>()?;
+                Ok(AutoRepository::Mirror(mirror::MirrorRepository::new(mirrors)))
+            }
+        }
+    }
+
+    /// Change how package downloads are retried, only HTTP(S) and S3
+    /// repositories retry
+    pub fn set_retry_options(
+        &mut self,
+        retry_options: RetryOptions,
+    ) -> Result<(), RepositoryError> {
+        match self {
+            AutoRepository::Https(r) => r.set_retry_options(retry_options),
+            AutoRepository::S3(r) => r.set_retry_options(retry_options),
+            AutoRepository::File(_) | AutoRepository::Bundle(_) => Ok(()),
+            AutoRepository::Mirror(r) => r
+                .mirrors_mut()
+                .iter_mut()
+                .try_for_each(|r| r.set_retry_options(retry_options.clone())),
+        }
+    }
 }
 
 #[async_trait]
@@ -112,18 +521,76 @@ impl RemoteRepository for AutoRepository {
         match self {
             AutoRepository::Https(r) => r.current_version().await,
             AutoRepository::File(r) => r.current_version().await,
+            AutoRepository::S3(r) => r.current_version().await,
+            AutoRepository::Bundle(r) => r.current_version().await,
+            AutoRepository::Mirror(r) => r.current_version().await,
+        }
+    }
+    async fn current_version_for(
+        &self,
+        channel: &metadata::CleanName,
+    ) -> Result<metadata::Current, RepositoryError> {
+        match self {
+            AutoRepository::Https(r) => r.current_version_for(channel).await,
+            AutoRepository::File(r) => r.current_version_for(channel).await,
+            AutoRepository::S3(r) => r.current_version_for(channel).await,
+            AutoRepository::Bundle(r) => r.current_version_for(channel).await,
+            AutoRepository::Mirror(r) => r.current_version_for(channel).await,
         }
     }
     async fn versions(&self) -> Result<metadata::Versions, RepositoryError> {
         match self {
             AutoRepository::Https(r) => r.versions().await,
             AutoRepository::File(r) => r.versions().await,
+            AutoRepository::S3(r) => r.versions().await,
+            AutoRepository::Bundle(r) => r.versions().await,
+            AutoRepository::Mirror(r) => r.versions().await,
         }
     }
     async fn packages(&self) -> Result<metadata::Packages, RepositoryError> {
         match self {
             AutoRepository::Https(r) => r.packages().await,
             AutoRepository::File(r) => r.packages().await,
+            AutoRepository::S3(r) => r.packages().await,
+            AutoRepository::Bundle(r) => r.packages().await,
+            AutoRepository::Mirror(r) => r.packages().await,
+        }
+    }
+    async fn current_version_if_modified(
+        &self,
+        channel: Option<&metadata::CleanName>,
+        validator: Option<&Validator>,
+    ) -> Result<Conditional<metadata::Current>, RepositoryError> {
+        match self {
+            AutoRepository::Https(r) => r.current_version_if_modified(channel, validator).await,
+            AutoRepository::File(r) => r.current_version_if_modified(channel, validator).await,
+            AutoRepository::S3(r) => r.current_version_if_modified(channel, validator).await,
+            AutoRepository::Bundle(r) => r.current_version_if_modified(channel, validator).await,
+            AutoRepository::Mirror(r) => r.current_version_if_modified(channel, validator).await,
+        }
+    }
+    async fn versions_if_modified(
+        &self,
+        validator: Option<&Validator>,
+    ) -> Result<Conditional<metadata::Versions>, RepositoryError> {
+        match self {
+            AutoRepository::Https(r) => r.versions_if_modified(validator).await,
+            AutoRepository::File(r) => r.versions_if_modified(validator).await,
+            AutoRepository::S3(r) => r.versions_if_modified(validator).await,
+            AutoRepository::Bundle(r) => r.versions_if_modified(validator).await,
+            AutoRepository::Mirror(r) => r.versions_if_modified(validator).await,
+        }
+    }
+    async fn packages_if_modified(
+        &self,
+        validator: Option<&Validator>,
+    ) -> Result<Conditional<metadata::Packages>, RepositoryError> {
+        match self {
+            AutoRepository::Https(r) => r.packages_if_modified(validator).await,
+            AutoRepository::File(r) => r.packages_if_modified(validator).await,
+            AutoRepository::S3(r) => r.packages_if_modified(validator).await,
+            AutoRepository::Bundle(r) => r.packages_if_modified(validator).await,
+            AutoRepository::Mirror(r) => r.packages_if_modified(validator).await,
         }
     }
     async fn package_metadata(
@@ -133,6 +600,9 @@ impl RemoteRepository for AutoRepository {
         match self {
             AutoRepository::Https(r) => r.package_metadata(package_name).await,
             AutoRepository::File(r) => r.package_metadata(package_name).await,
+            AutoRepository::S3(r) => r.package_metadata(package_name).await,
+            AutoRepository::Bundle(r) => r.package_metadata(package_name).await,
+            AutoRepository::Mirror(r) => r.package_metadata(package_name).await,
         }
     }
     async fn package(
@@ -143,6 +613,58 @@ impl RemoteRepository for AutoRepository {
         match self {
             AutoRepository::Https(r) => r.package(package_name, range).await,
             AutoRepository::File(r) => r.package(package_name, range).await,
+            AutoRepository::S3(r) => r.package(package_name, range).await,
+            AutoRepository::Bundle(r) => r.package(package_name, range).await,
+            AutoRepository::Mirror(r) => r.package(package_name, range).await,
+        }
+    }
+    async fn package_ranges(
+        &self,
+        package_name: metadata::CleanName,
+        ranges: Vec<Range<u64>>,
+    ) -> Result<RepositoryStream<(u64, Bytes)>, RepositoryError> {
+        match self {
+            AutoRepository::Https(r) => r.package_ranges(package_name, ranges).await,
+            AutoRepository::File(r) => r.package_ranges(package_name, ranges).await,
+            AutoRepository::S3(r) => r.package_ranges(package_name, ranges).await,
+            AutoRepository::Bundle(r) => r.package_ranges(package_name, ranges).await,
+            AutoRepository::Mirror(r) => r.package_ranges(package_name, ranges).await,
+        }
+    }
+    fn download_retries(&self) -> u64 {
+        match self {
+            AutoRepository::Https(r) => r.download_retries(),
+            AutoRepository::File(r) => r.download_retries(),
+            AutoRepository::S3(r) => r.download_retries(),
+            AutoRepository::Bundle(r) => r.download_retries(),
+            AutoRepository::Mirror(r) => r.download_retries(),
+        }
+    }
+    fn signed_versions(&self) -> HashMap<String, u64> {
+        match self {
+            AutoRepository::Https(r) => r.signed_versions(),
+            AutoRepository::File(r) => r.signed_versions(),
+            AutoRepository::S3(r) => r.signed_versions(),
+            AutoRepository::Bundle(r) => r.signed_versions(),
+            AutoRepository::Mirror(r) => r.signed_versions(),
+        }
+    }
+    fn expect_signed_versions(&self, versions: &HashMap<String, u64>) {
+        match self {
+            AutoRepository::Https(r) => r.expect_signed_versions(versions),
+            AutoRepository::File(r) => r.expect_signed_versions(versions),
+            AutoRepository::S3(r) => r.expect_signed_versions(versions),
+            AutoRepository::Bundle(r) => r.expect_signed_versions(versions),
+            AutoRepository::Mirror(r) => r.expect_signed_versions(versions),
+        }
+    }
+    fn public_key(&self) -> Option<VerifyingKey> {
+        match self {
+            AutoRepository::Https(r) => r.public_key(),
+            AutoRepository::File(r) => r.public_key(),
+            AutoRepository::S3(r) => r.public_key(),
+            AutoRepository::Bundle(r) => r.public_key(),
+            AutoRepository::Mirror(r) => r.public_key(),
         }
     }
 }
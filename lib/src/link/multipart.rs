@@ -0,0 +1,143 @@
+//! `multipart/byteranges` HTTP response parsing
+use bytes::{Buf, Bytes, BytesMut};
+
+/// Boundary of a `multipart/byteranges` content type
+pub(super) fn byteranges_boundary(content_type: &str) -> Option<String> {
+    let mut params = content_type.split(';');
+    if !params.next()?.trim().eq_ignore_ascii_case("multipart/byteranges") {
+        return None;
+    }
+    params.find_map(|param| {
+        let (name, value) = param.split_once('=')?;
+        match name.trim().eq_ignore_ascii_case("boundary") {
+            true => Some(value.trim().trim_matches('"').to_string()),
+            false => None,
+        }
+    })
+}
+
+/// First and last byte of a `Content-Range: bytes first-last/size` value
+pub(super) fn parse_content_range(value: &str) -> Option<(u64, u64)> {
+    let range = value.trim().strip_prefix("bytes")?.trim_start();
+    let (range, _size) = range.split_once('/')?;
+    let (first, last) = range.split_once('-')?;
+    let (first, last) = (first.trim().parse().ok()?, last.trim().parse().ok()?);
+    match first <= last {
+        true => Some((first, last)),
+        false => None,
+    }
+}
+
+enum State {
+    Headers,
+    Body { offset: u64, remaining: u64 },
+    Done,
+}
+
+/// Incremental `multipart/byteranges` body parser
+///
+/// Parts are returned as chunks with the package offset of their first byte.
+pub(super) struct ByteRanges {
+    delimiter: Vec<u8>,
+    buf: BytesMut,
+    state: State,
+}
+
+impl ByteRanges {
+    pub fn new(boundary: &str) -> Self {
+        let delimiter = format!("--{}", boundary).into_bytes();
+        ByteRanges { delimiter, buf: BytesMut::new(), state: State::Headers }
+    }
+
+    /// Append the next body chunk
+    pub fn push(&mut self, chunk: &[u8]) {
+        self.buf.extend_from_slice(chunk);
+    }
+
+    /// True once the closing delimiter was parsed
+    pub fn is_done(&self) -> bool {
+        matches!(self.state, State::Done)
+    }
+
+    /// Next part chunk, `Ok(None)` if more body bytes are needed
+    pub fn next_part(&mut self) -> Result<Option<(u64, Bytes)>, String> {
+        loop {
+            match &mut self.state {
+                State::Done => return Ok(None),
+                State::Body { offset, remaining } => {
+                    if self.buf.is_empty() {
+                        return Ok(None);
+                    }
+                    let len = (*remaining).min(self.buf.len() as u64);
+                    let part = (*offset, self.buf.split_to(len as usize).freeze());
+                    *offset += len;
+                    *remaining -= len;
+                    if *remaining == 0 {
+                        self.state = State::Headers;
+                    }
+                    return Ok(Some(part));
+                }
+                State::Headers => {
+                    let skip = self.buf.iter().take_while(|&&b| b == b'\r' || b == b'\n').count();
+                    self.buf.advance(skip);
+                    let close_len = self.delimiter.len() + 2;
+                    if self.buf.len() >= close_len
+                        && self.buf.starts_with(&self.delimiter)
+                        && &self.buf[self.delimiter.len()..close_len] == b"--"
+                    {
+                        self.state = State::Done;
+                        return Ok(None);
+                    }
+                    let end = match self.buf.windows(4).position(|w| w == b"\r\n\r\n") {
+                        Some(end) => end,
+                        None => return Ok(None),
+                    };
+                    let headers = self.buf.split_to(end + 4);
+                    let headers = String::from_utf8_lossy(&headers);
+                    let mut lines = headers.lines();
+                    if lines.next().map(|line| line.trim_end().as_bytes())
+                        != Some(&self.delimiter[..])
+                    {
+                        return Err("missing multipart delimiter".to_string());
+                    }
+                    let (first, last) = lines
+                        .filter_map(|line| line.split_once(':'))
+                        .find(|(name, _)| name.trim().eq_ignore_ascii_case("content-range"))
+                        .and_then(|(_, value)| parse_content_range(value))
+                        .ok_or_else(|| "missing part content range".to_string())?;
+                    self.state = State::Body { offset: first, remaining: last - first + 1 };
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn byteranges() {
+        let content_type = "multipart/byteranges; boundary=\"3d6b6a416f9b5\"";
+        let boundary = byteranges_boundary(content_type).unwrap();
+        let body = b"\r\n--3d6b6a416f9b5\r\nContent-Type: application/octet-stream\r\n\
+            Content-Range: bytes 0-4/100\r\n\r\nhello\r\n--3d6b6a416f9b5\r\n\
+            content-range: bytes 50-55/100\r\n\r\nworld!\r\n--3d6b6a416f9b5--\r\n";
+
+        // feed the body in small chunks to split headers and parts
+        let mut parser = ByteRanges::new(&boundary);
+        let mut parts: Vec<(u64, Vec<u8>)> = Vec::new();
+        for chunk in body.chunks(7) {
+            parser.push(chunk);
+            while let Some((offset, bytes)) = parser.next_part().unwrap() {
+                match parts.last_mut() {
+                    Some((o, b)) if *o + b.len() as u64 == offset => b.extend_from_slice(&bytes),
+                    _ => parts.push((offset, bytes.to_vec())),
+                }
+            }
+        }
+        assert!(parser.is_done());
+        assert_eq!(parts, vec![(0, b"hello".to_vec()), (50, b"world!".to_vec())]);
+        assert_eq!(parse_content_range("bytes 10-5/100"), None);
+    }
+}
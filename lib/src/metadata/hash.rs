@@ -0,0 +1,270 @@
+//! Content hashes (sha1, sha256, blake3)
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+use sha2::Sha256;
+
+use super::Sha1Hash;
+
+/// Hash algorithm used by a package to describe its content
+#[derive(Serialize, Deserialize, Debug, Default, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum HashAlgorithm {
+    #[default]
+    #[serde(rename = "sha1")]
+    Sha1,
+    #[serde(rename = "sha256")]
+    Sha256,
+    #[serde(rename = "blake3")]
+    Blake3,
+}
+
+impl HashAlgorithm {
+    pub fn name(self) -> &'static str {
+        match self {
+            HashAlgorithm::Sha1 => "sha1",
+            HashAlgorithm::Sha256 => "sha256",
+            HashAlgorithm::Blake3 => "blake3",
+        }
+    }
+
+    /// Size in bytes of the digest
+    pub fn digest_len(self) -> usize {
+        match self {
+            HashAlgorithm::Sha1 => 20,
+            HashAlgorithm::Sha256 | HashAlgorithm::Blake3 => 32,
+        }
+    }
+
+    pub fn hasher(self) -> Hasher {
+        match self {
+            HashAlgorithm::Sha1 => Hasher::Sha1(Sha1::new()),
+            HashAlgorithm::Sha256 => Hasher::Sha256(Sha256::new()),
+            HashAlgorithm::Blake3 => Hasher::Blake3(Box::new(blake3::Hasher::new())),
+        }
+    }
+
+    pub fn digest(self, buf: &[u8]) -> Hash {
+        let mut hasher = self.hasher();
+        hasher.update(buf);
+        hasher.finalize_reset()
+    }
+}
+
+impl fmt::Display for HashAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for HashAlgorithm {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "sha1" => Ok(HashAlgorithm::Sha1),
+            "sha256" => Ok(HashAlgorithm::Sha256),
+            "blake3" => Ok(HashAlgorithm::Blake3),
+            _ => Err(format!("unsupported hash algorithm {}", name)),
+        }
+    }
+}
+
+/// Incremental hasher for any supported [`HashAlgorithm`]
+#[derive(Clone)]
+pub enum Hasher {
+    Sha1(Sha1),
+    Sha256(Sha256),
+    Blake3(Box<blake3::Hasher>),
+}
+
+impl Hasher {
+    pub fn algorithm(&self) -> HashAlgorithm {
+        match self {
+            Hasher::Sha1(_) => HashAlgorithm::Sha1,
+            Hasher::Sha256(_) => HashAlgorithm::Sha256,
+            Hasher::Blake3(_) => HashAlgorithm::Blake3,
+        }
+    }
+
+    pub fn update(&mut self, buf: &[u8]) {
+        match self {
+            Hasher::Sha1(hasher) => hasher.update(buf),
+            Hasher::Sha256(hasher) => hasher.update(buf),
+            Hasher::Blake3(hasher) => {
+                hasher.update(buf);
+            }
+        }
+    }
+
+    /// Returns the hash of the data hashed so far and reset the hasher
+    pub fn finalize_reset(&mut self) -> Hash {
+        match self {
+            Hasher::Sha1(hasher) => Hash::Sha1(Sha1Hash::new(hasher.finalize_reset().into())),
+            Hasher::Sha256(hasher) => Hash::Sha256(hasher.finalize_reset().into()),
+            Hasher::Blake3(hasher) => {
+                let hash = hasher.finalize();
+                hasher.reset();
+                Hash::Blake3(hash.into())
+            }
+        }
+    }
+}
+
+/// A content hash
+#[derive(Clone, Eq, PartialEq, Hash)]
+pub enum Hash {
+    Sha1(Sha1Hash),
+    Sha256([u8; 32]),
+    Blake3([u8; 32]),
+}
+
+impl Hash {
+    pub fn algorithm(&self) -> HashAlgorithm {
+        match self {
+            Hash::Sha1(_) => HashAlgorithm::Sha1,
+            Hash::Sha256(_) => HashAlgorithm::Sha256,
+            Hash::Blake3(_) => HashAlgorithm::Blake3,
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            Hash::Sha1(hash) => &hash[..],
+            Hash::Sha256(hash) | Hash::Blake3(hash) => hash,
+        }
+    }
+
+    /// Parse an hex encoded digest of `algorithm`
+    pub fn from_hex(algorithm: HashAlgorithm, hex: &str) -> Result<Self, &'static str> {
+        let mut digest = [0u8; 32];
+        let digest = &mut digest[..algorithm.digest_len()];
+        decode_hex(hex, digest)?;
+        Self::from_digest(algorithm, digest)
+    }
+
+    fn from_digest(algorithm: HashAlgorithm, digest: &[u8]) -> Result<Self, &'static str> {
+        let err = "invalid digest length";
+        Ok(match algorithm {
+            HashAlgorithm::Sha1 => Hash::Sha1(Sha1Hash::new(digest.try_into().map_err(|_| err)?)),
+            HashAlgorithm::Sha256 => Hash::Sha256(digest.try_into().map_err(|_| err)?),
+            HashAlgorithm::Blake3 => Hash::Blake3(digest.try_into().map_err(|_| err)?),
+        })
+    }
+
+    /// Reinterpret this digest as a digest of `algorithm`
+    ///
+    /// Hex encoded digests doesn't carry their algorithm, it is known once the
+    /// package hash algorithm is.
+    pub(crate) fn set_algorithm(&mut self, algorithm: HashAlgorithm) -> Result<(), &'static str> {
+        if self.algorithm() != algorithm {
+            *self = Self::from_digest(algorithm, self.as_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+impl From<Sha1Hash> for Hash {
+    fn from(hash: Sha1Hash) -> Self {
+        Hash::Sha1(hash)
+    }
+}
+
+impl fmt::Debug for Hash {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.algorithm(), self)
+    }
+}
+
+impl fmt::Display for Hash {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for i in self.as_bytes() {
+            write!(f, "{:02x}", i)?;
+        }
+        Ok(())
+    }
+}
+
+impl serde::Serialize for Hash {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        if serializer.is_human_readable() {
+            self.to_string().serialize(serializer)
+        } else {
+            serializer.serialize_bytes(self.as_bytes())
+        }
+    }
+}
+
+/// Deserialize a digest
+///
+/// 20 bytes digests are sha1, 32 bytes digests are sha256 until
+/// [`Hash::set_algorithm`] is called.
+impl<'de> serde::Deserialize<'de> for Hash {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let digest = deserialize_digest(deserializer)?;
+        let algorithm = match digest.len() {
+            20 => HashAlgorithm::Sha1,
+            _ => HashAlgorithm::Sha256,
+        };
+        Self::from_digest(algorithm, &digest)
+            .map_err(|err| serde::de::Error::invalid_length(digest.len(), &err))
+    }
+}
+
+struct DigestVisitor;
+
+impl serde::de::Visitor<'_> for DigestVisitor {
+    type Value = Vec<u8>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("an hex encoded digest or digest bytes")
+    }
+
+    fn visit_bytes<E: serde::de::Error>(self, digest: &[u8]) -> Result<Vec<u8>, E> {
+        Ok(digest.to_vec())
+    }
+
+    fn visit_str<E: serde::de::Error>(self, hex: &str) -> Result<Vec<u8>, E> {
+        let mut digest = vec![0u8; hex.len() / 2];
+        decode_hex(hex, &mut digest).map_err(|err| {
+            serde::de::Error::invalid_value(serde::de::Unexpected::Str(hex), &err)
+        })?;
+        Ok(digest)
+    }
+}
+
+/// Deserialize an hex string (human readable formats) or raw bytes
+pub(super) fn deserialize_digest<'de, D>(deserializer: D) -> Result<Vec<u8>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    deserializer.deserialize_any(DigestVisitor)
+}
+
+pub(super) fn decode_hex(hex: &str, out: &mut [u8]) -> Result<(), &'static str> {
+    fn val(c: u8) -> Result<u8, &'static str> {
+        match c {
+            b'A'..=b'F' => Ok(c - b'A' + 10),
+            b'a'..=b'f' => Ok(c - b'a' + 10),
+            b'0'..=b'9' => Ok(c - b'0'),
+            _ => Err("invalid hex char"),
+        }
+    }
+
+    let hex = hex.as_bytes();
+    if hex.len() != out.len() * 2 {
+        return Err("invalid string length");
+    }
+
+    for (i, byte) in out.iter_mut().enumerate() {
+        *byte = val(hex[2 * i])? << 4 | val(hex[2 * i + 1])?;
+    }
+    Ok(())
+}
@@ -1,7 +1,10 @@
 //! Workspace and Repository metadata definition, serde, ...
 mod dijkstra;
+mod hash;
 pub mod v1;
+pub mod v2;
 
+use std::collections::hash_map::Entry;
 use std::collections::HashMap;
 use std::fmt;
 use std::ops::{Deref, Range};
@@ -12,10 +15,18 @@ use std::str::FromStr;
 use serde::{Deserialize, Serialize};
 use sha1::{Digest, Sha1};
 
+pub use self::hash::{Hash, HashAlgorithm, Hasher};
+
 /// Common version information
 pub trait Version {
     fn revision(&self) -> &CleanName;
     fn description(&self) -> &str;
+    /// Reason this version was withdrawn, `None` if it isn't yanked
+    ///
+    /// Clients never update to a yanked version.
+    fn yanked(&self) -> Option<&str> {
+        None
+    }
 }
 
 /// Common package information
@@ -29,10 +40,68 @@ pub trait Package {
     fn from(&self) -> Option<&CleanName>;
     fn to(&self) -> &CleanName;
     fn size(&self) -> u64;
+    /// Platform this package is built for, `None` if it is platform independent
+    fn platform(&self) -> Option<&CleanName> {
+        None
+    }
+    /// True if this package can be installed on `platform`
+    ///
+    /// Platform independent packages can be installed everywhere, platform
+    /// specific ones only on their platform.
+    fn is_for_platform(&self, platform: Option<&CleanName>) -> bool {
+        self.platform().is_none() || self.platform() == platform
+    }
+    /// True if the repository hints that this package is cached close to clients
+    fn is_cached(&self) -> bool {
+        false
+    }
+    /// Hash of the package metadata file, if the repository publishes it
+    fn metadata_hash(&self) -> Option<&Hash> {
+        None
+    }
     fn package_data_name(&self) -> CleanName;
     fn package_metadata_name(&self) -> CleanName;
 }
 
+/// Cost of installing a package, the update path with the lowest total cost is
+/// used
+pub trait PathCost: Send + Sync {
+    fn cost(&self, package: &dyn Package) -> u64;
+}
+
+/// Package size weighted by cache hints, plus a fixed cost per package
+///
+/// The default weights only count package bytes.
+#[derive(Debug, Clone)]
+pub struct WeightedPathCost {
+    /// Fixed cost of each package (HTTP requests, latency, ...), in bytes
+    pub per_package: u64,
+    /// Cost of a package byte, in percent
+    ///
+    /// Higher than 100 to take the apply time into account (i.e. 150 if
+    /// applying a byte takes half the time needed to download it).
+    pub byte_percent: u64,
+    /// Cost of a cached package byte, in percent
+    pub cached_byte_percent: u64,
+}
+
+impl Default for WeightedPathCost {
+    fn default() -> Self {
+        Self { per_package: 0, byte_percent: 100, cached_byte_percent: 100 }
+    }
+}
+
+impl PathCost for WeightedPathCost {
+    fn cost(&self, package: &dyn Package) -> u64 {
+        let percent = match package.is_cached() {
+            true => self.cached_byte_percent,
+            false => self.byte_percent,
+        };
+        let bytes = (package.size() as u128 * percent as u128 / 100) as u64;
+        self.per_package.saturating_add(bytes)
+    }
+}
+
 /// Operation type (add, patch, check, ...)
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum OperationKind {
@@ -42,6 +111,9 @@ pub enum OperationKind {
     Rm,
     MkDir,
     RmDir,
+    Symlink,
+    Chunked,
+    Copy,
 }
 
 /// Common operation info
@@ -55,6 +127,8 @@ pub trait Operation {
     fn check_size(&self) -> u64;
     fn data_size(&self) -> u64;
     fn final_size(&self) -> u64;
+    /// Expected hash of the downloaded data
+    fn data_hash(&self) -> Option<&Hash>;
 
     fn set_data_offset(&mut self, offset: u64);
 }
@@ -93,8 +167,12 @@ pub(crate) mod maybe_cleanname {
     }
 }
 
+/// (De)serialize an `u64` as a string in human readable formats (JSON) and as
+/// an integer otherwise (CBOR)
 pub(crate) mod u64_str {
-    use serde::{self, Deserialize, Deserializer, Serializer};
+    use std::fmt;
+
+    use serde::{self, de, Deserializer, Serializer};
 
     pub fn is_zero(value: &u64) -> bool {
         *value == 0
@@ -104,25 +182,69 @@ pub(crate) mod u64_str {
     where
         S: Serializer,
     {
-        serializer.serialize_str(&value.to_string())
+        if serializer.is_human_readable() {
+            serializer.serialize_str(&value.to_string())
+        } else {
+            serializer.serialize_u64(*value)
+        }
     }
 
+    struct U64Visitor;
+
+    impl de::Visitor<'_> for U64Visitor {
+        type Value = u64;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("an integer or a string containing an integer")
+        }
+
+        fn visit_u64<E: de::Error>(self, value: u64) -> Result<u64, E> {
+            Ok(value)
+        }
+
+        fn visit_str<E: de::Error>(self, s: &str) -> Result<u64, E> {
+            s.parse::<u64>().map_err(|err| {
+                de::Error::invalid_value(de::Unexpected::Str(s), &err.to_string().as_str())
+            })
+        }
+    }
+
+    /// Both representations are accepted, as serde internally tagged enums
+    /// always report human readable formats to their content.
     pub fn deserialize<'de, D>(deserializer: D) -> Result<u64, D::Error>
     where
         D: Deserializer<'de>,
     {
-        let s = String::deserialize(deserializer)?;
-        (s).parse::<u64>().map_err(|err| {
-            serde::de::Error::invalid_value(
-                serde::de::Unexpected::Str(&s),
-                &err.to_string().as_str(),
-            )
-        })
+        deserializer.deserialize_any(U64Visitor)
+    }
+}
+
+/// (De)serialize an optional `u64` like [`u64_str`]
+///
+/// Must be used with `#[serde(default, skip_serializing_if = "Option::is_none")]`.
+pub(crate) mod maybe_u64_str {
+    use serde::{Deserializer, Serializer};
+
+    pub fn serialize<S>(value: &Option<u64>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match value {
+            Some(value) => super::u64_str::serialize(value, serializer),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<u64>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        super::u64_str::deserialize(deserializer).map(Some)
     }
 }
 
 /// A sha1 hash
-#[derive(Clone, Eq, PartialEq)]
+#[derive(Clone, Eq, PartialEq, Hash)]
 pub struct Sha1Hash {
     hash: [u8; 20],
 }
@@ -155,24 +277,8 @@ impl FromStr for Sha1Hash {
     type Err = &'static str;
 
     fn from_str(hex: &str) -> Result<Self, Self::Err> {
-        fn val(c: u8) -> Result<u8, &'static str> {
-            match c {
-                b'A'..=b'F' => Ok(c - b'A' + 10),
-                b'a'..=b'f' => Ok(c - b'a' + 10),
-                b'0'..=b'9' => Ok(c - b'0'),
-                _ => Err("invalid hex char"),
-            }
-        }
-
-        let hex = hex.as_bytes();
-        if hex.len() != 20 * 2 {
-            return Err("invalid string length");
-        }
-
         let mut hash = [0u8; 20];
-        for (i, byte) in hash.iter_mut().enumerate() {
-            *byte = val(hex[2 * i])? << 4 | val(hex[2 * i + 1])?;
-        }
+        hash::decode_hex(hex, &mut hash)?;
         Ok(Self { hash })
     }
 }
@@ -197,7 +303,11 @@ impl serde::Serialize for Sha1Hash {
     where
         S: serde::Serializer,
     {
-        self.to_string().serialize(serializer)
+        if serializer.is_human_readable() {
+            self.to_string().serialize(serializer)
+        } else {
+            serializer.serialize_bytes(&self.hash)
+        }
     }
 }
 
@@ -206,9 +316,11 @@ impl<'de> serde::Deserialize<'de> for Sha1Hash {
     where
         D: serde::Deserializer<'de>,
     {
-        let hex = String::deserialize(deserializer)?;
-        Self::from_str(&hex)
-            .map_err(|err| serde::de::Error::invalid_value(serde::de::Unexpected::Str(&hex), &err))
+        let digest = hash::deserialize_digest(deserializer)?;
+        let hash = digest.as_slice().try_into().map_err(|_| {
+            serde::de::Error::invalid_length(digest.len(), &"a 20 bytes sha1 digest")
+        })?;
+        Ok(Self { hash })
     }
 }
 
@@ -279,6 +391,26 @@ impl<'de> serde::Deserialize<'de> for CleanPath {
     }
 }
 
+/// Returns true if the symbolic link `target`, relative to the directory
+/// containing `path`, stays inside the workspace
+pub fn is_contained_symlink(path: &CleanPath, target: &str) -> bool {
+    if target.is_empty() || target.starts_with('/') || target.contains(['\\', ':']) {
+        return false;
+    }
+    let mut depth = path.split('/').filter(|c| !c.is_empty()).count().saturating_sub(1);
+    for component in target.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => match depth.checked_sub(1) {
+                Some(parent) => depth = parent,
+                None => return false,
+            },
+            _ => depth += 1,
+        }
+    }
+    true
+}
+
 /// A clean name (i.e  `[A-Za-Z0-9_.-]+`)
 #[derive(Debug, Clone, Serialize, Eq, PartialEq, Hash)]
 #[serde(transparent)]
@@ -343,6 +475,41 @@ impl<'de> serde::Deserialize<'de> for CleanName {
     }
 }
 
+/// Metadata file encoding
+///
+/// The encoding is selected by the file extension: `$name` is JSON and
+/// `$name.cbor` is CBOR. JSON is always available, CBOR is optional.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub enum Encoding {
+    #[default]
+    Json,
+    /// Compact binary encoding (numbers are integers, hashes are bytes)
+    Cbor,
+}
+
+impl Encoding {
+    /// Name of the file holding `name` metadata with this encoding
+    pub fn filename(self, name: &str) -> String {
+        match self {
+            Encoding::Json => name.to_string(),
+            Encoding::Cbor => format!("{}.cbor", name),
+        }
+    }
+
+    pub fn encode<T: Serialize>(self, value: &T) -> std::io::Result<Vec<u8>> {
+        match self {
+            Encoding::Json => Ok(serde_json::to_vec_pretty(value)?),
+            Encoding::Cbor => {
+                let mut raw = Vec::new();
+                ciborium::into_writer(value, &mut raw).map_err(|err| {
+                    std::io::Error::new(std::io::ErrorKind::InvalidData, err.to_string())
+                })?;
+                Ok(raw)
+            }
+        }
+    }
+}
+
 /// Repository `current` JSON file definition
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(tag = "version")]
@@ -356,6 +523,22 @@ impl Current {
         "current"
     }
 
+    /// Name of the `channel` current version file (i.e. `current.beta`)
+    ///
+    /// The default channel (`None`) is `current`.
+    pub fn channel_filename(channel: Option<&CleanName>) -> String {
+        match channel {
+            Some(channel) => format!("{}.{}", Self::filename(), channel),
+            None => Self::filename().to_string(),
+        }
+    }
+
+    /// Returns true if `filename` is the current version file of a channel
+    pub fn is_filename(filename: &str) -> bool {
+        filename == Self::filename()
+            || filename.strip_prefix(Self::filename()).is_some_and(|rest| rest.starts_with('.'))
+    }
+
     pub fn version(&self) -> &CleanName {
         match self {
             Current::V1 { current } => &current.revision,
@@ -418,46 +601,103 @@ impl Packages {
 #[serde(tag = "version")]
 pub enum PackageMetadata {
     #[serde(rename = "1")]
-    V1 { package: v1::Package, operations: Vec<v1::Operation> },
+    V1 {
+        package: v1::Package,
+        #[serde(with = "v1::operations")]
+        operations: Vec<v2::Operation>,
+    },
+    #[serde(rename = "2")]
+    V2(v2::PackageMetadata),
 }
 
 impl Package for PackageMetadata {
     fn from(&self) -> Option<&CleanName> {
-        match self {
-            PackageMetadata::V1 { package, .. } => package.from(),
-        }
+        self.package().from()
     }
     fn to(&self) -> &CleanName {
-        match self {
-            PackageMetadata::V1 { package, .. } => package.to(),
-        }
+        self.package().to()
     }
     fn size(&self) -> u64 {
+        self.package().size()
+    }
+    fn platform(&self) -> Option<&CleanName> {
+        self.package().platform()
+    }
+    fn package_data_name(&self) -> CleanName {
+        self.package().package_data_name()
+    }
+    fn package_metadata_name(&self) -> CleanName {
+        self.package().package_metadata_name()
+    }
+}
+
+impl PackageMetadata {
+    /// Package metadata in the oldest format supporting `hash` and `operations`
+    pub fn new(package: v1::Package, hash: HashAlgorithm, operations: Vec<v2::Operation>) -> Self {
+        match hash {
+            HashAlgorithm::Sha1 if operations.iter().all(v2::Operation::is_v1_compatible) => {
+                PackageMetadata::V1 { package, operations }
+            }
+            hash => PackageMetadata::V2(v2::PackageMetadata { package, hash, operations }),
+        }
+    }
+
+    pub fn package(&self) -> &v1::Package {
         match self {
-            PackageMetadata::V1 { package, .. } => package.size(),
+            PackageMetadata::V1 { package, .. }
+            | PackageMetadata::V2(v2::PackageMetadata { package, .. }) => package,
         }
     }
-    fn package_data_name(&self) -> CleanName {
+
+    pub fn hash_algorithm(&self) -> HashAlgorithm {
         match self {
-            PackageMetadata::V1 { package, .. } => package.package_data_name(),
+            PackageMetadata::V1 { .. } => HashAlgorithm::Sha1,
+            PackageMetadata::V2(v2::PackageMetadata { hash, .. }) => *hash,
         }
     }
-    fn package_metadata_name(&self) -> CleanName {
+
+    pub(crate) fn iter(&self) -> slice::Iter<v2::Operation> {
         match self {
-            PackageMetadata::V1 { package, .. } => package.package_metadata_name(),
+            PackageMetadata::V1 { operations, .. }
+            | PackageMetadata::V2(v2::PackageMetadata { operations, .. }) => operations.iter(),
         }
     }
 }
 
-impl PackageMetadata {
-    pub(crate) fn iter(&self) -> slice::Iter<v1::Operation> {
-        match self {
-            PackageMetadata::V1 { operations, .. } => operations.iter(),
+/// True if `package` is cheaper than `best`, platform specific packages win
+/// ties
+fn is_cheaper<P: Package>((cost, package): (u64, &P), (best_cost, best): (u64, &P)) -> bool {
+    (cost, package.platform().is_none()) < (best_cost, best.platform().is_none())
+}
+
+/// Cheapest package installable on `platform`, the one [`shortest_path`]
+/// selects if they all go through the same step
+pub fn cheapest_package<'a, P, I>(
+    packages: I,
+    platform: Option<&CleanName>,
+    path_cost: &dyn PathCost,
+) -> Option<&'a P>
+where
+    P: Package + 'a,
+    I: IntoIterator<Item = &'a P>,
+{
+    let mut cheapest: Option<(u64, &'a P)> = None;
+    for package in packages.into_iter().filter(|p| p.is_for_platform(platform)) {
+        let cost = path_cost.cost(package);
+        match cheapest {
+            Some(best) if !is_cheaper((cost, package), best) => {}
+            _ => cheapest = Some((cost, package)),
         }
     }
+    cheapest.map(|(_, package)| package)
 }
 
-/// Find the shortest path accross packages
+/// Find the shortest path accross packages installable on `platform`
+///
+/// If several packages exist between two versions, the cheapest one is used
+/// and platform specific packages win ties.
+///
+/// The cost of each package is given by `path_cost`.
 ///
 /// Returns [`Some(Vec<P>)`] if a path between `start` and `goal` exists
 /// Otherwise returns [`None`]
@@ -465,6 +705,8 @@ pub fn shortest_path<'a: 'b, 'b, P>(
     start: Option<&'b CleanName>,
     goal: &'b CleanName,
     packages: &'a [P],
+    platform: Option<&CleanName>,
+    path_cost: &dyn PathCost,
 ) -> Option<Vec<&'a P>>
 where
     P: Package,
@@ -490,10 +732,29 @@ where
     if empty_idx != start_idx {
         nodes[start_idx].push(dijkstra::Edge { node: empty_idx, cost: 0 });
     }
-    for package in packages {
+    // keep the cheapest package of each step, platform specific packages win
+    // ties
+    let mut steps: HashMap<(usize, usize), (u64, &'a P)> = HashMap::new();
+    let mut step_order: Vec<(usize, usize)> = Vec::new();
+    for package in packages.iter().filter(|p| p.is_for_platform(platform)) {
         let from = get_node_idx(&mut nodes, &mut idx_to_name, package.from());
         let to = get_node_idx(&mut nodes, &mut idx_to_name, Some(package.to()));
-        nodes[from].push(dijkstra::Edge { node: to, cost: package.size() });
+        let cost = path_cost.cost(package);
+        match steps.entry((from, to)) {
+            Entry::Vacant(entry) => {
+                step_order.push((from, to));
+                entry.insert((cost, package));
+            }
+            Entry::Occupied(mut entry) => {
+                if is_cheaper((cost, package), *entry.get()) {
+                    entry.insert((cost, package));
+                }
+            }
+        }
+    }
+    for (from, to) in step_order {
+        let (cost, _) = steps[&(from, to)];
+        nodes[from].push(dijkstra::Edge { node: to, cost });
     }
 
     let path = dijkstra::shortest_path(&nodes, start_idx, goal_idx);
@@ -501,18 +762,14 @@ where
         Some(path) => {
             let mut path = path.as_slice();
             let mut ret = Vec::new();
-            let mut from = start;
+            let mut from = start_idx;
             if empty_idx != start_idx && path[0] == empty_idx {
-                from = None;
+                from = empty_idx;
                 path = &path[1..];
             }
-            for &p in path {
-                let to = idx_to_name[p];
-                let p = packages
-                    .iter()
-                    .find(|&package| package.from() == from && Some(package.to()) == to);
-                if let Some(p) = p {
-                    ret.push(p);
+            for &to in path {
+                if let Some(&(_, package)) = steps.get(&(from, to)) {
+                    ret.push(package);
                 }
                 from = to;
             }
@@ -523,41 +780,62 @@ where
     }
 }
 
-impl Operation for v1::Operation {
+impl Operation for v2::Operation {
     fn kind(&self) -> OperationKind {
         match self {
-            v1::Operation::Add(_) => OperationKind::Add,
-            v1::Operation::Patch(_) => OperationKind::Patch,
-            v1::Operation::Check(_) => OperationKind::Check,
-            v1::Operation::Rm(_) => OperationKind::Rm,
-            v1::Operation::MkDir { .. } => OperationKind::MkDir,
-            v1::Operation::RmDir { .. } => OperationKind::RmDir,
+            v2::Operation::Add(_) => OperationKind::Add,
+            v2::Operation::Patch(_) => OperationKind::Patch,
+            v2::Operation::Check(_) => OperationKind::Check,
+            v2::Operation::Rm(_) => OperationKind::Rm,
+            v2::Operation::MkDir { .. } => OperationKind::MkDir,
+            v2::Operation::RmDir { .. } => OperationKind::RmDir,
+            v2::Operation::Symlink { .. } => OperationKind::Symlink,
+            v2::Operation::Chunked(_) => OperationKind::Chunked,
+            v2::Operation::Copy(_) => OperationKind::Copy,
         }
     }
     fn check_size(&self) -> u64 {
-        match self {
-            &v1::Operation::Check(v1::Check { local_size, .. }) => local_size,
+        match *self {
+            v2::Operation::Check(v2::Check { local_size, .. }) => local_size,
+            // copied bytes are read and checked like a check operation
+            v2::Operation::Copy(v2::Copy { final_size, .. }) => final_size,
             _ => 0,
         }
     }
     fn data_size(&self) -> u64 {
         match *self {
-            v1::Operation::Add(v1::Add { data_size, .. }) => data_size,
-            v1::Operation::Patch(v1::Patch { data_size, .. }) => data_size,
+            v2::Operation::Add(v2::Add { data_size, .. }) => data_size,
+            v2::Operation::Patch(v2::Patch { data_size, .. }) => data_size,
+            v2::Operation::Chunked(v2::Chunked { data_size, .. }) => data_size,
             _ => 0,
         }
     }
     fn final_size(&self) -> u64 {
         match *self {
-            v1::Operation::Add(v1::Add { final_size, .. }) => final_size,
-            v1::Operation::Patch(v1::Patch { final_size, .. }) => final_size,
+            v2::Operation::Add(v2::Add { final_size, .. }) => final_size,
+            v2::Operation::Patch(v2::Patch { final_size, .. }) => final_size,
+            v2::Operation::Chunked(v2::Chunked { final_size, .. }) => final_size,
             _ => 0,
         }
     }
+    fn data_hash(&self) -> Option<&Hash> {
+        match self {
+            v2::Operation::Add(v2::Add { data_hash, .. })
+            | v2::Operation::Patch(v2::Patch { data_hash, .. })
+            | v2::Operation::Chunked(v2::Chunked { data_hash, .. }) => Some(data_hash),
+            _ => None,
+        }
+    }
     fn range(&self) -> Option<Range<u64>> {
         match self {
-            &v1::Operation::Add(v1::Add { data_offset, data_size, .. })
-            | &v1::Operation::Patch(v1::Patch { data_offset, data_size, .. }) => {
+            &v2::Operation::Add(v2::Add { data_offset, data_size, .. })
+            | &v2::Operation::Patch(v2::Patch { data_offset, data_size, .. }) => {
+                Some(Range { start: data_offset, end: data_offset + data_size })
+            }
+            // every chunk might be available locally
+            &v2::Operation::Chunked(v2::Chunked { data_offset, data_size, .. })
+                if data_size > 0 =>
+            {
                 Some(Range { start: data_offset, end: data_offset + data_size })
             }
             _ => None,
@@ -566,41 +844,52 @@ impl Operation for v1::Operation {
 
     fn set_data_offset(&mut self, offset: u64) {
         match self {
-            v1::Operation::Add(v1::Add { data_offset, .. })
-            | v1::Operation::Patch(v1::Patch { data_offset, .. }) => *data_offset = offset,
+            v2::Operation::Add(v2::Add { data_offset, .. })
+            | v2::Operation::Patch(v2::Patch { data_offset, .. })
+            | v2::Operation::Chunked(v2::Chunked { data_offset, .. }) => *data_offset = offset,
             _ => {}
         }
     }
 
     fn path(&self) -> &CleanPath {
         match self {
-            v1::Operation::Add(v1::Add { common, .. })
-            | v1::Operation::Patch(v1::Patch { common, .. })
-            | v1::Operation::Check(v1::Check { common, .. }) => &common.path,
-            v1::Operation::MkDir { path, .. }
-            | v1::Operation::RmDir { path, .. }
-            | v1::Operation::Rm(v1::Rm { path, .. }) => path,
+            v2::Operation::Add(v2::Add { common, .. })
+            | v2::Operation::Patch(v2::Patch { common, .. })
+            | v2::Operation::Check(v2::Check { common, .. })
+            | v2::Operation::Chunked(v2::Chunked { common, .. })
+            | v2::Operation::Copy(v2::Copy { common, .. }) => &common.path,
+            v2::Operation::MkDir { path, .. }
+            | v2::Operation::RmDir { path, .. }
+            | v2::Operation::Symlink { path, .. }
+            | v2::Operation::Rm(v2::Rm { path, .. }) => path,
         }
     }
 
     fn slice(&self) -> Option<&CleanPath> {
         match self {
-            v1::Operation::Add(v1::Add { common, .. })
-            | v1::Operation::Patch(v1::Patch { common, .. })
-            | v1::Operation::Check(v1::Check { common, .. }) => common.slice.as_ref(),
-            v1::Operation::Rm(v1::Rm { slice, .. }) => slice.as_ref(),
-            v1::Operation::MkDir { .. } | v1::Operation::RmDir { .. } => None,
+            v2::Operation::Add(v2::Add { common, .. })
+            | v2::Operation::Patch(v2::Patch { common, .. })
+            | v2::Operation::Check(v2::Check { common, .. })
+            | v2::Operation::Chunked(v2::Chunked { common, .. })
+            | v2::Operation::Copy(v2::Copy { common, .. }) => common.slice.as_ref(),
+            v2::Operation::Rm(v2::Rm { slice, .. }) => slice.as_ref(),
+            v2::Operation::MkDir { .. }
+            | v2::Operation::RmDir { .. }
+            | v2::Operation::Symlink { .. } => None,
         }
     }
 
     fn slice_handler(&self) -> Option<&CleanName> {
         match self {
-            v1::Operation::Add(v1::Add { common, .. })
-            | v1::Operation::Patch(v1::Patch { common, .. })
-            | v1::Operation::Check(v1::Check { common, .. }) => common.slice_handler.as_ref(),
-            v1::Operation::Rm(_) | v1::Operation::MkDir { .. } | v1::Operation::RmDir { .. } => {
-                None
-            }
+            v2::Operation::Add(v2::Add { common, .. })
+            | v2::Operation::Patch(v2::Patch { common, .. })
+            | v2::Operation::Check(v2::Check { common, .. })
+            | v2::Operation::Chunked(v2::Chunked { common, .. })
+            | v2::Operation::Copy(v2::Copy { common, .. }) => common.slice_handler.as_ref(),
+            v2::Operation::Rm(_)
+            | v2::Operation::MkDir { .. }
+            | v2::Operation::RmDir { .. }
+            | v2::Operation::Symlink { .. } => None,
         }
     }
 }
@@ -616,13 +905,27 @@ pub enum WorkspaceState {
 #[serde(tag = "version")]
 pub enum WorkspaceChecks {
     #[serde(rename = "1")]
-    V1 { operations: Vec<v1::Operation> },
+    V1 {
+        #[serde(with = "v1::operations")]
+        operations: Vec<v2::Operation>,
+    },
+    #[serde(rename = "2")]
+    V2(v2::Checks),
 }
 
 impl WorkspaceChecks {
-    pub(crate) fn iter(&self) -> slice::Iter<v1::Operation> {
+    /// Workspace checks in the oldest format supporting `hash`
+    pub fn new(hash: HashAlgorithm, operations: Vec<v2::Operation>) -> Self {
+        match hash {
+            HashAlgorithm::Sha1 => WorkspaceChecks::V1 { operations },
+            hash => WorkspaceChecks::V2(v2::Checks { hash, operations }),
+        }
+    }
+
+    pub(crate) fn iter(&self) -> slice::Iter<v2::Operation> {
         match self {
-            WorkspaceChecks::V1 { operations, .. } => operations.iter(),
+            WorkspaceChecks::V1 { operations, .. }
+            | WorkspaceChecks::V2(v2::Checks { operations, .. }) => operations.iter(),
         }
     }
 }
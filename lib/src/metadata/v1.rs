@@ -3,13 +3,16 @@ use std::fmt;
 
 use serde::{Deserialize, Serialize};
 
-use super::{maybe_cleanname, u64_str, CleanName, CleanPath, Sha1Hash};
+use super::{maybe_cleanname, maybe_u64_str, u64_str, v2, CleanName, CleanPath, Hash, Sha1Hash};
 use crate::workspace::UpdatePosition;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Version {
     pub revision: CleanName,
     pub description: String,
+    /// Reason this version was withdrawn, `None` if it isn't yanked
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub yanked: Option<String>,
 }
 
 impl super::Version for Version {
@@ -20,6 +23,10 @@ impl super::Version for Version {
     fn description(&self) -> &str {
         &self.description
     }
+
+    fn yanked(&self) -> Option<&str> {
+        self.yanked.as_deref()
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
@@ -29,13 +36,43 @@ pub struct Package {
     pub to: CleanName,
     #[serde(with = "u64_str")]
     pub size: u64,
+    /// Platform this package is built for (i.e. `win64`, `linux`)
+    ///
+    /// Packages without platform are installable on every platform.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub platform: Option<CleanName>,
+    /// Repository hints used to select the update path
+    #[serde(default, skip_serializing_if = "PackageHints::is_empty")]
+    pub hints: PackageHints,
+    /// SHA-256 hash of the package metadata file, published by `packages` so
+    /// clients notice packages built again
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata_hash: Option<Hash>,
+}
+
+/// Repository hints about a package
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct PackageHints {
+    /// The package data is cached close to clients (i.e. by a CDN)
+    #[serde(default)]
+    pub cached: bool,
+}
+
+impl PackageHints {
+    fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
 }
 
 impl Package {
     fn package_name(&self, suffix: &'static str) -> CleanName {
+        let platform = match &self.platform {
+            Some(platform) => format!(".{}", platform),
+            None => String::new(),
+        };
         CleanName::new(match &self.from {
-            Some(from) => format!("patch{}_{}{}", from, self.to, suffix),
-            None => format!("complete_{}{}", self.to, suffix),
+            Some(from) => format!("patch{}_{}{}{}", from, self.to, platform, suffix),
+            None => format!("complete_{}{}{}", self.to, platform, suffix),
         })
         .expect("name to be clean")
     }
@@ -51,6 +88,15 @@ impl super::Package for Package {
     fn size(&self) -> u64 {
         self.size
     }
+    fn platform(&self) -> Option<&CleanName> {
+        self.platform.as_ref()
+    }
+    fn is_cached(&self) -> bool {
+        self.hints.cached
+    }
+    fn metadata_hash(&self) -> Option<&Hash> {
+        self.metadata_hash.as_ref()
+    }
     fn package_data_name(&self) -> CleanName {
         self.package_name("")
     }
@@ -68,6 +114,17 @@ pub struct Common {
     pub slice: Option<CleanPath>,
     #[serde(default)]
     pub exe: bool,
+    /// Unix permission bits (i.e. `0o755`)
+    ///
+    /// `exe` is still set for clients that doesn't restore permission bits.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mode: Option<u32>,
+    /// Modification time in seconds since the Unix epoch
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(with = "maybe_u64_str")]
+    pub mtime: Option<u64>,
     /// Name of the slice handler that will be available for other
     /// operations with a slice and the same path
     #[serde(rename = "sliceHandler")]
@@ -185,23 +242,97 @@ pub enum Operation {
     MkDir { path: CleanPath },
     #[serde(rename = "rmdir")]
     RmDir { path: CleanPath },
+    /// Create a symbolic link at `path` pointing to `target`
+    ///
+    /// `target` is relative to the directory containing `path`.
+    #[serde(rename = "symlink")]
+    Symlink { path: CleanPath, target: String },
 }
 
-impl Operation {
-    pub fn as_check_operation(&self) -> Option<Operation> {
-        match self {
-            Operation::Add(Add { common, final_offset, final_size, final_sha1, .. })
-            | Operation::Patch(Patch { common, final_offset, final_size, final_sha1, .. }) => {
-                Some(Operation::Check(Check {
-                    common: common.clone(),
-                    local_offset: *final_offset,
-                    local_size: *final_size,
-                    local_sha1: final_sha1.clone(),
-                }))
+impl TryFrom<&v2::Operation> for Operation {
+    type Error = String;
+
+    fn try_from(operation: &v2::Operation) -> Result<Self, Self::Error> {
+        fn sha1(hash: &Hash) -> Result<Sha1Hash, String> {
+            match hash {
+                Hash::Sha1(hash) => Ok(hash.clone()),
+                hash => Err(format!("{} hash is not supported by v1 metadata", hash.algorithm())),
             }
-            Operation::Check { .. } | Operation::MkDir { .. } => Some(self.clone()),
-            Operation::RmDir { .. } | Operation::Rm { .. } => None,
         }
+
+        Ok(match operation {
+            v2::Operation::Add(op) => Operation::Add(Add {
+                common: op.common.clone(),
+                data_offset: op.data_offset,
+                data_size: op.data_size,
+                data_sha1: sha1(&op.data_hash)?,
+                data_compression: op.data_compression.clone(),
+                final_offset: op.final_offset,
+                final_size: op.final_size,
+                final_sha1: sha1(&op.final_hash)?,
+            }),
+            v2::Operation::Patch(op) => Operation::Patch(Patch {
+                common: op.common.clone(),
+                data_offset: op.data_offset,
+                data_size: op.data_size,
+                data_sha1: sha1(&op.data_hash)?,
+                data_compression: op.data_compression.clone(),
+                patch_type: op.patch_type.clone(),
+                local_offset: op.local_offset,
+                local_size: op.local_size,
+                local_sha1: sha1(&op.local_hash)?,
+                final_offset: op.final_offset,
+                final_size: op.final_size,
+                final_sha1: sha1(&op.final_hash)?,
+            }),
+            v2::Operation::Check(op) => Operation::Check(Check {
+                common: op.common.clone(),
+                local_offset: op.local_offset,
+                local_size: op.local_size,
+                local_sha1: sha1(&op.local_hash)?,
+            }),
+            v2::Operation::Rm(op) => Operation::Rm(op.clone()),
+            v2::Operation::MkDir { path } => Operation::MkDir { path: path.clone() },
+            v2::Operation::RmDir { path } => Operation::RmDir { path: path.clone() },
+            v2::Operation::Symlink { path, target } => {
+                Operation::Symlink { path: path.clone(), target: target.clone() }
+            }
+            v2::Operation::Chunked(_) => {
+                return Err("chunked operations are not supported by v1 metadata".to_string())
+            }
+            v2::Operation::Copy(_) => {
+                return Err("copy operations are not supported by v1 metadata".to_string())
+            }
+        })
+    }
+}
+
+/// (De)serialize v2 operations as v1 operations
+///
+/// Serialization fails if an operation hash isn't a sha1 hash.
+pub(crate) mod operations {
+    use serde::{self, Deserialize, Deserializer, Serialize, Serializer};
+
+    use super::{v2, Operation};
+
+    pub fn serialize<S>(operations: &[v2::Operation], serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let operations = operations
+            .iter()
+            .map(Operation::try_from)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(serde::ser::Error::custom)?;
+        operations.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Vec<v2::Operation>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let operations = Vec::<Operation>::deserialize(deserializer)?;
+        Ok(operations.into_iter().map(v2::Operation::from).collect())
     }
 }
 
@@ -260,6 +391,9 @@ pub struct StateUpdating {
     #[serde(with = "crate::metadata::maybe_cleanname")]
     pub from: Option<CleanName>,
     pub to: CleanName,
+    /// Platform of the package being applied
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub platform: Option<CleanName>,
     pub(crate) available: UpdatePosition,
     pub(crate) applied: UpdatePosition,
     #[serde(default)]
@@ -277,6 +411,7 @@ impl StateUpdating {
         StateUpdating {
             from,
             to,
+            platform: None,
             available: UpdatePosition::default(),
             applied: UpdatePosition::default(),
             failures,
@@ -288,6 +423,7 @@ impl StateUpdating {
     pub(crate) fn update_with(&mut self, other: Self) {
         self.from = other.from;
         self.to = other.to;
+        self.platform = other.platform;
         self.available = other.available;
         self.applied = other.applied;
         self.check_only = other.check_only;
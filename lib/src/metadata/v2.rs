@@ -0,0 +1,358 @@
+//! Version 2 metadata definition
+//!
+//! Same operations as version 1, but content hashes are computed with the
+//! package hash algorithm (sha1, sha256 or blake3) instead of sha1 only.
+//!
+//! Chunked and copy operations are only available in version 2.
+use serde::{Deserialize, Deserializer, Serialize};
+
+pub use super::v1::{Common, Package, Rm};
+use super::{u64_str, v1, CleanName, CleanPath, Hash, HashAlgorithm};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Add {
+    #[serde(flatten)]
+    pub common: Common,
+
+    #[serde(rename = "dataOffset")]
+    #[serde(with = "u64_str")]
+    pub data_offset: u64,
+    #[serde(rename = "dataSize")]
+    #[serde(with = "u64_str")]
+    pub data_size: u64,
+    #[serde(rename = "dataHash")]
+    pub data_hash: Hash,
+    #[serde(rename = "dataCompression")]
+    pub data_compression: CleanName,
+
+    #[serde(rename = "finalOffset")]
+    #[serde(default)]
+    #[serde(skip_serializing_if = "u64_str::is_zero")]
+    #[serde(with = "u64_str")]
+    pub final_offset: u64,
+    #[serde(rename = "finalSize")]
+    #[serde(with = "u64_str")]
+    pub final_size: u64,
+    #[serde(rename = "finalHash")]
+    pub final_hash: Hash,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Patch {
+    #[serde(flatten)]
+    pub common: Common,
+
+    #[serde(rename = "dataOffset")]
+    #[serde(with = "u64_str")]
+    pub data_offset: u64,
+    #[serde(rename = "dataSize")]
+    #[serde(with = "u64_str")]
+    pub data_size: u64,
+    #[serde(rename = "dataHash")]
+    pub data_hash: Hash,
+    #[serde(rename = "dataCompression")]
+    pub data_compression: CleanName,
+
+    #[serde(rename = "patchType")]
+    pub patch_type: CleanName,
+
+    #[serde(rename = "localOffset")]
+    #[serde(default)]
+    #[serde(skip_serializing_if = "u64_str::is_zero")]
+    #[serde(with = "u64_str")]
+    pub local_offset: u64,
+    #[serde(rename = "localSize")]
+    #[serde(with = "u64_str")]
+    pub local_size: u64,
+    #[serde(rename = "localHash")]
+    pub local_hash: Hash,
+
+    #[serde(rename = "finalOffset")]
+    #[serde(default)]
+    #[serde(skip_serializing_if = "u64_str::is_zero")]
+    #[serde(with = "u64_str")]
+    pub final_offset: u64,
+    #[serde(rename = "finalSize")]
+    #[serde(with = "u64_str")]
+    pub final_size: u64,
+    #[serde(rename = "finalHash")]
+    pub final_hash: Hash,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Check {
+    #[serde(flatten)]
+    pub common: Common,
+
+    #[serde(rename = "localOffset")]
+    #[serde(default)]
+    #[serde(skip_serializing_if = "u64_str::is_zero")]
+    #[serde(with = "u64_str")]
+    pub local_offset: u64,
+    #[serde(rename = "localSize")]
+    #[serde(with = "u64_str")]
+    pub local_size: u64,
+    #[serde(rename = "localHash")]
+    pub local_hash: Hash,
+}
+
+/// A content defined chunk of a chunked file
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Chunk {
+    pub hash: Hash,
+    #[serde(with = "u64_str")]
+    pub size: u64,
+
+    /// Local file containing this chunk before the operation is applied
+    ///
+    /// `None` if the chunk is in the package data.
+    #[serde(rename = "localPath")]
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub local_path: Option<CleanPath>,
+    #[serde(rename = "localOffset")]
+    #[serde(default)]
+    #[serde(skip_serializing_if = "u64_str::is_zero")]
+    #[serde(with = "u64_str")]
+    pub local_offset: u64,
+}
+
+/// A file reassembled from local chunks and package data chunks
+///
+/// The package data is the concatenation of the chunks without local path, in
+/// `chunks` order.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Chunked {
+    #[serde(flatten)]
+    pub common: Common,
+
+    #[serde(rename = "dataOffset")]
+    #[serde(with = "u64_str")]
+    pub data_offset: u64,
+    #[serde(rename = "dataSize")]
+    #[serde(with = "u64_str")]
+    pub data_size: u64,
+    #[serde(rename = "dataHash")]
+    pub data_hash: Hash,
+    #[serde(rename = "dataCompression")]
+    pub data_compression: CleanName,
+
+    pub chunks: Vec<Chunk>,
+
+    #[serde(rename = "finalSize")]
+    #[serde(with = "u64_str")]
+    pub final_size: u64,
+    #[serde(rename = "finalHash")]
+    pub final_hash: Hash,
+}
+
+/// A file copied from another workspace file
+///
+/// The source is read when the operation is applied, so it can be a file of the
+/// previous version or a file written by a previous operation of the package.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Copy {
+    #[serde(flatten)]
+    pub common: Common,
+
+    #[serde(rename = "fromPath")]
+    pub from_path: CleanPath,
+
+    #[serde(rename = "finalSize")]
+    #[serde(with = "u64_str")]
+    pub final_size: u64,
+    #[serde(rename = "finalHash")]
+    pub final_hash: Hash,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type")]
+pub enum Operation {
+    #[serde(rename = "add")]
+    Add(Add),
+    #[serde(rename = "patch")]
+    Patch(Patch),
+    #[serde(rename = "check")]
+    Check(Check),
+    #[serde(rename = "rm")]
+    Rm(Rm),
+    #[serde(rename = "mkdir")]
+    MkDir { path: CleanPath },
+    #[serde(rename = "rmdir")]
+    RmDir { path: CleanPath },
+    #[serde(rename = "symlink")]
+    Symlink { path: CleanPath, target: String },
+    #[serde(rename = "chunked")]
+    Chunked(Chunked),
+    #[serde(rename = "copy")]
+    Copy(Copy),
+}
+
+impl Operation {
+    pub fn as_check_operation(&self) -> Option<Operation> {
+        match self {
+            Operation::Add(Add { common, final_offset, final_size, final_hash, .. })
+            | Operation::Patch(Patch { common, final_offset, final_size, final_hash, .. }) => {
+                Some(Operation::Check(Check {
+                    common: common.clone(),
+                    local_offset: *final_offset,
+                    local_size: *final_size,
+                    local_hash: final_hash.clone(),
+                }))
+            }
+            Operation::Chunked(Chunked { common, final_size, final_hash, .. })
+            | Operation::Copy(Copy { common, final_size, final_hash, .. }) => {
+                Some(Operation::Check(Check {
+                    common: common.clone(),
+                    local_offset: 0,
+                    local_size: *final_size,
+                    local_hash: final_hash.clone(),
+                }))
+            }
+            Operation::Check { .. } | Operation::MkDir { .. } | Operation::Symlink { .. } => {
+                Some(self.clone())
+            }
+            Operation::RmDir { .. } | Operation::Rm { .. } => None,
+        }
+    }
+
+    fn set_hash_algorithm(&mut self, algorithm: HashAlgorithm) -> Result<(), &'static str> {
+        match self {
+            Operation::Add(Add { data_hash, final_hash, .. }) => {
+                data_hash.set_algorithm(algorithm)?;
+                final_hash.set_algorithm(algorithm)
+            }
+            Operation::Patch(Patch { data_hash, local_hash, final_hash, .. }) => {
+                data_hash.set_algorithm(algorithm)?;
+                local_hash.set_algorithm(algorithm)?;
+                final_hash.set_algorithm(algorithm)
+            }
+            Operation::Check(Check { local_hash, .. }) => local_hash.set_algorithm(algorithm),
+            Operation::Chunked(Chunked { data_hash, chunks, final_hash, .. }) => {
+                data_hash.set_algorithm(algorithm)?;
+                for chunk in chunks {
+                    chunk.hash.set_algorithm(algorithm)?;
+                }
+                final_hash.set_algorithm(algorithm)
+            }
+            Operation::Copy(Copy { final_hash, .. }) => final_hash.set_algorithm(algorithm),
+            Operation::Rm(_)
+            | Operation::MkDir { .. }
+            | Operation::RmDir { .. }
+            | Operation::Symlink { .. } => Ok(()),
+        }
+    }
+
+    /// Returns true if this operation can be written in version 1 metadata
+    pub(crate) fn is_v1_compatible(&self) -> bool {
+        !matches!(self, Operation::Chunked(_) | Operation::Copy(_))
+    }
+}
+
+impl From<v1::Operation> for Operation {
+    fn from(operation: v1::Operation) -> Self {
+        match operation {
+            v1::Operation::Add(op) => Operation::Add(Add {
+                common: op.common,
+                data_offset: op.data_offset,
+                data_size: op.data_size,
+                data_hash: op.data_sha1.into(),
+                data_compression: op.data_compression,
+                final_offset: op.final_offset,
+                final_size: op.final_size,
+                final_hash: op.final_sha1.into(),
+            }),
+            v1::Operation::Patch(op) => Operation::Patch(Patch {
+                common: op.common,
+                data_offset: op.data_offset,
+                data_size: op.data_size,
+                data_hash: op.data_sha1.into(),
+                data_compression: op.data_compression,
+                patch_type: op.patch_type,
+                local_offset: op.local_offset,
+                local_size: op.local_size,
+                local_hash: op.local_sha1.into(),
+                final_offset: op.final_offset,
+                final_size: op.final_size,
+                final_hash: op.final_sha1.into(),
+            }),
+            v1::Operation::Check(op) => Operation::Check(Check {
+                common: op.common,
+                local_offset: op.local_offset,
+                local_size: op.local_size,
+                local_hash: op.local_sha1.into(),
+            }),
+            v1::Operation::Rm(op) => Operation::Rm(op),
+            v1::Operation::MkDir { path } => Operation::MkDir { path },
+            v1::Operation::RmDir { path } => Operation::RmDir { path },
+            v1::Operation::Symlink { path, target } => Operation::Symlink { path, target },
+        }
+    }
+}
+
+fn set_hash_algorithm<'de, D>(
+    algorithm: HashAlgorithm,
+    operations: &mut [Operation],
+) -> Result<(), D::Error>
+where
+    D: Deserializer<'de>,
+{
+    for operation in operations {
+        operation.set_hash_algorithm(algorithm).map_err(|err| {
+            serde::de::Error::custom(format!("{} hash expected: {}", algorithm, err))
+        })?;
+    }
+    Ok(())
+}
+
+/// Package metadata content
+#[derive(Serialize, Debug)]
+pub struct PackageMetadata {
+    pub package: Package,
+    /// Hash algorithm of every operation of the package
+    pub hash: HashAlgorithm,
+    pub operations: Vec<Operation>,
+}
+
+impl<'de> Deserialize<'de> for PackageMetadata {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        struct Raw {
+            package: Package,
+            hash: HashAlgorithm,
+            operations: Vec<Operation>,
+        }
+
+        let Raw { package, hash, mut operations } = Raw::deserialize(deserializer)?;
+        set_hash_algorithm::<D>(hash, &mut operations)?;
+        Ok(Self { package, hash, operations })
+    }
+}
+
+/// Workspace checks content
+#[derive(Serialize, Debug)]
+pub struct Checks {
+    /// Hash algorithm of every operation
+    pub hash: HashAlgorithm,
+    pub operations: Vec<Operation>,
+}
+
+impl<'de> Deserialize<'de> for Checks {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        struct Raw {
+            hash: HashAlgorithm,
+            operations: Vec<Operation>,
+        }
+
+        let Raw { hash, mut operations } = Raw::deserialize(deserializer)?;
+        set_hash_algorithm::<D>(hash, &mut operations)?;
+        Ok(Self { hash, operations })
+    }
+}
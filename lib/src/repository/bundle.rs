@@ -0,0 +1,106 @@
+use std::fs;
+use std::path::Path;
+
+use super::Repository;
+use crate::io;
+use crate::link::{self, BundleSource};
+use crate::metadata::{self, CleanName, Package, Packages, Versions};
+use crate::signature;
+
+pub(super) fn export_bundle(
+    repository: &Repository,
+    from: Option<&CleanName>,
+    to: &CleanName,
+    platform: Option<&CleanName>,
+    bundle_path: &Path,
+) -> io::Result<()> {
+    let version = match repository.versions()? {
+        Versions::V1 { versions } => versions.into_iter().find(|v| &v.revision == to),
+    };
+    let version = match version {
+        Some(version) if version.yanked.is_none() => version,
+        Some(_) => return Err(invalid_input(format!("version {} is yanked", to))),
+        None => return Err(invalid_input(format!("version {} doesn't exists", to))),
+    };
+    let packages = repository.packages()?;
+    let path = metadata::shortest_path(
+        from,
+        to,
+        packages.as_slice(),
+        platform,
+        &metadata::WeightedPathCost::default(),
+    )
+    .ok_or_else(|| {
+        let from = from.map_or("scratch", CleanName::as_str);
+        invalid_input(format!("no package path from {} to {}", from, to))
+    })?;
+
+    let mut files = Vec::new();
+    let current = metadata::Current::V1 { current: version };
+    push_encoded(repository, &mut files, metadata::Current::filename(), &current)?;
+    if !push_existing(repository, &mut files, metadata::Versions::filename()) {
+        return Err(missing_file(metadata::Versions::filename()));
+    }
+    push_existing(
+        repository,
+        &mut files,
+        &signature::signature_filename(metadata::Versions::filename()),
+    );
+    let path_packages = Packages::V1 { packages: path.iter().map(|&p| p.clone()).collect() };
+    push_encoded(repository, &mut files, metadata::Packages::filename(), &path_packages)?;
+    for package in path {
+        let name = package.package_metadata_name();
+        let cbor_name = metadata::Encoding::Cbor.filename(&name);
+        let mut found = false;
+        for filename in [name.to_string(), cbor_name] {
+            push_existing(repository, &mut files, &signature::signature_filename(&filename));
+            found |= push_existing(repository, &mut files, &filename);
+        }
+        if !found {
+            return Err(missing_file(&name));
+        }
+        let data_name = package.package_data_name().to_string();
+        files.push((data_name.clone(), BundleSource::File(repository.dir().join(data_name))));
+    }
+    link::write_bundle(bundle_path, &files)
+}
+
+/// Bundle `value` as `filename`, signed if the repository has a signing key
+fn push_encoded<T>(
+    repository: &Repository,
+    files: &mut Vec<(String, BundleSource)>,
+    filename: &str,
+    value: &T,
+) -> io::Result<()>
+where
+    T: serde::Serialize,
+{
+    let raw = metadata::Encoding::Json.encode(value)?;
+    if let Some((signature_filename, signature)) = repository.signature_file(filename, &raw)? {
+        files.push((signature_filename, BundleSource::Raw(signature)));
+    }
+    files.push((filename.to_string(), BundleSource::Raw(raw)));
+    Ok(())
+}
+
+/// Bundle the repository file `filename` if it exists, returns false otherwise
+fn push_existing(
+    repository: &Repository,
+    files: &mut Vec<(String, BundleSource)>,
+    filename: &str,
+) -> bool {
+    let path = repository.dir().join(filename);
+    let exists = fs::metadata(&path).is_ok_and(|m| m.is_file());
+    if exists {
+        files.push((filename.to_string(), BundleSource::File(path)));
+    }
+    exists
+}
+
+fn invalid_input(reason: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, reason)
+}
+
+fn missing_file(filename: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, format!("{} is missing from the repository", filename))
+}
@@ -0,0 +1,147 @@
+//! Content defined chunking
+//!
+//! Files are cut where a gear rolling hash of the last bytes matches a mask,
+//! so inserting or removing bytes only changes the chunks around the edit.
+use std::collections::HashMap;
+use std::io::Read;
+
+use crate::io;
+use crate::metadata::{CleanPath, Hash, HashAlgorithm};
+
+/// Content defined chunking options
+#[derive(Debug, Clone)]
+pub struct ChunkingOptions {
+    min_size: u64,
+    avg_size: u64,
+    max_size: u64,
+}
+
+impl ChunkingOptions {
+    /// Smallest average chunk size
+    pub const MIN_AVG_SIZE: u64 = 64;
+    /// Biggest average chunk size
+    pub const MAX_AVG_SIZE: u64 = 1 << 30;
+
+    /// Chunking options with chunks between `avg_size / 4` and `avg_size * 4`
+    ///
+    /// `avg_size` is clamped between [`Self::MIN_AVG_SIZE`] and
+    /// [`Self::MAX_AVG_SIZE`].
+    pub fn new(avg_size: u64) -> Self {
+        let avg_size = avg_size.clamp(Self::MIN_AVG_SIZE, Self::MAX_AVG_SIZE);
+        Self { min_size: avg_size / 4, avg_size, max_size: avg_size * 4 }
+    }
+
+    /// Chunks are never smaller than this, except the last one
+    pub fn min_size(&self) -> u64 {
+        self.min_size
+    }
+
+    /// Set the minimum chunk size, at most the average chunk size
+    pub fn set_min_size(&mut self, min_size: u64) {
+        self.min_size = min_size.min(self.avg_size);
+    }
+
+    /// Average chunk size
+    ///
+    /// Chunks are cut with a mask of `avg_size` rounded up to a power of two,
+    /// the actual average is closer to that power of two.
+    pub fn avg_size(&self) -> u64 {
+        self.avg_size
+    }
+
+    /// Chunks are never bigger than this
+    pub fn max_size(&self) -> u64 {
+        self.max_size
+    }
+
+    /// Set the maximum chunk size, at least the average chunk size
+    pub fn set_max_size(&mut self, max_size: u64) {
+        self.max_size = max_size.max(self.avg_size);
+    }
+
+    fn mask(&self) -> u64 {
+        let bits = self.avg_size.next_power_of_two().trailing_zeros();
+        u64::MAX << (64 - bits)
+    }
+}
+
+impl Default for ChunkingOptions {
+    fn default() -> Self {
+        Self::new(64 * 1024)
+    }
+}
+
+/// Random values for each byte, generated with splitmix64
+const GEAR: [u64; 256] = {
+    let mut gear = [0u64; 256];
+    let mut state = 0u64;
+    let mut i = 0;
+    while i < gear.len() {
+        state = state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        gear[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    gear
+};
+
+/// Split `reader` content into chunks
+///
+/// `on_chunk` is called with each chunk offset, content and hash.
+pub(super) fn chunks<R, F>(
+    options: &ChunkingOptions,
+    algorithm: HashAlgorithm,
+    mut reader: R,
+    mut on_chunk: F,
+) -> io::Result<()>
+where
+    R: Read,
+    F: FnMut(u64, &[u8], Hash) -> io::Result<()>,
+{
+    let mask = options.mask();
+    let mut buffer = [0u8; io::BUFFER_SIZE];
+    // chunks can be up to 4 GiB, the buffer only grows as big as needed
+    let mut chunk = Vec::with_capacity(options.max_size.min(io::BUFFER_SIZE as u64) as usize);
+    let mut offset = 0u64;
+    let mut gear = 0u64;
+    loop {
+        let read = reader.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        for &byte in &buffer[..read] {
+            chunk.push(byte);
+            gear = (gear << 1).wrapping_add(GEAR[byte as usize]);
+            let size = chunk.len() as u64;
+            if (size >= options.min_size && gear & mask == 0) || size >= options.max_size {
+                on_chunk(offset, &chunk, algorithm.digest(&chunk))?;
+                offset += size;
+                chunk.clear();
+                gear = 0;
+            }
+        }
+    }
+    if !chunk.is_empty() {
+        on_chunk(offset, &chunk, algorithm.digest(&chunk))?;
+    }
+    Ok(())
+}
+
+/// Chunks available in the workspace before the package is applied
+pub(super) type LocalChunks = HashMap<Hash, (CleanPath, u64)>;
+
+/// Index the chunks of `path` content
+pub(super) fn index<R: Read>(
+    options: &ChunkingOptions,
+    algorithm: HashAlgorithm,
+    path: &CleanPath,
+    reader: R,
+    local_chunks: &mut LocalChunks,
+) -> io::Result<()> {
+    chunks(options, algorithm, reader, |offset, _chunk, hash| {
+        local_chunks.entry(hash).or_insert_with(|| (path.clone(), offset));
+        Ok(())
+    })
+}
@@ -6,48 +6,151 @@
 //! - `versions`: a JSON file with informations about all versions.
 //! - `packages`: a JSON file that list available packages (i.e. the update graph).
 //! - `$package_name.metadata`: a JSON file with precise informations about a package and how to apply it.
+//! - `$package_name.metadata.cbor`: the same informations CBOR encoded (only if binary metadata is enabled).
 //! - `$package_name`: a binary file containing package update operations data.
+//! - `$file.zst`, `$file.br`: `$file` compressed (only if metadata compression is enabled).
+//! - `$file.sig`: the ed25519 signature of `$file` and its version (only if the repository has a signing key).
+//!
+//! The repository can also be published to an S3 bucket (see
+//! [`Repository::set_publish_bucket`]).
 //!
 //! ## Safety
 //!
 //! In order to have zero downtime, it's important to only do atomic update
 //! (i.e. renaming of existing file) of  repository known files (i.e. `current`,
 //! `versions` and `packages`).
+//!
+//! A file, its compressed copy and its signature are written to temporary
+//! files first, then renamed (and uploaded) one after the other, the
+//! signature last. Links read a file whose signature doesn't match again
+//! after a short delay, until the new signature is there.
+mod bundle;
+mod chunker;
 mod packager;
 pub mod progress;
+mod verify;
 
 use std::fs;
+use std::future::Future;
 use std::path::{Path, PathBuf};
 
 use serde::Serialize;
 use serde_json;
 
+pub use self::chunker::ChunkingOptions;
 pub use self::packager::{BuildError, BuildOptions, PackageBuilder};
+pub use self::verify::VerifyError;
 pub use crate::codecs::CoderOptions;
-use crate::metadata::{self, CleanName, PackageMetadata, Packages, Versions};
-use crate::{io, link};
+use crate::metadata::{self, CleanName, HashAlgorithm, Package, Packages, Versions};
+use crate::signature::{self, SigningKey};
+use crate::{codecs, io, link};
 
 /// Manage a repository (get/set current version, add/rm package, ...)
 pub struct Repository {
     dir: PathBuf,
+    signing_key: Option<SigningKey>,
+    binary_metadata: bool,
+    metadata_compression: Option<CoderOptions>,
+    publish_bucket: Option<link::S3Repository>,
 }
 
 impl Repository {
     pub fn new(dir: PathBuf) -> Repository {
-        Repository { dir }
+        Repository {
+            dir,
+            signing_key: None,
+            binary_metadata: false,
+            metadata_compression: None,
+            publish_bucket: None,
+        }
+    }
+
+    /// Sign every metadata file written from now on with `signing_key`
+    pub fn set_signing_key(&mut self, signing_key: SigningKey) {
+        self.signing_key = Some(signing_key);
+    }
+
+    /// Also write a CBOR encoded copy of package metadata when registering
+    /// packages
+    ///
+    /// Remote links prefer this copy, it is smaller and faster to parse.
+    pub fn set_binary_metadata(&mut self, binary_metadata: bool) {
+        self.binary_metadata = binary_metadata;
+    }
+
+    /// Also write a compressed copy (`.zst` or `.br`) of `versions`, `packages`
+    /// and package metadata files
+    ///
+    /// Remote links prefer this copy. Stale compressed copies are removed
+    /// when the files are written without compression.
+    pub fn set_metadata_compression(
+        &mut self,
+        compression: Option<CoderOptions>,
+    ) -> io::Result<()> {
+        if let Some(options) = &compression {
+            if !codecs::METADATA_COMPRESSIONS.iter().any(|&(_, name)| name == options.name()) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("{} can't be used to compress metadata", options.name()),
+                ));
+            }
+        }
+        self.metadata_compression = compression;
+        Ok(())
+    }
+
+    /// Also upload every file written to the repository from now on to
+    /// `bucket`
+    ///
+    /// Package files are uploaded before `packages` and `current` files last,
+    /// so clients only switch to a version once its packages are available.
+    /// Signatures are uploaded after the files they sign.
+    /// Existing files are uploaded by [`publish`](Self::publish).
+    pub fn set_publish_bucket(&mut self, bucket: Option<link::S3Repository>) {
+        self.publish_bucket = bucket;
+    }
+
+    /// Upload every repository file to the publish bucket, `current` files
+    /// last
+    ///
+    /// Fails if there is no publish bucket.
+    pub fn publish(&self) -> io::Result<()> {
+        if self.publish_bucket.is_none() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "no publish bucket"));
+        }
+        let mut filenames = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            if !entry.file_type()?.is_file() {
+                continue;
+            }
+            match entry.file_name().into_string() {
+                Ok(filename) if !filename.ends_with(".tmp") => filenames.push(filename),
+                _ => {}
+            }
+        }
+        filenames.sort_by_cached_key(|filename| {
+            (publish_rank(filename), filename.ends_with(".sig"), filename.clone())
+        });
+        let filenames: Vec<&str> = filenames.iter().map(String::as_str).collect();
+        self.upload(&filenames)
     }
 
     pub fn link(&self) -> link::FileRepository {
-        link::FileRepository::new(self.dir.clone())
+        let mut link = link::FileRepository::new(self.dir.clone());
+        if let Some(signing_key) = &self.signing_key {
+            link.set_public_key(signing_key.verifying_key());
+        }
+        link
     }
 
     pub fn init(&mut self) -> io::Result<()> {
-        create_if_missing(
-            &self.dir.join(metadata::Versions::filename()),
+        self.create_if_missing(
+            metadata::Versions::filename(),
             &Versions::V1 { versions: Vec::new() },
         )?;
-        create_if_missing(
-            &self.dir.join(metadata::Packages::filename()),
+        self.create_if_missing(
+            metadata::Packages::filename(),
             &Packages::V1 { packages: Vec::new() },
         )?;
         Ok(())
@@ -57,16 +160,31 @@ impl Repository {
         &self.dir
     }
 
-    pub fn current_version(&self) -> io::Result<metadata::Current> {
-        serde_json::from_reader(fs::File::open(self.dir.join(metadata::Current::filename()))?)
-            .map_err(io::Error::from)
+    /// Repository current version of `channel` (`None` is the default channel)
+    pub fn current_version(&self, channel: Option<&CleanName>) -> io::Result<metadata::Current> {
+        let filename = metadata::Current::channel_filename(channel);
+        serde_json::from_reader(fs::File::open(self.dir.join(filename))?).map_err(io::Error::from)
     }
 
-    /// Set repository current version
+    /// Set repository current version of `channel` (`None` is the default channel)
+    ///
+    /// Channels share versions and packages, only the current version differs.
     ///
     /// Fails if the request version isn't in the list of known versions or
     /// if the atomic rename of `current` fails
-    pub fn set_current_version(&mut self, version: &CleanName) -> io::Result<()> {
+    pub fn set_current_version(
+        &mut self,
+        channel: Option<&CleanName>,
+        version: &CleanName,
+    ) -> io::Result<()> {
+        if let Some(channel) = channel {
+            if is_sibling_file(channel.as_str()) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("channel name {} is reserved", channel),
+                ));
+            }
+        }
         let version: metadata::Current = match self.versions()? {
             Versions::V1 { versions } => versions
                 .into_iter()
@@ -79,7 +197,15 @@ impl Repository {
                 format!("version {} doesn't exists", version),
             )
         })?;
-        io::atomic_write_json(self.dir.join(metadata::Current::filename()), &version)?;
+        if let metadata::Current::V1 { current: metadata::v1::Version { yanked: Some(_), .. } } =
+            &version
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("version {} is yanked", version.version()),
+            ));
+        }
+        self.write_json(&metadata::Current::channel_filename(channel), &version)?;
         Ok(())
     }
 
@@ -99,14 +225,71 @@ impl Repository {
                 .chain(std::iter::once(metadata::v1::Version {
                     revision: version.revision().clone(),
                     description: version.description().to_owned(),
+                    yanked: version.yanked().map(str::to_owned),
                 }))
                 .collect(),
         };
         let versions = Versions::V1 { versions };
-        io::atomic_write_json(self.dir.join(metadata::Versions::filename()), &versions)?;
+        self.write_json(metadata::Versions::filename(), &versions)?;
+        Ok(())
+    }
+
+    /// Withdraw a version without removing it from the history
+    ///
+    /// Clients never update to a yanked version, workspaces on a yanked version
+    /// are updated to the current version. Registering the version again
+    /// un-yanks it.
+    ///
+    /// Fails if the version doesn't exist, if it is the current version of a
+    /// channel or if the atomic rename of `versions` fails.
+    pub fn yank_version(&self, revision: &CleanName, reason: &str) -> io::Result<()> {
+        for channel in self.channels()? {
+            if self.current_version(channel.as_ref())?.version() == revision {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("version {} is the current version", revision),
+                ));
+            }
+        }
+        let Versions::V1 { mut versions } = self.versions()?;
+        let version = versions.iter_mut().find(|v| &v.revision == revision).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("version {} doesn't exists", revision),
+            )
+        })?;
+        version.yanked = Some(reason.to_owned());
+        let versions = Versions::V1 { versions };
+        self.write_json(metadata::Versions::filename(), &versions)?;
         Ok(())
     }
 
+    /// Channels with a current version, `None` being the default one
+    fn channels(&self) -> io::Result<Vec<Option<CleanName>>> {
+        let mut channels = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let filename = entry?.file_name();
+            let filename = match filename.to_str() {
+                Some(filename)
+                    if metadata::Current::is_filename(filename) && !is_sibling_file(filename) =>
+                {
+                    filename
+                }
+                _ => continue,
+            };
+            match filename.strip_prefix(metadata::Current::filename()) {
+                Some("") => channels.push(None),
+                Some(channel) => {
+                    if let Ok(channel) = CleanName::new(channel[1..].to_string()) {
+                        channels.push(Some(channel));
+                    }
+                }
+                None => {}
+            }
+        }
+        Ok(channels)
+    }
+
     /// Remove version to repository
     ///
     /// Fails if the atomic rename of `versions` fails.
@@ -117,7 +300,7 @@ impl Repository {
             }
         };
         let versions = Versions::V1 { versions };
-        io::atomic_write_json(self.dir.join(metadata::Versions::filename()), &versions)?;
+        self.write_json(metadata::Versions::filename(), &versions)?;
         Ok(())
     }
 
@@ -164,44 +347,334 @@ impl Repository {
 
     /// Register or update package to repository
     ///
+    /// If the repository has a signing key, the package metadata file is signed too.
+    /// If binary metadata is enabled, the CBOR encoded package metadata is written too.
+    /// If metadata compression is enabled, package metadata files are compressed too.
+    ///
     /// Fails if the atomic rename of `packages` fails.
     pub fn register_package(&self, package_metadata_name: &str) -> io::Result<()> {
-        let packages = match (self.package_metadata(package_metadata_name)?, self.packages()?) {
-            (PackageMetadata::V1 { package, .. }, Packages::V1 { packages }) => packages
+        let raw = fs::read(self.dir.join(package_metadata_name))?;
+        let package_metadata = self.package_metadata(package_metadata_name)?;
+        let package = package_metadata.package();
+        self.upload(&[package.package_data_name().as_str(), package_metadata_name])?;
+        let mut files = self.compressed_files(package_metadata_name, &raw)?;
+        files.extend(self.signature_file(package_metadata_name, &raw)?);
+        self.write_files(&files)?;
+        self.remove_stale_compressed(package_metadata_name)?;
+        if self.binary_metadata {
+            self.write_encoded(package_metadata_name, metadata::Encoding::Cbor, &package_metadata)?;
+        }
+        let name = package.package_metadata_name();
+        let package = metadata::v1::Package {
+            metadata_hash: Some(HashAlgorithm::Sha256.digest(&raw)),
+            ..package.clone()
+        };
+        let packages = match self.packages()? {
+            Packages::V1 { packages } => packages
                 .into_iter()
-                .filter(|p| p != &package)
-                .chain(std::iter::once(package.clone()))
+                .filter(|p| p.package_metadata_name() != name)
+                .chain(std::iter::once(package))
                 .collect(),
         };
         let packages = Packages::V1 { packages };
-        io::atomic_write_json(self.dir.join(metadata::Packages::filename()), &packages)?;
+        self.write_json(metadata::Packages::filename(), &packages)?;
         Ok(())
     }
 
     /// Unregister package to repository
     ///
+    /// The CBOR encoded, compressed and signature siblings of the package
+    /// metadata file are removed too, the package metadata and data files are
+    /// kept.
+    ///
     /// Fails if the atomic rename of `packages` fails.
     pub fn unregister_package(&self, package_metadata_name: &str) -> io::Result<()> {
-        let packages = match (self.package_metadata(package_metadata_name)?, self.packages()?) {
-            (PackageMetadata::V1 { package, .. }, Packages::V1 { packages }) => {
-                packages.into_iter().filter(|p| p != &package).collect()
+        let package_metadata = self.package_metadata(package_metadata_name)?;
+        let name = package_metadata.package().package_metadata_name();
+        let packages = match self.packages()? {
+            Packages::V1 { packages } => {
+                packages.into_iter().filter(|p| p.package_metadata_name() != name).collect()
             }
         };
         let packages = Packages::V1 { packages };
-        io::atomic_write_json(self.dir.join(metadata::Packages::filename()), &packages)?;
+        self.write_json(metadata::Packages::filename(), &packages)?;
+        let cbor_name = metadata::Encoding::Cbor.filename(package_metadata_name);
+        let mut filenames = vec![cbor_name.clone()];
+        for filename in [package_metadata_name, &cbor_name] {
+            filenames.push(signature::signature_filename(filename));
+            for &(extension, _) in codecs::METADATA_COMPRESSIONS {
+                filenames.push(format!("{}.{}", filename, extension));
+            }
+        }
+        self.remove_files(&filenames)
+    }
+
+    /// Publish hints about a registered package, used by clients to select the
+    /// update path
+    ///
+    /// Registering the package again resets its hints.
+    ///
+    /// Fails if the package isn't registered or if the atomic rename of
+    /// `packages` fails.
+    pub fn set_package_hints(
+        &self,
+        package_metadata_name: &str,
+        hints: metadata::v1::PackageHints,
+    ) -> io::Result<()> {
+        let Packages::V1 { mut packages } = self.packages()?;
+        let package = packages
+            .iter_mut()
+            .find(|p| p.package_metadata_name().as_str() == package_metadata_name)
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("package {} isn't registered", package_metadata_name),
+                )
+            })?;
+        package.hints = hints;
+        let packages = Packages::V1 { packages };
+        self.write_json(metadata::Packages::filename(), &packages)?;
         Ok(())
     }
+
+    /// Check the repository consistency
+    ///
+    /// Registered packages must have readable metadata and data files, the
+    /// data file size must match the package size and every operation data
+    /// must lie within it with the expected hash. Versions referred to by
+    /// `current` files and packages must be registered and every version that
+    /// isn't yanked must be reachable by some package path.
+    ///
+    /// Returns every inconsistency found, fails only if `versions`, `packages`
+    /// or a `current` file can't be read.
+    pub fn verify(&self) -> io::Result<Vec<VerifyError>> {
+        verify::verify(self)
+    }
+
+    /// Write an offline bundle to `bundle_path`, updating workspaces from
+    /// `from` (`None` is an empty workspace) to `to` for `platform`
+    ///
+    /// The bundle holds `current` (set to `to`), `versions`, the packages of
+    /// the shortest path and their metadata files. `current` and `packages`
+    /// are signed if the repository has a signing key. Bundles are opened
+    /// with `bundle://path` repository URLs (see
+    /// [`BundleRepository`](link::BundleRepository)).
+    ///
+    /// Fails if `to` doesn't exist or is yanked or if no package path leads
+    /// from `from` to `to`.
+    pub fn export_bundle(
+        &self,
+        from: Option<&CleanName>,
+        to: &CleanName,
+        platform: Option<&CleanName>,
+        bundle_path: &Path,
+    ) -> io::Result<()> {
+        bundle::export_bundle(self, from, to, platform, bundle_path)
+    }
+
+    fn create_if_missing<T>(&self, filename: &str, value: &T) -> io::Result<()>
+    where
+        T: Serialize,
+    {
+        if fs::metadata(self.dir.join(filename)).is_err() {
+            self.write_json(filename, value)?;
+        }
+        Ok(())
+    }
+
+    /// Atomically write `value` to `filename` and its signature if the
+    /// repository has a signing key
+    fn write_json<T>(&self, filename: &str, value: &T) -> io::Result<()>
+    where
+        T: Serialize,
+    {
+        self.write_encoded(filename, metadata::Encoding::Json, value)
+    }
+
+    fn write_encoded<T>(
+        &self,
+        filename: &str,
+        encoding: metadata::Encoding,
+        value: &T,
+    ) -> io::Result<()>
+    where
+        T: Serialize,
+    {
+        let filename = encoding.filename(filename);
+        let raw = encoding.encode(value)?;
+        let signature = self.signature_file(&filename, &raw)?;
+        let mut files = self.compressed_files(&filename, &raw)?;
+        files.push((filename.clone(), raw));
+        files.extend(signature);
+        self.write_files(&files)?;
+        self.remove_stale_compressed(&filename)
+    }
+
+    /// Compressed copy of `filename` if metadata compression is enabled
+    ///
+    /// `current` files are tiny and often polled, they are never compressed.
+    fn compressed_files(&self, filename: &str, raw: &[u8]) -> io::Result<Vec<(String, Vec<u8>)>> {
+        match &self.metadata_compression {
+            Some(options) if !metadata::Current::is_filename(filename) => {
+                let extension = codecs::METADATA_COMPRESSIONS
+                    .iter()
+                    .find(|&&(_, coder_name)| coder_name == options.name())
+                    .map(|&(extension, _)| extension)
+                    .expect("checked by set_metadata_compression");
+                let compressed = codecs::encode_all(options, raw)?;
+                Ok(vec![(format!("{}.{}", filename, extension), compressed)])
+            }
+            _ => Ok(Vec::new()),
+        }
+    }
+
+    /// Remove compressed copies of `filename` with another compression than
+    /// the enabled one
+    fn remove_stale_compressed(&self, filename: &str) -> io::Result<()> {
+        if metadata::Current::is_filename(filename) {
+            return Ok(());
+        }
+        let filenames: Vec<String> = codecs::METADATA_COMPRESSIONS
+            .iter()
+            .filter(|&&(_, coder_name)| {
+                self.metadata_compression
+                    .as_ref()
+                    .is_none_or(|options| options.name() != coder_name)
+            })
+            .map(|&(extension, _)| format!("{}.{}", filename, extension))
+            .collect();
+        self.remove_files(&filenames)
+    }
+
+    /// Signature file of `filename` with content `raw` if the repository has
+    /// a signing key
+    ///
+    /// The signature version is more recent than the one of the existing
+    /// signature file.
+    pub(super) fn signature_file(
+        &self,
+        filename: &str,
+        raw: &[u8],
+    ) -> io::Result<Option<(String, Vec<u8>)>> {
+        let signing_key = match &self.signing_key {
+            Some(signing_key) => signing_key,
+            None => return Ok(None),
+        };
+        let signature_filename = signature::signature_filename(filename);
+        let previous = match fs::read(self.dir.join(&signature_filename)) {
+            Ok(previous) => signature::signature_version(&previous),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+            Err(err) => return Err(err),
+        };
+        let version = signature::next_version(previous);
+        let signature = signature::sign(signing_key, filename, version, raw);
+        Ok(Some((signature_filename, signature.into_bytes())))
+    }
+
+    /// Write `files` to temporary files, then rename and upload them to the
+    /// publish bucket in order
+    ///
+    /// Nothing is renamed if a temporary file can't be written.
+    fn write_files(&self, files: &[(String, Vec<u8>)]) -> io::Result<()> {
+        let tmp_path = |filename: &str| self.dir.join(format!("{}.tmp", filename));
+        let res = files.iter().try_for_each(|(filename, raw)| {
+            let mut file = fs::File::create(tmp_path(filename))?;
+            io::Write::write_all(&mut file, raw)?;
+            file.sync_all()
+        });
+        let res = res.and_then(|_| {
+            files.iter().try_for_each(|(filename, _)| {
+                io::atomic_rename(tmp_path(filename), self.dir.join(filename))
+            })
+        });
+        if res.is_err() {
+            for (filename, _) in files {
+                let _ = io::remove_file(tmp_path(filename));
+            }
+        }
+        res?;
+        let filenames: Vec<&str> = files.iter().map(|(filename, _)| filename.as_str()).collect();
+        self.upload(&filenames)
+    }
+
+    /// Remove `filenames` and their copy in the publish bucket
+    fn remove_files(&self, filenames: &[String]) -> io::Result<()> {
+        let mut removed = Vec::new();
+        for filename in filenames {
+            let path = self.dir.join(filename);
+            if path.exists() {
+                removed.push(filename);
+            }
+            io::remove_file(path)?;
+        }
+        match &self.publish_bucket {
+            Some(bucket) if !removed.is_empty() => block_on(async {
+                for filename in removed {
+                    bucket.delete(filename).await?;
+                }
+                Ok::<_, link::RepositoryError>(())
+            })?
+            .map_err(io::Error::other),
+            _ => Ok(()),
+        }
+    }
+
+    /// Upload `filenames` to the publish bucket, in order
+    fn upload(&self, filenames: &[&str]) -> io::Result<()> {
+        match &self.publish_bucket {
+            Some(bucket) => block_on(async {
+                for filename in filenames {
+                    bucket.put_file(filename, &self.dir.join(filename)).await?;
+                }
+                Ok::<_, link::RepositoryError>(())
+            })?
+            .map_err(io::Error::other),
+            None => Ok(()),
+        }
+    }
 }
 
-fn create_if_missing<T>(path: &Path, value: &T) -> io::Result<()>
+/// Upload order of `filename`: package files, then `versions` and `packages`
+/// and `current` files last
+fn publish_rank(filename: &str) -> u8 {
+    let is_family = |name: &str| {
+        filename.strip_prefix(name).is_some_and(|rest| rest.is_empty() || rest.starts_with('.'))
+    };
+    if metadata::Current::is_filename(filename) {
+        2
+    } else if is_family(metadata::Versions::filename()) || is_family(metadata::Packages::filename())
+    {
+        1
+    } else {
+        0
+    }
+}
+
+/// Run `future` to completion, even if the caller is running in an async
+/// runtime
+///
+/// It runs in a new thread and runtime, callers batch their requests in a
+/// single future.
+fn block_on<F>(future: F) -> io::Result<F::Output>
 where
-    T: Serialize,
+    F: Future + Send,
+    F::Output: Send,
 {
-    if fs::metadata(path).is_err() {
-        let file = fs::File::create(path)?;
-        serde_json::to_writer_pretty(file, value)?;
-    }
-    Ok(())
+    std::thread::scope(|scope| {
+        let thread = scope.spawn(|| {
+            let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+            Ok(runtime.block_on(future))
+        });
+        thread.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+    })
+}
+
+/// Signature, compressed or temporary file of a metadata file (i.e.
+/// `current.beta.sig`), channel names can't end with these extensions
+fn is_sibling_file(filename: &str) -> bool {
+    let ext = filename.rsplit('.').next().unwrap_or_default();
+    ["sig", "cbor", "tmp"].contains(&ext)
+        || codecs::METADATA_COMPRESSIONS.iter().any(|&(e, _)| e == ext)
 }
 
 #[cfg(test)]
@@ -209,6 +682,7 @@ mod tests {
     use futures::TryStreamExt;
 
     use super::*;
+    use crate::metadata::{HashAlgorithm, PackageMetadata};
     use crate::workspace::UpdateOptions;
     use crate::Workspace;
 
@@ -234,4 +708,676 @@ mod tests {
         let build_stream = builder.build();
         rt.block_on(build_stream.try_for_each(|_| async { Ok(()) })).unwrap();
     }
+
+    #[test]
+    fn blake3_package() {
+        crate::tests::init();
+        let v1 = CleanName::from_static_str("1");
+        let path = crate::tests::tmp_dir("blake3_package");
+        let source_directory = path.join("source");
+        fs::create_dir_all(source_directory.join("dir")).unwrap();
+        fs::write(source_directory.join("file.txt"), "blake3 content").unwrap();
+        fs::write(source_directory.join("dir/other.txt"), "other content").unwrap();
+
+        let mut repository = crate::tests::init_repository(&path);
+        let versions = [(&v1, source_directory.as_path())];
+        let builder = crate::tests::publish_versions(&mut repository, &path, &versions, || {
+            BuildOptions { hash: HashAlgorithm::Blake3, ..BuildOptions::raw() }
+        });
+
+        let package_metadata =
+            repository.package_metadata(&builder.package_metadata_name()).unwrap();
+        assert!(matches!(package_metadata, PackageMetadata::V2(_)));
+        assert_eq!(package_metadata.hash_algorithm(), HashAlgorithm::Blake3);
+
+        let workspace_dir = path.join("workspace");
+        let mut workspace = Workspace::open(&workspace_dir).unwrap();
+        crate::tests::update_workspace(&mut workspace, &repository.link(), &v1).unwrap();
+        workspace.remove_metadata().unwrap();
+        crate::tests::assert_fs_eq(&source_directory, &workspace_dir);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlink_package() {
+        use std::os::unix::fs::symlink;
+
+        crate::tests::init();
+        let v1 = CleanName::from_static_str("1");
+        let v2 = CleanName::from_static_str("2");
+        let path = crate::tests::tmp_dir("symlink_package");
+        let source_v1 = path.join("source_v1");
+        fs::create_dir_all(source_v1.join("lib")).unwrap();
+        fs::write(source_v1.join("lib/libfoo.so.1"), "foo v1").unwrap();
+        symlink("libfoo.so.1", source_v1.join("lib/libfoo.so")).unwrap();
+        symlink("lib/libfoo.so", source_v1.join("foo")).unwrap();
+        symlink("lib", source_v1.join("libs")).unwrap();
+        let source_v2 = path.join("source_v2");
+        fs::create_dir_all(source_v2.join("lib")).unwrap();
+        fs::write(source_v2.join("lib/libfoo.so.2"), "foo v2").unwrap();
+        symlink("libfoo.so.2", source_v2.join("lib/libfoo.so")).unwrap();
+        symlink("lib", source_v2.join("libs")).unwrap();
+        fs::write(source_v2.join("foo"), "not a link anymore").unwrap();
+
+        let mut repository = crate::tests::init_repository(&path);
+        let versions = [(&v1, source_v1.as_path()), (&v2, source_v2.as_path())];
+        let builder =
+            crate::tests::publish_versions(&mut repository, &path, &versions, BuildOptions::raw);
+
+        // the unchanged `libs` link isn't written again
+        let package_metadata =
+            repository.package_metadata(&builder.package_metadata_name()).unwrap();
+        let links: Vec<&str> = package_metadata
+            .iter()
+            .filter_map(|op| match op {
+                metadata::v2::Operation::Symlink { path, .. } => Some(path.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(links, ["lib/libfoo.so"]);
+
+        let workspace_dir = path.join("workspace");
+        let mut workspace = Workspace::open(&workspace_dir).unwrap();
+        let link = repository.link();
+        crate::tests::update_workspace(&mut workspace, &link, &v1).unwrap();
+        let target = fs::read_link(workspace_dir.join("lib/libfoo.so")).unwrap();
+        assert_eq!(target, Path::new("libfoo.so.1"));
+        assert_eq!(fs::read_to_string(workspace_dir.join("foo")).unwrap(), "foo v1");
+
+        crate::tests::update_workspace(&mut workspace, &link, &v2).unwrap();
+        workspace.remove_metadata().unwrap();
+        crate::tests::assert_fs_eq(&source_v2, &workspace_dir);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn chained_symlinks() {
+        use std::os::unix::fs::symlink;
+
+        crate::tests::init();
+        let v1 = CleanName::from_static_str("1");
+        let path = crate::tests::tmp_dir("chained_symlinks");
+        let source_directory = path.join("source");
+        fs::create_dir_all(source_directory.join("d")).unwrap();
+        fs::write(source_directory.join("file.txt"), "inside").unwrap();
+        // each link stays inside on its own, but d/x resolves to d/y/.. = ../
+        symlink("..", source_directory.join("d/y")).unwrap();
+        symlink("y/..", source_directory.join("d/x")).unwrap();
+
+        let mut repository = crate::tests::init_repository(&path);
+        let versions = [(&v1, source_directory.as_path())];
+        crate::tests::publish_versions(&mut repository, &path, &versions, BuildOptions::raw);
+
+        let link = repository.link();
+        let workspace_dir = path.join("workspace");
+        let mut workspace = Workspace::open(&workspace_dir).unwrap();
+        assert!(crate::tests::update_workspace(&mut workspace, &link, &v1).is_err());
+        assert!(fs::symlink_metadata(workspace_dir.join("d/x")).is_err());
+
+        // files are never written through a symbolic link to a directory
+        let outside = path.join("outside");
+        fs::create_dir_all(&outside).unwrap();
+        let workspace_dir = path.join("workspace_linked");
+        fs::create_dir_all(&workspace_dir).unwrap();
+        symlink("../outside", workspace_dir.join("d")).unwrap();
+        let mut workspace = Workspace::open(&workspace_dir).unwrap();
+        assert!(crate::tests::update_workspace(&mut workspace, &link, &v1).is_err());
+        assert_eq!(fs::read_dir(&outside).unwrap().count(), 0);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn file_mode_and_mtime() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o7777;
+        let set_mode_and_mtime = |path: &Path, file_mode: u32, mtime: u64| {
+            let file = fs::File::options().write(true).open(path).unwrap();
+            file.set_permissions(fs::Permissions::from_mode(file_mode)).unwrap();
+            file.set_modified(std::time::UNIX_EPOCH + std::time::Duration::from_secs(mtime))
+                .unwrap();
+        };
+        crate::tests::init();
+        let rt = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        let v1 = CleanName::from_static_str("1");
+        let v2 = CleanName::from_static_str("2");
+        let path = crate::tests::tmp_dir("file_mode_and_mtime");
+        let source_v1 = path.join("source_v1");
+        let source_v2 = path.join("source_v2");
+        fs::create_dir_all(&source_v1).unwrap();
+        fs::create_dir_all(&source_v2).unwrap();
+        // the setuid bit isn't restored
+        for (name, v1_mode, v2_mode, v2_mtime) in
+            [("private.txt", 0o600, 0o640, 1_600_000_000), ("run.sh", 0o4750, 0o750, 1_700_000_000)]
+        {
+            fs::write(source_v1.join(name), name).unwrap();
+            set_mode_and_mtime(&source_v1.join(name), v1_mode, 1_600_000_000);
+            fs::write(source_v2.join(name), name).unwrap();
+            set_mode_and_mtime(&source_v2.join(name), v2_mode, v2_mtime);
+        }
+
+        let mut repository = crate::tests::init_repository(&path);
+        let versions = [(&v1, source_v1.as_path()), (&v2, source_v2.as_path())];
+        crate::tests::publish_versions(&mut repository, &path, &versions, || BuildOptions {
+            mtime: true,
+            ..BuildOptions::raw()
+        });
+
+        let workspace_dir = path.join("workspace");
+        let mut workspace = Workspace::open(&workspace_dir).unwrap();
+        let link = repository.link();
+        crate::tests::update_workspace(&mut workspace, &link, &v1).unwrap();
+        assert_eq!(mode(&workspace_dir.join("private.txt")), 0o600);
+        assert_eq!(mode(&workspace_dir.join("run.sh")), 0o750);
+        let modified = fs::metadata(workspace_dir.join("run.sh")).unwrap().modified().unwrap();
+        assert_eq!(modified, std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_600_000_000));
+
+        // mode drift is a check failure that the next update repairs
+        let run_sh = workspace_dir.join("run.sh");
+        fs::set_permissions(&run_sh, fs::Permissions::from_mode(0o777)).unwrap();
+        rt.block_on(workspace.check().try_for_each(|_| async { Ok(()) })).unwrap();
+        assert!(matches!(workspace.state(), metadata::v1::State::Corrupted { .. }));
+        crate::tests::update_workspace(&mut workspace, &link, &v1).unwrap();
+        assert_eq!(mode(&run_sh), 0o750);
+
+        // mode and mtime changes of unchanged files are applied by the update
+        crate::tests::update_workspace(&mut workspace, &link, &v2).unwrap();
+        assert_eq!(mode(&workspace_dir.join("private.txt")), 0o640);
+        let modified = fs::metadata(&run_sh).unwrap().modified().unwrap();
+        assert_eq!(modified, std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_700_000_000));
+        rt.block_on(workspace.check().try_for_each(|_| async { Ok(()) })).unwrap();
+        assert!(matches!(workspace.state(), metadata::v1::State::Stable { .. }));
+    }
+
+    #[test]
+    fn chunked_package() {
+        crate::tests::init();
+        let v1 = CleanName::from_static_str("1");
+        let v2 = CleanName::from_static_str("2");
+        let path = crate::tests::tmp_dir("chunked_package");
+        let mut state = 0x2545f4914f6cdd1du64;
+        let mut random = |len: usize| -> Vec<u8> {
+            (0..len)
+                .map(|_| {
+                    state ^= state << 13;
+                    state ^= state >> 7;
+                    state ^= state << 17;
+                    state as u8
+                })
+                .collect()
+        };
+        let big = random(512 * 1024);
+        let other = random(64 * 1024);
+        let source_v1 = path.join("source_v1");
+        fs::create_dir_all(&source_v1).unwrap();
+        fs::write(source_v1.join("big.bin"), &big).unwrap();
+        fs::write(source_v1.join("other.bin"), &other).unwrap();
+        fs::write(source_v1.join("copy.bin"), "small").unwrap();
+        let source_v2 = path.join("source_v2");
+        fs::create_dir_all(&source_v2).unwrap();
+        let mut big_v2 = big.clone();
+        big_v2.splice(200_000..200_000, random(100));
+        fs::write(source_v2.join("big.bin"), &big_v2).unwrap();
+        fs::write(source_v2.join("other.bin"), &other).unwrap();
+        fs::write(source_v2.join("copy.bin"), [&other[..], b"suffix"].concat()).unwrap();
+
+        let mut repository = crate::tests::init_repository(&path);
+        let versions = [(&v1, source_v1.as_path()), (&v2, source_v2.as_path())];
+        let builder = crate::tests::publish_versions(&mut repository, &path, &versions, || {
+            BuildOptions { chunking: Some(ChunkingOptions::new(4096)), ..BuildOptions::raw() }
+        });
+
+        let package_metadata =
+            repository.package_metadata(&builder.package_metadata_name()).unwrap();
+        let PackageMetadata::V2(package_metadata) = package_metadata else {
+            panic!("chunked packages must use v2 metadata");
+        };
+        for name in ["big.bin", "copy.bin"] {
+            let chunked = package_metadata
+                .operations
+                .iter()
+                .find_map(|op| match op {
+                    metadata::v2::Operation::Chunked(op) if op.common.path.as_str() == name => {
+                        Some(op)
+                    }
+                    _ => None,
+                })
+                .expect("chunked operation");
+            assert!(chunked.chunks.iter().any(|chunk| chunk.local_path.is_some()));
+            assert!(chunked.data_size < chunked.final_size / 4);
+        }
+
+        let workspace_dir = path.join("workspace");
+        let mut workspace = Workspace::open(&workspace_dir).unwrap();
+        let link = repository.link();
+        crate::tests::update_workspace(&mut workspace, &link, &v1).unwrap();
+        crate::tests::update_workspace(&mut workspace, &link, &v2).unwrap();
+        workspace.remove_metadata().unwrap();
+        crate::tests::assert_fs_eq(&source_v2, &workspace_dir);
+    }
+
+    #[test]
+    fn moved_files() {
+        crate::tests::init();
+        let rt = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        let v1 = CleanName::from_static_str("1");
+        let v2 = CleanName::from_static_str("2");
+        let path = crate::tests::tmp_dir("moved_files");
+        let source_v1 = path.join("source_v1");
+        fs::create_dir_all(source_v1.join("old")).unwrap();
+        fs::write(source_v1.join("old/a.txt"), "moved a").unwrap();
+        fs::write(source_v1.join("old/b.txt"), "moved b").unwrap();
+        fs::write(source_v1.join("c.txt"), "renamed c").unwrap();
+        let source_v2 = path.join("source_v2");
+        fs::create_dir_all(source_v2.join("new")).unwrap();
+        fs::write(source_v2.join("new/a.txt"), "moved a").unwrap();
+        fs::write(source_v2.join("new/b.txt"), "moved b").unwrap();
+        fs::write(source_v2.join("d.txt"), "renamed c").unwrap();
+
+        let mut repository = crate::tests::init_repository(&path);
+        let versions = [(&v1, source_v1.as_path()), (&v2, source_v2.as_path())];
+        let builder = crate::tests::publish_versions(&mut repository, &path, &versions, || {
+            BuildOptions { detect_moves: true, ..BuildOptions::raw() }
+        });
+
+        let package_metadata =
+            repository.package_metadata(&builder.package_metadata_name()).unwrap();
+        assert_eq!(package_metadata.package().size, 0);
+        let copies = package_metadata
+            .iter()
+            .filter(|op| matches!(op, metadata::v2::Operation::Copy(_)))
+            .count();
+        assert_eq!(copies, 3);
+
+        let workspace_dir = path.join("workspace");
+        let mut workspace = Workspace::open(&workspace_dir).unwrap();
+        let link = repository.link();
+        crate::tests::update_workspace(&mut workspace, &link, &v1).unwrap();
+        crate::tests::update_workspace(&mut workspace, &link, &v2).unwrap();
+
+        // the repair goes through both packages, the moved files are copied
+        // from their previous path again
+        fs::write(workspace_dir.join("new/a.txt"), "corrupted").unwrap();
+        rt.block_on(workspace.check().try_for_each(|_| async { Ok(()) })).unwrap();
+        assert!(matches!(workspace.state(), metadata::v1::State::Corrupted { .. }));
+        crate::tests::update_workspace(&mut workspace, &link, &v2).unwrap();
+        workspace.remove_metadata().unwrap();
+        crate::tests::assert_fs_eq(&source_v2, &workspace_dir);
+    }
+
+    #[test]
+    fn duplicate_files() {
+        crate::tests::init();
+        let rt = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        let v1 = CleanName::from_static_str("1");
+        let path = crate::tests::tmp_dir("duplicate_files");
+        let source_directory = path.join("source");
+        for dir in ["a", "b", "c"] {
+            fs::create_dir_all(source_directory.join(dir)).unwrap();
+            fs::write(source_directory.join(dir).join("shared.dll"), "shared content").unwrap();
+            fs::write(source_directory.join(dir).join("own.txt"), dir).unwrap();
+        }
+
+        let mut repository = crate::tests::init_repository(&path);
+        let versions = [(&v1, source_directory.as_path())];
+        let builder = crate::tests::publish_versions(&mut repository, &path, &versions, || {
+            BuildOptions { dedup_files: true, ..BuildOptions::raw() }
+        });
+
+        let package_metadata =
+            repository.package_metadata(&builder.package_metadata_name()).unwrap();
+        let copies: Vec<_> = package_metadata
+            .iter()
+            .filter_map(|op| match op {
+                metadata::v2::Operation::Copy(op) => Some(op.from_path.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(copies, ["a/shared.dll", "a/shared.dll"]);
+
+        let workspace_dir = path.join("workspace");
+        let mut workspace = Workspace::open(&workspace_dir).unwrap();
+        let link = repository.link();
+        crate::tests::update_workspace(&mut workspace, &link, &v1).unwrap();
+
+        // both the copy source and a copy are repaired
+        fs::remove_file(workspace_dir.join("a/shared.dll")).unwrap();
+        fs::write(workspace_dir.join("c/shared.dll"), "corrupted").unwrap();
+        rt.block_on(workspace.check().try_for_each(|_| async { Ok(()) })).unwrap();
+        assert!(matches!(workspace.state(), metadata::v1::State::Corrupted { .. }));
+        crate::tests::update_workspace(&mut workspace, &link, &v1).unwrap();
+        workspace.remove_metadata().unwrap();
+        crate::tests::assert_fs_eq(&source_directory, &workspace_dir);
+    }
+
+    #[test]
+    fn duplicate_files_repair() {
+        crate::tests::init();
+        let rt = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        let v1 = CleanName::from_static_str("1");
+        let v2 = CleanName::from_static_str("2");
+        let path = crate::tests::tmp_dir("duplicate_files_repair");
+        let source_v1 = path.join("source_v1");
+        fs::create_dir_all(&source_v1).unwrap();
+        fs::write(source_v1.join("a.dll"), "shared content").unwrap();
+        fs::write(source_v1.join("b.dll"), "shared content").unwrap();
+        let source_v2 = path.join("source_v2");
+        fs::create_dir_all(&source_v2).unwrap();
+        fs::write(source_v2.join("a.dll"), "a content v2").unwrap();
+        fs::write(source_v2.join("b.dll"), "shared content").unwrap();
+
+        let mut repository = crate::tests::init_repository(&path);
+        let versions = [(&v1, source_v1.as_path()), (&v2, source_v2.as_path())];
+        crate::tests::publish_versions(&mut repository, &path, &versions, || BuildOptions {
+            dedup_files: true,
+            ..BuildOptions::raw()
+        });
+
+        let workspace_dir = path.join("workspace");
+        let mut workspace = Workspace::open(&workspace_dir).unwrap();
+        let link = repository.link();
+        crate::tests::update_workspace(&mut workspace, &link, &v2).unwrap();
+
+        // the copy of the first package reads a.dll as it was in this package
+        fs::write(workspace_dir.join("b.dll"), "corrupted").unwrap();
+        rt.block_on(workspace.check().try_for_each(|_| async { Ok(()) })).unwrap();
+        assert!(matches!(workspace.state(), metadata::v1::State::Corrupted { .. }));
+        crate::tests::update_workspace(&mut workspace, &link, &v2).unwrap();
+        workspace.remove_metadata().unwrap();
+        crate::tests::assert_fs_eq(&source_v2, &workspace_dir);
+    }
+
+    #[test]
+    fn binary_metadata() {
+        crate::tests::init();
+        let v1 = CleanName::from_static_str("1");
+        let path = crate::tests::tmp_dir("binary_metadata");
+        let source_directory = path.join("source");
+        fs::create_dir_all(source_directory.join("dir")).unwrap();
+        fs::write(source_directory.join("file.txt"), "cbor content").unwrap();
+        fs::write(source_directory.join("dir/other.txt"), "other content").unwrap();
+
+        let mut repository = crate::tests::init_repository(&path);
+        repository.set_binary_metadata(true);
+        let versions = [(&v1, source_directory.as_path())];
+        let builder =
+            crate::tests::publish_versions(&mut repository, &path, &versions, BuildOptions::raw);
+
+        // the link must use the CBOR copy
+        let json_path = repository.dir().join(builder.package_metadata_name());
+        let cbor_path = repository.dir().join(format!("{}.cbor", builder.package_metadata_name()));
+        assert!(fs::metadata(&cbor_path).unwrap().len() < fs::metadata(&json_path).unwrap().len());
+        fs::remove_file(json_path).unwrap();
+
+        let workspace_dir = path.join("workspace");
+        let mut workspace = Workspace::open(&workspace_dir).unwrap();
+        crate::tests::update_workspace(&mut workspace, &repository.link(), &v1).unwrap();
+        workspace.remove_metadata().unwrap();
+        crate::tests::assert_fs_eq(&source_directory, &workspace_dir);
+    }
+
+    #[test]
+    fn unregister_package_siblings() {
+        crate::tests::init();
+        let v1 = CleanName::from_static_str("1");
+        let path = crate::tests::tmp_dir("unregister_package_siblings");
+        let source_directory = path.join("source");
+        fs::create_dir_all(&source_directory).unwrap();
+        fs::write(source_directory.join("file.txt"), "unregistered content").unwrap();
+
+        let mut repository = crate::tests::init_repository(&path);
+        repository.set_binary_metadata(true);
+        repository.set_signing_key(signature::SigningKey::from_bytes(&[7u8; 32]));
+        let zstd = CoderOptions::from_static_str("zstd:19").unwrap();
+        repository.set_metadata_compression(Some(zstd)).unwrap();
+        let versions = [(&v1, source_directory.as_path())];
+        let builder =
+            crate::tests::publish_versions(&mut repository, &path, &versions, BuildOptions::raw);
+
+        let name = builder.package_metadata_name().to_string();
+        let siblings = [
+            format!("{}.sig", name),
+            format!("{}.zst", name),
+            format!("{}.cbor", name),
+            format!("{}.cbor.sig", name),
+            format!("{}.cbor.zst", name),
+        ];
+        for sibling in &siblings {
+            assert!(repository.dir().join(sibling).is_file(), "{} is missing", sibling);
+        }
+        repository.unregister_package(&name).unwrap();
+        assert!(repository.packages().unwrap().as_slice().is_empty());
+        for sibling in &siblings {
+            assert!(!repository.dir().join(sibling).exists(), "{} is left behind", sibling);
+        }
+        assert!(repository.dir().join(&name).is_file());
+    }
+
+    #[test]
+    fn compressed_metadata() {
+        use crate::link::RemoteRepository;
+
+        crate::tests::init();
+        let rt = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        let v1 = CleanName::from_static_str("1");
+        let path = crate::tests::tmp_dir("compressed_metadata");
+        let mut repository = Repository::new(path.clone());
+        let zstd = CoderOptions::from_static_str("zstd:19").unwrap();
+        repository.set_metadata_compression(Some(zstd)).unwrap();
+        repository.init().unwrap();
+        let version =
+            metadata::v1::Version { revision: v1.clone(), description: "v1".into(), yanked: None };
+        repository.register_version(&version).unwrap();
+        repository.set_current_version(None, &v1).unwrap();
+        assert!(!path.join("current.zst").exists());
+
+        // the link must use the compressed copy
+        let raw = fs::read(path.join(Versions::filename())).unwrap();
+        fs::write(path.join(Versions::filename()), "garbage").unwrap();
+        let versions = rt.block_on(repository.link().versions()).unwrap();
+        assert_eq!(versions.iter().count(), 1);
+        fs::write(path.join(Versions::filename()), raw).unwrap();
+
+        repository.set_metadata_compression(None).unwrap();
+        repository.register_version(&version).unwrap();
+        assert!(!path.join("versions.zst").exists());
+        rt.block_on(repository.link().versions()).unwrap();
+    }
+
+    #[test]
+    fn release_channels() {
+        use crate::link::RemoteRepository;
+
+        crate::tests::init();
+        let rt = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        let v1 = CleanName::from_static_str("1");
+        let v2 = CleanName::from_static_str("2");
+        let beta = CleanName::from_static_str("beta");
+        let path = crate::tests::tmp_dir("release_channels");
+        let mut repository = Repository::new(path.clone());
+        repository.init().unwrap();
+        crate::tests::register_version(&repository, &v1);
+        crate::tests::register_version(&repository, &v2);
+        repository.set_current_version(None, &v1).unwrap();
+        repository.set_current_version(Some(&beta), &v2).unwrap();
+        assert!(repository
+            .set_current_version(Some(&beta), &CleanName::from_static_str("3"))
+            .is_err());
+        // `current.beta.sig` is the signature of `current.beta`
+        for reserved in ["sig", "tmp", "beta.sig", "beta.cbor", "beta.tmp"] {
+            let channel = CleanName::new(reserved.to_string()).unwrap();
+            assert!(repository.set_current_version(Some(&channel), &v2).is_err());
+        }
+        // an interrupted write of the beta current version
+        fs::write(path.join("current.beta.tmp"), "").unwrap();
+        assert_eq!(repository.channels().unwrap().len(), 2);
+
+        assert_eq!(repository.current_version(None).unwrap().version(), &v1);
+        assert_eq!(repository.current_version(Some(&beta)).unwrap().version(), &v2);
+        let link = repository.link();
+        assert_eq!(rt.block_on(link.current_version()).unwrap().version(), &v1);
+        assert_eq!(rt.block_on(link.current_version_for(&beta)).unwrap().version(), &v2);
+    }
+
+    #[test]
+    fn yanked_version() {
+        crate::tests::init();
+        let v1 = CleanName::from_static_str("1");
+        let v2 = CleanName::from_static_str("2");
+        let path = crate::tests::tmp_dir("yanked_version");
+        let source_v1 = path.join("source_v1");
+        fs::create_dir_all(&source_v1).unwrap();
+        fs::write(source_v1.join("a.txt"), "v1").unwrap();
+        let source_v2 = path.join("source_v2");
+        fs::create_dir_all(&source_v2).unwrap();
+        fs::write(source_v2.join("a.txt"), "v2").unwrap();
+
+        let mut repository = crate::tests::init_repository(&path);
+        let versions = [(&v1, source_v1.as_path()), (&v2, source_v2.as_path())];
+        crate::tests::publish_versions(&mut repository, &path, &versions, BuildOptions::raw);
+        repository.set_current_version(None, &v1).unwrap();
+        assert!(repository.yank_version(&v1, "current").is_err());
+        repository.yank_version(&v2, "broken build").unwrap();
+        assert!(repository.set_current_version(None, &v2).is_err());
+        let versions = repository.versions().unwrap();
+        let yanked = versions.iter().find(|v| v.revision() == &v2).unwrap();
+        assert_eq!(yanked.yanked(), Some("broken build"));
+
+        let workspace_dir = path.join("workspace");
+        let mut workspace = Workspace::open(&workspace_dir).unwrap();
+        crate::tests::update_workspace(&mut workspace, &repository.link(), &v2).unwrap();
+        match workspace.state() {
+            metadata::v1::State::Stable { version } => assert_eq!(version, &v1),
+            _ => panic!("expected stable state"),
+        }
+
+        crate::tests::register_version(&repository, &v2);
+        repository.set_current_version(None, &v2).unwrap();
+    }
+
+    #[test]
+    fn verify_repository() {
+        crate::tests::init();
+        let v1 = CleanName::from_static_str("1");
+        let v2 = CleanName::from_static_str("2");
+        let path = crate::tests::tmp_dir("verify_repository");
+        let source_directory = path.join("source");
+        fs::create_dir_all(&source_directory).unwrap();
+        fs::write(source_directory.join("file.txt"), "verified content").unwrap();
+
+        let mut repository = crate::tests::init_repository(&path);
+        let versions = [(&v1, source_directory.as_path())];
+        let builder =
+            crate::tests::publish_versions(&mut repository, &path, &versions, BuildOptions::raw);
+        crate::tests::register_version(&repository, &v2);
+
+        let errors = repository.verify().unwrap();
+        assert!(
+            matches!(&errors[..], [VerifyError::UnreachableVersion { version }] if version == &v2)
+        );
+        repository.yank_version(&v2, "no package").unwrap();
+        assert!(repository.verify().unwrap().is_empty());
+
+        let package_metadata =
+            repository.package_metadata(&builder.package_metadata_name()).unwrap();
+        let data_path = repository.dir().join(package_metadata.package_data_name().as_str());
+        let mut data = fs::read(&data_path).unwrap();
+        data[0] ^= 0xff;
+        fs::write(&data_path, &data).unwrap();
+        let errors = repository.verify().unwrap();
+        assert!(matches!(&errors[..], [VerifyError::OperationHash { .. }]));
+
+        data.push(0);
+        fs::write(&data_path, &data).unwrap();
+        let errors = repository.verify().unwrap();
+        assert!(matches!(&errors[..], [VerifyError::PackageSize { .. }]));
+
+        // the data file is verified without metadata
+        let metadata_path = repository.dir().join(builder.package_metadata_name().as_str());
+        fs::remove_file(metadata_path).unwrap();
+        let errors = repository.verify().unwrap();
+        assert!(matches!(
+            &errors[..],
+            [VerifyError::PackageSize { .. }, VerifyError::PackageMetadata { .. }]
+        ));
+        fs::remove_file(&data_path).unwrap();
+        let errors = repository.verify().unwrap();
+        let [VerifyError::PackageData { name, data_name, .. }, VerifyError::PackageMetadata { .. }] =
+            &errors[..]
+        else {
+            panic!("expected missing package data and metadata, got {:?}", errors);
+        };
+        assert_eq!(name, &builder.package_metadata_name());
+        assert_eq!(data_name, &builder.package_data_name());
+    }
+
+    #[test]
+    fn signed_metadata() {
+        use crate::link::{RemoteRepository, RepositoryError};
+
+        crate::tests::init();
+        let rt = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        let v1 = CleanName::from_static_str("1");
+        let path = crate::tests::tmp_dir("signed_metadata");
+        let mut repository = Repository::new(path.clone());
+        repository.set_signing_key(signature::SigningKey::from_bytes(&[7u8; 32]));
+        repository.init().unwrap();
+        crate::tests::register_version(&repository, &v1);
+        repository.set_current_version(None, &v1).unwrap();
+
+        let link = repository.link();
+        let current = rt.block_on(link.current_version()).unwrap();
+        assert_eq!(current.version(), &v1);
+        rt.block_on(link.versions()).unwrap();
+
+        let mut versions = fs::read_to_string(path.join(Versions::filename())).unwrap();
+        versions.push('\n');
+        fs::write(path.join(Versions::filename()), versions).unwrap();
+        match rt.block_on(link.versions()) {
+            Err(RepositoryError::BadSignature { name }) => assert_eq!(name, "versions"),
+            res => panic!("expected bad signature, got {:?}", res.map(|_| ())),
+        }
+
+        // a signed file can't be served under another name
+        let channel = CleanName::from_static_str("beta");
+        let beta_filename = metadata::Current::channel_filename(Some(&channel));
+        fs::copy(path.join("current"), path.join(&beta_filename)).unwrap();
+        fs::copy(path.join("current.sig"), path.join(format!("{}.sig", beta_filename))).unwrap();
+        match rt.block_on(link.current_version_for(&channel)) {
+            Err(RepositoryError::BadSignature { name }) => assert_eq!(name, beta_filename),
+            res => panic!("expected bad signature, got {:?}", res.map(|_| ())),
+        }
+
+        // a stale signed file can't be replayed, even by another link
+        let stale_current = fs::read(path.join("current")).unwrap();
+        let stale_signature = fs::read(path.join("current.sig")).unwrap();
+        let v2 = CleanName::from_static_str("2");
+        crate::tests::register_version(&repository, &v2);
+        repository.set_current_version(None, &v2).unwrap();
+        // the file is read again until its new signature is written
+        let signature_path = path.join("current.sig");
+        let signature = fs::read(&signature_path).unwrap();
+        fs::write(&signature_path, &stale_signature).unwrap();
+        let writer = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            fs::write(signature_path, signature).unwrap();
+        });
+        assert_eq!(rt.block_on(link.current_version()).unwrap().version(), &v2);
+        writer.join().unwrap();
+        fs::write(path.join("current"), stale_current).unwrap();
+        fs::write(path.join("current.sig"), stale_signature).unwrap();
+        match rt.block_on(link.current_version()) {
+            Err(RepositoryError::StaleSignature { name, .. }) => assert_eq!(name, "current"),
+            res => panic!("expected stale signature, got {:?}", res.map(|_| ())),
+        }
+        let other_link = repository.link();
+        rt.block_on(other_link.current_version()).unwrap();
+        other_link.expect_signed_versions(&link.signed_versions());
+        assert!(rt.block_on(other_link.current_version()).is_err());
+
+        let mut unsigned_link = crate::link::FileRepository::new(path);
+        rt.block_on(unsigned_link.versions()).unwrap();
+        unsigned_link.set_public_key(signature::SigningKey::from_bytes(&[8u8; 32]).verifying_key());
+        assert!(rt.block_on(unsigned_link.current_version()).is_err());
+    }
 }
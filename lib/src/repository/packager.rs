@@ -1,7 +1,7 @@
-use std::collections::BTreeMap;
+use std::collections::{hash_map, BTreeMap, HashMap};
 use std::io::{Read, Write};
 use std::num::NonZeroUsize;
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
 use std::pin::Pin;
 use std::sync::Arc;
 use std::{fmt, fs};
@@ -10,9 +10,10 @@ use futures::prelude::*;
 use io::BUFFER_SIZE;
 use tracing::{debug, error, instrument, span, Level};
 
+use super::chunker::{self, ChunkingOptions, LocalChunks};
 use super::progress::{BuildProgress, BuildStage, BuildWorkerProgress, SharedBuildProgress};
 use crate::codecs::{CheckCoder, CoderOptions};
-use crate::metadata::{self, CleanName, CleanPath, Operation, Package, Sha1Hash};
+use crate::metadata::{self, CleanName, CleanPath, Hash, HashAlgorithm, Operation, Package};
 use crate::sync::watch_progress;
 use crate::{io, Repository};
 
@@ -26,6 +27,8 @@ pub struct PackageBuilder {
     pub source_directory: PathBuf,
     /// Previous version revision and directory if targeting a patch package
     pub previous: Option<(CleanName, PathBuf)>,
+    /// Platform the package is built for, `None` if it is platform independent
+    pub platform: Option<CleanName>,
     /// Number of threads to use for building
     pub num_threads: NonZeroUsize,
     /// Shared build options
@@ -85,6 +88,7 @@ impl PackageBuilder {
             source_version,
             source_directory,
             previous: None,
+            platform: None,
             num_threads: NonZeroUsize::new(num_cpus::get()).expect(">= 1"),
             options: Arc::new(BuildOptions::default()),
         }
@@ -94,6 +98,10 @@ impl PackageBuilder {
         self.previous = Some((prev_version, prev_directory));
     }
 
+    pub fn set_platform(&mut self, platform: Option<CleanName>) {
+        self.platform = platform;
+    }
+
     pub fn set_num_threads(&mut self, num_threads: usize) {
         self.num_threads = NonZeroUsize::new(num_threads.max(1)).expect(">= 1");
     }
@@ -107,6 +115,9 @@ impl PackageBuilder {
             from: self.previous.as_ref().map(|(rev, _)| rev.clone()),
             to: self.source_version.to_owned(),
             size: 0,
+            platform: self.platform.clone(),
+            hints: metadata::v1::PackageHints::default(),
+            metadata_hash: None,
         }
     }
 
@@ -163,8 +174,45 @@ impl PackageBuilder {
         let previous = self.previous.clone();
         let options = self.options.clone();
         let tasks = tokio::task::spawn_blocking(move || -> Result<_, BuildError> {
-            let mut task_builder = BuildTaskBuilder { tasks: Vec::new() };
+            let local_chunks = match (&options.chunking, &previous) {
+                (Some(chunking), Some((_version, previous_directory))) => {
+                    let mut local_chunks = LocalChunks::new();
+                    index_unchanged_files(
+                        chunking,
+                        options.hash,
+                        &source_directory,
+                        previous_directory,
+                        Path::new(""),
+                        &mut local_chunks,
+                    )
+                    .map_err(BuildError::BuildTaskList)?;
+                    Some(Arc::new(local_chunks))
+                }
+                _ => None,
+            };
+            let moved = match &previous {
+                Some((_version, previous_directory)) if options.detect_moves => {
+                    find_moved_files(options.hash, &source_directory, previous_directory)
+                        .map_err(BuildError::BuildTaskList)?
+                }
+                _ => BTreeMap::new(),
+            };
+            let duplicates = match options.dedup_files {
+                true => find_duplicate_files(options.hash, &source_directory)
+                    .map_err(BuildError::BuildTaskList)?,
+                false => HashMap::new(),
+            };
+            let mut task_builder = BuildTaskBuilder {
+                tasks: Vec::new(),
+                local_chunks,
+                moved,
+                duplicates,
+                written: HashMap::new(),
+            };
             fs::create_dir_all(&build_directory).map_err(BuildError::BuildTaskList)?;
+            task_builder
+                .push_moved(&options, &build_directory, &source_directory)
+                .map_err(BuildError::BuildTaskList)?;
             task_builder
                 .push_dir(
                     &options,
@@ -286,8 +334,8 @@ impl PackageBuilder {
                 .flush()
                 .map_err(|err| BuildError::PackageCreateError { path: path(), err })?;
 
-            let package_metadata_v1 =
-                metadata::PackageMetadata::V1 { package: package_v1.clone(), operations };
+            let package_metadata =
+                metadata::PackageMetadata::new(package_v1.clone(), ctx.options.hash, operations);
 
             {
                 let path = || metadata_path.display().to_string().into_boxed_str();
@@ -297,7 +345,7 @@ impl PackageBuilder {
                     .create_new(true)
                     .open(&metadata_path)
                     .map_err(meta_err)?;
-                serde_json::to_writer_pretty(&mut metadata_file, &package_metadata_v1)
+                serde_json::to_writer_pretty(&mut metadata_file, &package_metadata)
                     .map_err(|err| meta_err(err.into()))?;
                 metadata_file.flush().map_err(meta_err)?;
             }
@@ -364,6 +412,45 @@ fn is_exe(filename: &str, _metadata: &fs::Metadata) -> bool {
     filename.ends_with(".exe")
 }
 
+#[cfg(unix)]
+fn file_mode(metadata: &fs::Metadata) -> Option<u32> {
+    use std::os::unix::fs::PermissionsExt;
+    Some(metadata.permissions().mode() & 0o777)
+}
+
+#[cfg(not(unix))]
+fn file_mode(_metadata: &fs::Metadata) -> Option<u32> {
+    None
+}
+
+/// Operation common part of the `src_path` file
+fn file_common(
+    options: &BuildOptions,
+    path: CleanPath,
+    src_t: FileType,
+    src_path: &Path,
+) -> io::Result<metadata::v2::Common> {
+    let metadata = fs::metadata(src_path)?;
+    let mtime = match options.mtime {
+        true => metadata.modified()?.duration_since(std::time::UNIX_EPOCH).ok(),
+        false => None,
+    };
+    Ok(metadata::v2::Common {
+        path,
+        slice: None,
+        exe: src_t.is_exe(),
+        mode: file_mode(&metadata),
+        mtime: mtime.map(|mtime| mtime.as_secs()),
+        slice_handler: None,
+    })
+}
+
+/// Check operations don't change files, permission bits and modification time
+/// changes require a patch operation
+fn same_attributes(src: &metadata::v2::Common, pre: &metadata::v2::Common) -> bool {
+    src.exe == pre.exe && src.mode == pre.mode && src.mtime == pre.mtime
+}
+
 #[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
 pub enum FileType {
     #[default]
@@ -371,11 +458,13 @@ pub enum FileType {
     Dir,
     File,
     Exe,
+    Symlink,
 }
 
 impl FileType {
     fn new(filename: &str, metadata: &fs::Metadata) -> io::Result<Self> {
         match metadata.file_type() {
+            t if t.is_symlink() => Ok(FileType::Symlink),
             t if t.is_dir() => Ok(FileType::Dir),
             t if t.is_file() => {
                 if is_exe(filename, metadata) {
@@ -399,6 +488,10 @@ impl FileType {
     fn is_exe(self) -> bool {
         matches!(self, FileType::Exe)
     }
+
+    fn is_symlink(self) -> bool {
+        matches!(self, FileType::Symlink)
+    }
 }
 
 #[derive(Debug, Default)]
@@ -431,16 +524,16 @@ fn ordered_dir_list(
 }
 
 struct BuiltOperation {
-    pub operation: metadata::v1::Operation,
+    pub operation: metadata::v2::Operation,
     pub data_path: Option<PathBuf>,
 }
 
 impl BuiltOperation {
-    fn no_data(operation: metadata::v1::Operation) -> Self {
+    fn no_data(operation: metadata::v2::Operation) -> Self {
         Self { operation, data_path: None }
     }
 
-    fn with_data(data_path: PathBuf, operation: metadata::v1::Operation) -> Self {
+    fn with_data(data_path: PathBuf, operation: metadata::v2::Operation) -> Self {
         Self { operation, data_path: Some(data_path) }
     }
 }
@@ -449,6 +542,27 @@ impl BuiltOperation {
 pub struct BuildOptions {
     pub compressors: Vec<CoderOptions>,
     pub patchers: Vec<CoderOptions>,
+    /// Hash algorithm of the package content
+    ///
+    /// Packages using something else than sha1 requires v2 metadata support.
+    pub hash: HashAlgorithm,
+    /// Record files modification time so they are restored on update
+    pub mtime: bool,
+    /// Split modified files with content defined chunking instead of patching them
+    ///
+    /// Only chunks missing from the previous version are stored in the package.
+    /// Chunked packages requires v2 metadata support.
+    pub chunking: Option<ChunkingOptions>,
+    /// Copy files moved since the previous version from their previous path
+    /// instead of adding them again
+    ///
+    /// Packages with moved files requires v2 metadata support.
+    pub detect_moves: bool,
+    /// Copy files with the same content as a file written before them by the
+    /// package instead of storing them again
+    ///
+    /// Packages with duplicate files requires v2 metadata support.
+    pub dedup_files: bool,
 }
 
 impl BuildOptions {
@@ -456,6 +570,11 @@ impl BuildOptions {
         Self {
             compressors: vec![CoderOptions::new("raw".to_string())],
             patchers: vec![CoderOptions::new("raw".to_string())],
+            hash: HashAlgorithm::default(),
+            mtime: false,
+            chunking: None,
+            detect_moves: false,
+            dedup_files: false,
         }
     }
 }
@@ -475,6 +594,11 @@ impl Default for BuildOptions {
                 CoderOptions::new("zstd".to_string()),
                 CoderOptions::new("raw".to_string()),
             ],
+            hash: HashAlgorithm::default(),
+            mtime: false,
+            chunking: None,
+            detect_moves: false,
+            dedup_files: false,
         }
     }
 }
@@ -511,6 +635,14 @@ type BuildTaskBuilderResult =
     Box<dyn FnOnce(&mut BuildTaskCtx) -> Result<BuiltOperation, BuildError> + Send>;
 struct BuildTaskBuilder {
     tasks: Vec<BuildTaskBuilderResult>,
+    /// Chunks of the previous version unchanged files, if chunking is enabled
+    local_chunks: Option<Arc<LocalChunks>>,
+    /// Files moved since the previous version, by new path
+    moved: BTreeMap<CleanPath, MovedFile>,
+    /// Size and hash of source files sharing their content with other ones
+    duplicates: HashMap<CleanPath, (u64, Hash)>,
+    /// First file written by the package for each duplicated content
+    written: HashMap<(u64, Hash), CleanPath>,
 }
 
 impl BuildTaskBuilder {
@@ -538,6 +670,66 @@ impl BuildTaskBuilder {
         }));
     }
 
+    /// Copy moved files before anything else, so their previous path is still
+    /// there
+    fn push_moved(&mut self, options: &BuildOptions, tmp_dir: &Path, src: &Path) -> io::Result<()> {
+        for (path, moved) in self.moved.clone() {
+            let src_path = src.join(&path);
+            let filename = src_path.file_name().and_then(|f| f.to_str()).unwrap_or_default();
+            let src_t = FileType::new(filename, &fs::symlink_metadata(&src_path)?)?;
+            let tmp_path = tmp_dir.join(format!("task_{}", self.tasks.len()));
+            let common = file_common(options, path.clone(), src_t, &src_path)?;
+            let mut src_slices = slices(options, common, src_path, tmp_path)?;
+            if src_slices.len() != 1 || src_slices[0].common.slice.is_some() {
+                // sliced files are added as usual
+                self.moved.remove(&path);
+                continue;
+            }
+            let common = src_slices.remove(0).common;
+            self.push_copy(common, moved.from_path, moved.size, moved.hash);
+        }
+        Ok(())
+    }
+
+    fn push_copy(
+        &mut self,
+        common: metadata::v2::Common,
+        from_path: CleanPath,
+        size: u64,
+        hash: Hash,
+    ) {
+        self.push(&format!("copy {} -> {}", from_path, common.path), move |_| {
+            Ok(BuiltOperation::no_data(metadata::v2::Operation::Copy(metadata::v2::Copy {
+                common,
+                from_path,
+                final_size: size,
+                final_hash: hash,
+            })))
+        });
+    }
+
+    /// Returns the first written file with the same content as `path`
+    ///
+    /// If there is none, `path` becomes the one other duplicates are copied from.
+    /// Sliced files are never copied nor copied from.
+    fn duplicate_of(
+        &mut self,
+        path: &CleanPath,
+        src_slices: &[Slice],
+    ) -> Option<(CleanPath, u64, Hash)> {
+        if src_slices.len() != 1 || src_slices[0].common.slice.is_some() {
+            return None;
+        }
+        let (size, hash) = self.duplicates.get(path)?.clone();
+        match self.written.entry((size, hash.clone())) {
+            hash_map::Entry::Occupied(entry) => Some((entry.get().clone(), size, hash)),
+            hash_map::Entry::Vacant(entry) => {
+                entry.insert(path.clone());
+                None
+            }
+        }
+    }
+
     fn push_dir(
         &mut self,
         options: &BuildOptions,
@@ -556,10 +748,11 @@ impl BuildTaskBuilder {
             let relative = relative.join(&filename);
             let path = CleanPath::new(relative.to_str().unwrap().to_string())
                 .map_err(|_| err(&format!("weird characters in path {:?}", relative)))?;
-            if pre_t.is_file() && !src_t.is_file() {
+            if (pre_t.is_file() && !src_t.is_file()) || (pre_t.is_symlink() && !src_t.is_symlink())
+            {
                 let path = path.to_owned();
                 self.push(&format!("rm {}", path), move |_| {
-                    Ok(BuiltOperation::no_data(metadata::v1::Operation::Rm(metadata::v1::Rm {
+                    Ok(BuiltOperation::no_data(metadata::v2::Operation::Rm(metadata::v2::Rm {
                         path,
                         slice: None,
                     })))
@@ -568,25 +761,29 @@ impl BuildTaskBuilder {
             if src_t.is_dir() && !pre_t.is_dir() {
                 let path = path.to_owned();
                 self.push(&format!("mkdir {}", path), move |_| {
-                    Ok(BuiltOperation::no_data(metadata::v1::Operation::MkDir { path }))
+                    Ok(BuiltOperation::no_data(metadata::v2::Operation::MkDir { path }))
                 });
             }
             if src_t.is_file() && !pre_t.is_file() {
-                // add file
+                // add || copy file
                 let path = path.to_owned();
                 let src_path = src.expect("src is_file").join(&filename);
                 let tmp_path = tmp_dir.join(format!("task_{}", self.tasks.len()));
-                let common = metadata::v1::Common {
-                    path: path.clone(),
-                    slice: None,
-                    exe: src_t.is_exe(),
-                    slice_handler: None,
-                };
-                for src_slice in slices(options, common, src_path, tmp_path)? {
-                    self.push(
-                        &format!("add {} [{} {}]", path, src_slice.offset, src_slice.size),
-                        move |ctx| add_file(ctx, src_slice),
-                    );
+                let common = file_common(options, path.clone(), src_t, &src_path)?;
+                let src_slices = slices(options, common, src_path, tmp_path)?;
+                let duplicate_of = self.duplicate_of(&path, &src_slices);
+                if self.moved.contains_key(&path) {
+                    // already copied from its previous path
+                } else if let Some((from_path, size, hash)) = duplicate_of {
+                    let common = src_slices.into_iter().next().expect("one slice").common;
+                    self.push_copy(common, from_path, size, hash);
+                } else {
+                    for src_slice in src_slices {
+                        self.push(
+                            &format!("add {} [{} {}]", path, src_slice.offset, src_slice.size),
+                            move |ctx| add_file(ctx, src_slice),
+                        );
+                    }
                 }
             }
             if src_t.is_file() && pre_t.is_file() {
@@ -595,18 +792,34 @@ impl BuildTaskBuilder {
                 let src_path = src.expect("src is_file").join(&filename);
                 let pre_path = pre.expect("pre is_file").join(&filename);
                 let tmp_path = tmp_dir.join(format!("task_{}", self.tasks.len()));
-                let common = metadata::v1::Common {
-                    path: path.clone(),
-                    slice: None,
-                    exe: src_t.is_exe(),
-                    slice_handler: None,
-                };
-                let pre_slices = slices(options, common.clone(), pre_path, tmp_path.clone())?;
-                for src_slice in slices(options, common, src_path, tmp_path)? {
+                let common = file_common(options, path.clone(), src_t, &src_path)?;
+                let pre_common = file_common(options, path.clone(), pre_t, &pre_path)?;
+                let pre_slices = slices(options, pre_common, pre_path.clone(), tmp_path.clone())?;
+                let src_slices = slices(options, common, src_path, tmp_path)?;
+                if let Some((from_path, size, hash)) = self.duplicate_of(&path, &src_slices) {
+                    // the previous content might already be the right one
+                    if size != pre_slices.iter().map(|s| s.size).sum::<u64>()
+                        || hash != hash_file(options.hash, &pre_path)?
+                    {
+                        let common = src_slices.into_iter().next().expect("one slice").common;
+                        self.push_copy(common, from_path, size, hash);
+                        continue;
+                    }
+                }
+                for src_slice in src_slices {
                     let pre_slice = pre_slices
                         .iter()
                         .find(|pre_slice| pre_slice.common.slice == src_slice.common.slice);
                     match pre_slice {
+                        Some(pre_slice)
+                            if self.local_chunks.is_some() && src_slice.common.slice.is_none() =>
+                        {
+                            let pre_slice = pre_slice.clone();
+                            let local_chunks = self.local_chunks.clone().expect("local chunks");
+                            self.push(&format!("chunk {}", path), move |ctx| {
+                                chunk_file(ctx, src_slice, pre_slice, &local_chunks)
+                            });
+                        }
                         Some(pre_slice) => {
                             let pre_slice = pre_slice.clone();
                             self.push(
@@ -660,7 +873,33 @@ impl BuildTaskBuilder {
             if pre_t.is_dir() && !src_t.is_dir() {
                 let path = path.to_owned();
                 self.push(&format!("rmdir {}", path), |_| {
-                    Ok(BuiltOperation::no_data(metadata::v1::Operation::RmDir { path }))
+                    Ok(BuiltOperation::no_data(metadata::v2::Operation::RmDir { path }))
+                });
+            }
+
+            if src_t.is_symlink() {
+                let src_path = src.expect("src is_symlink").join(&filename);
+                let target = fs::read_link(&src_path)?;
+                let target = target
+                    .to_str()
+                    .ok_or_else(|| err(&format!("weird characters in symlink {:?}", src_path)))?
+                    .replace('\\', "/");
+                if !metadata::is_contained_symlink(&path, &target) {
+                    return Err(err(&format!(
+                        "symlink {} target {} escapes the source directory",
+                        path, target
+                    )));
+                }
+                if pre_t.is_symlink() {
+                    // unchanged links are kept as is, like unchanged files
+                    let pre_target = fs::read_link(pre.expect("pre is_symlink").join(&filename))?;
+                    if pre_target.to_str().map(|t| t.replace('\\', "/")) == Some(target.clone()) {
+                        continue;
+                    }
+                }
+                let path = path.to_owned();
+                self.push(&format!("symlink {}", path), move |_| {
+                    Ok(BuiltOperation::no_data(metadata::v2::Operation::Symlink { path, target }))
                 });
             }
         }
@@ -673,16 +912,20 @@ struct Encoded<'a> {
     encoder_options: &'a CoderOptions,
     path: PathBuf,
     data_size: u64,
-    data_sha1: Sha1Hash,
+    data_hash: Hash,
     final_size: u64,
-    final_sha1: Sha1Hash,
+    final_hash: Hash,
 }
 
 #[instrument(skip(ctx, encoders_options, mk_encoder))]
 fn best_encoder<'a>(
     ctx: &mut BuildTaskCtx,
     encoders_options: &'a [CoderOptions],
-    mk_encoder: impl Fn(&CoderOptions, fs::File) -> io::Result<CheckCoder<fs::File, io::CheckSha1Size>>,
+    mk_encoder: impl Fn(
+        &CoderOptions,
+        fs::File,
+        io::CheckHashSize,
+    ) -> io::Result<CheckCoder<fs::File, io::CheckHashSize>>,
     src_slice: &Slice,
 ) -> io::Result<Encoded<'a>> {
     let mut best: Option<Encoded<'a>> = None;
@@ -697,7 +940,8 @@ fn best_encoder<'a>(
         enc_path.push(format!(".{}", encoder_options.name()));
         let mut src_file = src_slice.open()?;
         let enc_file = fs::File::create(&enc_path)?;
-        let mut encoder = mk_encoder(encoder_options, enc_file)?;
+        let checks = io::CheckHashSize::new(ctx.options.hash);
+        let mut encoder = mk_encoder(encoder_options, enc_file, checks)?;
         let mut buffer = [0u8; io::BUFFER_SIZE];
         loop {
             let read = src_file.read(&mut buffer)?;
@@ -711,10 +955,10 @@ fn best_encoder<'a>(
         encoder.flush()?;
         let input_checks = encoder.input_checks();
         let final_size = input_checks.bytes;
-        let final_sha1 = input_checks.sha1();
+        let final_hash = input_checks.hash();
         let mut output_checks = encoder.finish()?.check;
         let data_size = output_checks.bytes;
-        let data_sha1 = output_checks.sha1();
+        let data_hash = output_checks.hash();
 
         let ratio = (data_size * 100) / final_size;
 
@@ -722,9 +966,9 @@ fn best_encoder<'a>(
             path: PathBuf::from(&enc_path),
             encoder_options,
             data_size,
-            data_sha1,
+            data_hash,
             final_size,
-            final_sha1,
+            final_hash,
         };
 
         io::assert_eq(encoded.final_size, src_slice.size, "src file size")?;
@@ -759,7 +1003,7 @@ fn best_encoder<'a>(
 
 #[derive(Debug, Clone)]
 struct Slice {
-    common: metadata::v1::Common,
+    common: metadata::v2::Common,
     src_path: PathBuf,
     tmp_path: PathBuf,
     offset: u64,
@@ -774,7 +1018,7 @@ impl Slice {
 
 fn slices(
     _options: &BuildOptions,
-    common: metadata::v1::Common,
+    common: metadata::v2::Common,
     src_path: PathBuf,
     tmp_path: PathBuf,
 ) -> io::Result<Vec<Slice>> {
@@ -790,7 +1034,7 @@ fn slices(
 
 #[cfg(feature = "ue4pak")]
 fn ue4pak_slices(
-    mut common: metadata::v1::Common,
+    mut common: metadata::v2::Common,
     src_path: PathBuf,
     tmp_path: PathBuf,
 ) -> io::Result<Vec<Slice>> {
@@ -809,7 +1053,7 @@ fn ue4pak_slices(
     let mut cuts = Vec::new();
 
     let new_cut = |path: &str, offset: u64| {
-        let slice = CleanPath::new(Sha1Hash::digest(path.as_bytes()).to_string())
+        let slice = CleanPath::new(metadata::Sha1Hash::digest(path.as_bytes()).to_string())
             .expect("sha1 is cleanpath valid");
         (offset, slice)
     };
@@ -836,7 +1080,7 @@ fn ue4pak_slices(
     let mut prev = it.next().unwrap();
     for cut in it {
         let slice = Slice {
-            common: metadata::v1::Common { slice: Some(prev.1), ..common.clone() },
+            common: metadata::v2::Common { slice: Some(prev.1), ..common.clone() },
             src_path: src_path.clone(),
             tmp_path: tmp_path.clone(),
             offset: prev.0,
@@ -846,7 +1090,7 @@ fn ue4pak_slices(
         prev = cut;
     }
     let slice = Slice {
-        common: metadata::v1::Common { slice: Some(prev.1), ..common },
+        common: metadata::v2::Common { slice: Some(prev.1), ..common },
         src_path,
         tmp_path,
         offset: prev.0,
@@ -863,19 +1107,19 @@ fn add_file(ctx: &mut BuildTaskCtx, src_slice: Slice) -> Result<BuiltOperation,
     let best_compressor = best_encoder(
         ctx,
         &options.compressors,
-        |encoder_options, enc_file| CheckCoder::encoder(encoder_options, enc_file),
+        |encoder_options, enc_file, checks| CheckCoder::encoder(encoder_options, enc_file, checks),
         &src_slice,
     )?;
-    let op = metadata::v1::Operation::Add(metadata::v1::Add {
+    let op = metadata::v2::Operation::Add(metadata::v2::Add {
         common: src_slice.common,
         data_offset: 0,
         data_size: best_compressor.data_size,
-        data_sha1: best_compressor.data_sha1,
+        data_hash: best_compressor.data_hash,
         data_compression: CleanName::new(best_compressor.encoder_options.name().to_string())
             .expect("supported encoder name to be clean"),
         final_offset: 0,
         final_size: best_compressor.final_size,
-        final_sha1: best_compressor.final_sha1,
+        final_hash: best_compressor.final_hash,
     });
 
     Ok(BuiltOperation::with_data(best_compressor.path, op))
@@ -887,9 +1131,11 @@ fn patch_file(
     pre_slice: Slice,
 ) -> Result<BuiltOperation, io::Error> {
     let options = ctx.options.clone();
-    let mut are_equals = src_slice.size == pre_slice.size;
+    let mut are_equals =
+        src_slice.size == pre_slice.size && same_attributes(&src_slice.common, &pre_slice.common);
 
-    let mut pre_file = io::CheckReader::new(pre_slice.open()?);
+    let mut pre_file =
+        io::CheckReader::with_check(pre_slice.open()?, io::CheckHashSize::new(options.hash));
     let mut pre_buffer = [0u8; BUFFER_SIZE];
     if are_equals {
         // same len, let's check if content is the same
@@ -905,12 +1151,12 @@ fn patch_file(
         }
         if are_equals {
             // same content
-            return Ok(BuiltOperation::no_data(metadata::v1::Operation::Check(
-                metadata::v1::Check {
+            return Ok(BuiltOperation::no_data(metadata::v2::Operation::Check(
+                metadata::v2::Check {
                     common: src_slice.common,
                     local_offset: 0,
                     local_size: pre_file.read_bytes(),
-                    local_sha1: pre_file.sha1(),
+                    local_hash: pre_file.hash(),
                 },
             )));
         }
@@ -921,7 +1167,7 @@ fn patch_file(
             break;
         }
     }
-    let pre_sha1 = pre_file.sha1();
+    let pre_hash = pre_file.hash();
     io::assert_eq(pre_file.read_bytes(), pre_slice.size, "pre file size")?;
     drop(pre_file);
 
@@ -930,21 +1176,23 @@ fn patch_file(
     let best_patcher = best_encoder(
         ctx,
         &options.patchers,
-        |patcher_options, enc_file| {
+        |patcher_options, enc_file, checks| {
             let pre_file = pre_slice.open()?;
-            CheckCoder::patch_encoder(patcher_options, pre_file, enc_file)
+            CheckCoder::patch_encoder(patcher_options, pre_file, enc_file, checks)
         },
         &src_slice,
     )?;
     let best_compressor = best_encoder(
         ctx,
         &options.compressors,
-        |encoder_options, enc_file| CheckCoder::encoder(encoder_options, enc_file),
+        |encoder_options, enc_file, checks| CheckCoder::encoder(encoder_options, enc_file, checks),
         &Slice {
-            common: metadata::v1::Common {
+            common: metadata::v2::Common {
                 path: CleanPath::from_static_str("unreachable"),
                 slice: None,
                 exe: false,
+                mode: None,
+                mtime: None,
                 slice_handler: None,
             },
             src_path: best_patcher.path.clone(),
@@ -955,35 +1203,336 @@ fn patch_file(
     )?;
     let op = if best_patcher.encoder_options.name() == "raw" {
         // i.e. patch is bigger than file
-        metadata::v1::Operation::Add(metadata::v1::Add {
+        metadata::v2::Operation::Add(metadata::v2::Add {
             common: src_slice.common,
             data_offset: 0,
             data_size: best_compressor.data_size,
-            data_sha1: best_compressor.data_sha1,
+            data_hash: best_compressor.data_hash,
             data_compression: CleanName::new(best_patcher.encoder_options.name().to_string())
                 .expect("supported encoder name to be clean"),
             final_offset: 0,
             final_size: best_patcher.final_size,
-            final_sha1: best_patcher.final_sha1,
+            final_hash: best_patcher.final_hash,
         })
     } else {
-        metadata::v1::Operation::Patch(metadata::v1::Patch {
+        metadata::v2::Operation::Patch(metadata::v2::Patch {
             common: src_slice.common,
             data_offset: 0,
             data_size: best_compressor.data_size,
-            data_sha1: best_compressor.data_sha1,
+            data_hash: best_compressor.data_hash,
             data_compression: CleanName::new(best_compressor.encoder_options.name().to_string())
                 .expect("supported encoder name to be clean"),
             patch_type: CleanName::new(best_patcher.encoder_options.name().to_string())
                 .expect("supported encoder name to be clean"),
             local_offset: 0,
             local_size: pre_slice.size,
-            local_sha1: pre_sha1,
+            local_hash: pre_hash,
             final_offset: 0,
             final_size: best_patcher.final_size,
-            final_sha1: best_patcher.final_sha1,
+            final_hash: best_patcher.final_hash,
         })
     };
 
     Ok(BuiltOperation::with_data(best_compressor.path, op))
 }
+
+/// Index the chunks of previous version files that are unchanged in the
+/// source directory
+///
+/// Those files are left untouched by the package, so any chunked operation can
+/// use their chunks.
+fn index_unchanged_files(
+    chunking: &ChunkingOptions,
+    hash: HashAlgorithm,
+    src: &Path,
+    pre: &Path,
+    relative: &Path,
+    local_chunks: &mut LocalChunks,
+) -> io::Result<()> {
+    for entry in fs::read_dir(pre)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        let relative = relative.join(entry.file_name());
+        let src_path = src.join(entry.file_name());
+        if file_type.is_dir() {
+            if fs::symlink_metadata(&src_path).is_ok_and(|m| m.is_dir()) {
+                index_unchanged_files(
+                    chunking,
+                    hash,
+                    &src_path,
+                    &entry.path(),
+                    &relative,
+                    local_chunks,
+                )?;
+            }
+        } else if file_type.is_file() && is_same_content(&src_path, &entry.path())? {
+            let path = CleanPath::new(relative.to_str().unwrap().to_string())
+                .map_err(|_| err(&format!("weird characters in path {:?}", relative)))?;
+            chunker::index(chunking, hash, &path, fs::File::open(entry.path())?, local_chunks)?;
+        }
+    }
+    Ok(())
+}
+
+/// Returns true if `src_path` is a file with the same content as `pre_path`
+fn is_same_content(src_path: &Path, pre_path: &Path) -> io::Result<bool> {
+    match fs::symlink_metadata(src_path) {
+        Ok(src) if src.is_file() && src.len() == fs::metadata(pre_path)?.len() => {}
+        _ => return Ok(false),
+    }
+    let mut src_file = fs::File::open(src_path)?;
+    let mut pre_file = fs::File::open(pre_path)?;
+    let mut src_buffer = [0u8; BUFFER_SIZE];
+    let mut pre_buffer = [0u8; BUFFER_SIZE];
+    loop {
+        let read = pre_file.read(&mut pre_buffer)?;
+        if read == 0 {
+            return Ok(true);
+        }
+        src_file.read_exact(&mut src_buffer[..read])?;
+        if src_buffer[..read] != pre_buffer[..read] {
+            return Ok(false);
+        }
+    }
+}
+
+/// Build a chunked operation
+///
+/// Chunks available in the previous content of the file or in unchanged files
+/// are copied locally, the other ones are stored in the package data.
+fn chunk_file(
+    ctx: &mut BuildTaskCtx,
+    src_slice: Slice,
+    pre_slice: Slice,
+    local_chunks: &LocalChunks,
+) -> Result<BuiltOperation, io::Error> {
+    let options = ctx.options.clone();
+    let chunking = options.chunking.as_ref().expect("chunking options");
+    let hash = options.hash;
+    ctx.set_len(pre_slice.size + src_slice.size);
+
+    let mut pre_file = io::CheckReader::with_check(pre_slice.open()?, io::CheckHashSize::new(hash));
+    let mut file_chunks = LocalChunks::new();
+    chunker::index(chunking, hash, &src_slice.common.path, &mut pre_file, &mut file_chunks)?;
+    let pre_size = pre_file.read_bytes();
+    let pre_hash = pre_file.hash();
+    io::assert_eq(pre_size, pre_slice.size, "pre file size")?;
+    ctx.inc(pre_size);
+
+    let mut data_path = src_slice.tmp_path.as_os_str().to_owned();
+    data_path.push(".chunks");
+    let data_path = PathBuf::from(data_path);
+    let mut data_file = io::BufWriter::new(fs::File::create(&data_path)?);
+    let mut src_file = io::CheckReader::with_check(src_slice.open()?, io::CheckHashSize::new(hash));
+    let mut chunks = Vec::new();
+    chunker::chunks(chunking, hash, &mut src_file, |_offset, chunk, chunk_hash| {
+        let (local_path, local_offset) =
+            match file_chunks.get(&chunk_hash).or_else(|| local_chunks.get(&chunk_hash)) {
+                Some((path, offset)) => (Some(path.clone()), *offset),
+                None => {
+                    data_file.write_all(chunk)?;
+                    (None, 0)
+                }
+            };
+        chunks.push(metadata::v2::Chunk {
+            hash: chunk_hash,
+            size: chunk.len() as u64,
+            local_path,
+            local_offset,
+        });
+        ctx.inc(chunk.len() as u64);
+        Ok(())
+    })?;
+    data_file.flush()?;
+    drop(data_file);
+    let final_size = src_file.read_bytes();
+    let final_hash = src_file.hash();
+    io::assert_eq(final_size, src_slice.size, "src file size")?;
+
+    if final_size == pre_size
+        && final_hash == pre_hash
+        && same_attributes(&src_slice.common, &pre_slice.common)
+    {
+        // same content
+        io::remove_file(&data_path)?;
+        return Ok(BuiltOperation::no_data(metadata::v2::Operation::Check(metadata::v2::Check {
+            common: src_slice.common,
+            local_offset: 0,
+            local_size: pre_size,
+            local_hash: pre_hash,
+        })));
+    }
+
+    let raw_size = fs::metadata(&data_path)?.len();
+    if raw_size == 0 {
+        // every chunk is available locally
+        io::remove_file(&data_path)?;
+        return Ok(BuiltOperation::no_data(metadata::v2::Operation::Chunked(
+            metadata::v2::Chunked {
+                common: src_slice.common,
+                data_offset: 0,
+                data_size: 0,
+                data_hash: hash.digest(&[]),
+                data_compression: CleanName::from_static_str("raw"),
+                chunks,
+                final_size,
+                final_hash,
+            },
+        )));
+    }
+
+    ctx.set_len(raw_size * options.compressors.len() as u64);
+    let best_compressor = best_encoder(
+        ctx,
+        &options.compressors,
+        |encoder_options, enc_file, checks| CheckCoder::encoder(encoder_options, enc_file, checks),
+        &Slice {
+            common: src_slice.common.clone(),
+            src_path: data_path.clone(),
+            tmp_path: data_path.clone(),
+            offset: 0,
+            size: raw_size,
+        },
+    )?;
+    io::remove_file(&data_path)?;
+    let op = metadata::v2::Operation::Chunked(metadata::v2::Chunked {
+        common: src_slice.common,
+        data_offset: 0,
+        data_size: best_compressor.data_size,
+        data_hash: best_compressor.data_hash,
+        data_compression: CleanName::new(best_compressor.encoder_options.name().to_string())
+            .expect("supported encoder name to be clean"),
+        chunks,
+        final_size,
+        final_hash,
+    });
+
+    Ok(BuiltOperation::with_data(best_compressor.path, op))
+}
+
+/// Previous version file now at another path
+#[derive(Clone)]
+struct MovedFile {
+    from_path: CleanPath,
+    size: u64,
+    hash: Hash,
+}
+
+/// Match files removed from the previous version with files added to the
+/// source directory by size and content hash
+///
+/// Added files replacing something else than a directory in the previous
+/// version are ignored, as copies are done before anything is removed.
+fn find_moved_files(
+    hash: HashAlgorithm,
+    src: &Path,
+    pre: &Path,
+) -> io::Result<BTreeMap<CleanPath, MovedFile>> {
+    let mut removed: HashMap<u64, Vec<(CleanPath, Option<Hash>)>> = HashMap::new();
+    walk_files(pre, Path::new(""), &mut |relative, metadata| {
+        if !fs::symlink_metadata(src.join(relative)).is_ok_and(|m| m.is_file()) {
+            removed.entry(metadata.len()).or_default().push((clean_path(relative)?, None));
+        }
+        Ok(())
+    })?;
+
+    let mut moved = BTreeMap::new();
+    if removed.is_empty() {
+        return Ok(moved);
+    }
+    walk_files(src, Path::new(""), &mut |relative, metadata| {
+        let candidates = match removed.get_mut(&metadata.len()) {
+            Some(candidates) => candidates,
+            None => return Ok(()),
+        };
+        let is_free = fs::symlink_metadata(pre.join(relative)).is_err()
+            && relative
+                .ancestors()
+                .all(|parent| fs::symlink_metadata(pre.join(parent)).map_or(true, |m| m.is_dir()));
+        if !is_free {
+            return Ok(());
+        }
+        let src_hash = hash_file(hash, &src.join(relative))?;
+        for (from_path, from_hash) in candidates.iter_mut() {
+            let from_hash = match from_hash {
+                Some(from_hash) => from_hash,
+                None => from_hash.insert(hash_file(hash, &pre.join(&*from_path))?),
+            };
+            if *from_hash == src_hash {
+                let moved_file = MovedFile {
+                    from_path: from_path.clone(),
+                    size: metadata.len(),
+                    hash: src_hash,
+                };
+                moved.insert(clean_path(relative)?, moved_file);
+                break;
+            }
+        }
+        Ok(())
+    })?;
+    Ok(moved)
+}
+
+/// Call `f` with the relative path of every regular file in `dir`
+fn walk_files(
+    dir: &Path,
+    relative: &Path,
+    f: &mut dyn FnMut(&Path, &fs::Metadata) -> io::Result<()>,
+) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        let relative = relative.join(entry.file_name());
+        if metadata.is_dir() {
+            walk_files(&entry.path(), &relative, f)?;
+        } else if metadata.is_file() {
+            f(&relative, &metadata)?;
+        }
+    }
+    Ok(())
+}
+
+/// Clean path of `relative`, components are separated by `/` on every platform
+fn clean_path(relative: &Path) -> io::Result<CleanPath> {
+    let components: Option<Vec<&str>> = relative
+        .components()
+        .map(|component| match component {
+            Component::Normal(name) => name.to_str(),
+            _ => None,
+        })
+        .collect();
+    components
+        .and_then(|components| CleanPath::new(components.join("/")).ok())
+        .ok_or_else(|| err(&format!("weird characters in path {:?}", relative)))
+}
+
+fn hash_file(algorithm: HashAlgorithm, path: &Path) -> io::Result<Hash> {
+    let file = fs::File::open(path)?;
+    let mut reader = io::CheckReader::with_check(file, io::CheckHashSize::new(algorithm));
+    io::copy(&mut reader, &mut io::sink())?;
+    Ok(reader.hash())
+}
+
+/// Find source files sharing their content with other source files
+fn find_duplicate_files(
+    hash: HashAlgorithm,
+    src: &Path,
+) -> io::Result<HashMap<CleanPath, (u64, Hash)>> {
+    let mut by_size: HashMap<u64, Vec<CleanPath>> = HashMap::new();
+    walk_files(src, Path::new(""), &mut |relative, metadata| {
+        by_size.entry(metadata.len()).or_default().push(clean_path(relative)?);
+        Ok(())
+    })?;
+
+    let mut duplicates = HashMap::new();
+    for (size, paths) in by_size.into_iter().filter(|(_, paths)| paths.len() > 1) {
+        let mut by_hash: HashMap<Hash, Vec<CleanPath>> = HashMap::new();
+        for path in paths {
+            by_hash.entry(hash_file(hash, &src.join(&path))?).or_default().push(path);
+        }
+        for (hash, paths) in by_hash.into_iter().filter(|(_, paths)| paths.len() > 1) {
+            duplicates.extend(paths.into_iter().map(|path| (path, (size, hash.clone()))));
+        }
+    }
+    Ok(duplicates)
+}
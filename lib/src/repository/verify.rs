@@ -0,0 +1,187 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::fs;
+use std::ops::Range;
+
+use super::Repository;
+use crate::io;
+use crate::metadata::{self, v2, CleanName, CleanPath, Hash, HashAlgorithm, Package};
+
+/// An inconsistency found by [`Repository::verify`]
+///
+/// `name` is the package metadata name.
+#[derive(Debug)]
+pub enum VerifyError {
+    /// Package metadata file is missing or can't be parsed
+    PackageMetadata { name: CleanName, err: io::Error },
+    /// Package data file `data_name` is missing or can't be read
+    PackageData { name: CleanName, data_name: CleanName, err: io::Error },
+    /// Package data file size differs from the registered package size
+    PackageSize { name: CleanName, expected: u64, found: u64 },
+    /// Operation data lies outside the package data file
+    OperationRange { name: CleanName, path: CleanPath, range: Range<u64>, size: u64 },
+    /// Operation data hash differs from the package data content
+    OperationHash { name: CleanName, path: CleanPath, expected: Hash, found: Hash },
+    /// `what` refers to a version that isn't registered
+    UnknownVersion { what: String, version: CleanName },
+    /// No package path leads to this version
+    UnreachableVersion { version: CleanName },
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VerifyError::PackageMetadata { name, err } => {
+                write!(f, "package {} metadata is unreadable: {}", name, err)
+            }
+            VerifyError::PackageData { name, data_name, err } => {
+                write!(f, "package {} data {} is unreadable: {}", name, data_name, err)
+            }
+            VerifyError::PackageSize { name, expected, found } => {
+                write!(f, "package {} size is {}, expected {}", name, found, expected)
+            }
+            VerifyError::OperationRange { name, path, range, size } => write!(
+                f,
+                "package {} operation {} data {}..{} is outside the package data ({} bytes)",
+                name, path, range.start, range.end, size
+            ),
+            VerifyError::OperationHash { name, path, expected, found } => write!(
+                f,
+                "package {} operation {} data hash is {}, expected {}",
+                name, path, found, expected
+            ),
+            VerifyError::UnknownVersion { what, version } => {
+                write!(f, "{} refers to the unknown version {}", what, version)
+            }
+            VerifyError::UnreachableVersion { version } => {
+                write!(f, "version {} can't be reached by any package", version)
+            }
+        }
+    }
+}
+
+impl std::error::Error for VerifyError {}
+
+pub(super) fn verify(repository: &Repository) -> io::Result<Vec<VerifyError>> {
+    let mut errors = Vec::new();
+    let versions = repository.versions()?;
+    let packages = repository.packages()?;
+    let known: HashSet<&CleanName> = versions.iter().map(|v| v.revision()).collect();
+
+    for channel in repository.channels()? {
+        let current = repository.current_version(channel.as_ref())?;
+        if !known.contains(current.version()) {
+            let what = match channel {
+                Some(channel) => format!("current version of channel {}", channel),
+                None => "current version".to_owned(),
+            };
+            errors.push(VerifyError::UnknownVersion { what, version: current.version().clone() });
+        }
+    }
+
+    for package in packages.iter() {
+        let name = package.package_metadata_name();
+        for version in package.from().into_iter().chain(Some(package.to())) {
+            if !known.contains(version) {
+                let what = format!("package {}", name);
+                errors.push(VerifyError::UnknownVersion { what, version: version.clone() });
+            }
+        }
+        verify_package(repository, package, &mut errors);
+    }
+
+    // versions reachable from scratch, whatever the platform
+    let mut reachable: HashSet<&CleanName> = HashSet::new();
+    let mut queue: Vec<&CleanName> = packages
+        .iter()
+        .filter(|p| p.is_standalone())
+        .map(|p| p.to())
+        .filter(|&to| reachable.insert(to))
+        .collect();
+    while let Some(version) = queue.pop() {
+        for package in packages.iter().filter(|p| p.from() == Some(version)) {
+            if reachable.insert(package.to()) {
+                queue.push(package.to());
+            }
+        }
+    }
+    for version in versions.iter() {
+        // yanked versions are never installed
+        if version.yanked().is_none() && !reachable.contains(version.revision()) {
+            errors.push(VerifyError::UnreachableVersion { version: version.revision().clone() });
+        }
+    }
+
+    Ok(errors)
+}
+
+fn verify_package(repository: &Repository, package: &dyn Package, errors: &mut Vec<VerifyError>) {
+    let name = package.package_metadata_name();
+    // the data file is checked even if the metadata is unreadable
+    let data_path = repository.dir().join(package.package_data_name().as_str());
+    let data_file = match fs::File::open(data_path).and_then(|f| Ok((f.metadata()?, f))) {
+        Ok((m, f)) if m.len() == package.size() => Some(f),
+        Ok((m, _)) => {
+            let (name, expected, found) = (name.clone(), package.size(), m.len());
+            errors.push(VerifyError::PackageSize { name, expected, found });
+            None
+        }
+        Err(err) => {
+            let (name, data_name) = (name.clone(), package.package_data_name());
+            errors.push(VerifyError::PackageData { name, data_name, err });
+            None
+        }
+    };
+
+    let package_metadata = match repository.package_metadata(&name) {
+        Ok(package_metadata) => package_metadata,
+        Err(err) => {
+            errors.push(VerifyError::PackageMetadata { name, err });
+            return;
+        }
+    };
+    let mut data_file = match data_file {
+        Some(data_file) => data_file,
+        None => return,
+    };
+
+    for operation in package_metadata.iter() {
+        let expected = match operation {
+            v2::Operation::Add(v2::Add { data_hash, .. })
+            | v2::Operation::Patch(v2::Patch { data_hash, .. })
+            | v2::Operation::Chunked(v2::Chunked { data_hash, .. }) => data_hash,
+            _ => continue,
+        };
+        // chunked operations might not have any data
+        let range = match metadata::Operation::range(operation) {
+            Some(range) => range,
+            None => continue,
+        };
+        let path = metadata::Operation::path(operation).clone();
+        if range.end > package.size() {
+            let (name, size) = (name.clone(), package.size());
+            errors.push(VerifyError::OperationRange { name, path, range, size });
+            continue;
+        }
+        let found = data_hash(&mut data_file, range, expected.algorithm());
+        match found {
+            Ok(found) if &found == expected => {}
+            Ok(found) => {
+                let (name, expected) = (name.clone(), expected.clone());
+                errors.push(VerifyError::OperationHash { name, path, expected, found });
+            }
+            Err(err) => {
+                let data_name = package.package_data_name();
+                errors.push(VerifyError::PackageData { name, data_name, err });
+                return;
+            }
+        }
+    }
+}
+
+fn data_hash(file: &mut fs::File, range: Range<u64>, algorithm: HashAlgorithm) -> io::Result<Hash> {
+    let slice = io::Slice::new(file, range.start, range.end - range.start)?;
+    let mut reader = io::CheckReader::with_check(slice, io::CheckHashSize::new(algorithm));
+    io::copy(&mut reader, &mut io::sink())?;
+    Ok(reader.hash())
+}
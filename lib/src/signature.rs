@@ -0,0 +1,152 @@
+//! Detached ed25519 signatures of repository metadata files
+//!
+//! Each signed file `$name` has a sibling `$name.sig` file containing the
+//! signature version and the base64 encoded signature, separated by a space.
+//! The signature covers the file name, the version and the raw content, so a
+//! signed file can't be served under another name.
+//!
+//! Versions increase each time a file is signed. Clients reject files signed
+//! with an older version than the last one they verified (see
+//! [`SignedVersions`]), so a stale signed file can't be replayed.
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use ed25519_dalek::{Signature, Signer, Verifier};
+pub use ed25519_dalek::{SigningKey, VerifyingKey};
+use parking_lot::Mutex;
+
+use crate::io;
+
+/// Name of the file holding the signature of `name`
+pub fn signature_filename(name: &str) -> String {
+    format!("{}.sig", name)
+}
+
+/// Message actually signed for the `version` of the file `name`
+fn signed_message(name: &str, version: u64, data: &[u8]) -> Vec<u8> {
+    const DOMAIN: &[u8] = b"speedupdate metadata signature\0";
+    let mut message = Vec::with_capacity(DOMAIN.len() + name.len() + 9 + data.len());
+    message.extend_from_slice(DOMAIN);
+    message.extend_from_slice(name.as_bytes());
+    message.push(0);
+    message.extend_from_slice(&version.to_le_bytes());
+    message.extend_from_slice(data);
+    message
+}
+
+/// Split `signature` file content into its version and encoded signature
+fn split_signature(signature: &[u8]) -> Option<(u64, &[u8])> {
+    let signature = signature.trim_ascii();
+    let separator = signature.iter().position(|&b| b == b' ')?;
+    let version = std::str::from_utf8(&signature[..separator]).ok()?.parse().ok()?;
+    Some((version, signature[separator + 1..].trim_ascii_start()))
+}
+
+/// Sign the `version` of the file `name` with content `data` and returns the
+/// signature file content
+pub fn sign(signing_key: &SigningKey, name: &str, version: u64, data: &[u8]) -> String {
+    let signature = signing_key.sign(&signed_message(name, version, data));
+    format!("{} {}", version, STANDARD.encode(signature.to_bytes()))
+}
+
+/// Returns the signed version if `signature` (a signature file content) is a
+/// valid signature of the file `name` with content `data`
+pub fn verify(
+    verifying_key: &VerifyingKey,
+    name: &str,
+    data: &[u8],
+    signature: &[u8],
+) -> Option<u64> {
+    let (version, signature) = split_signature(signature)?;
+    let signature = STANDARD.decode(signature).ok()?;
+    let signature = Signature::from_slice(&signature).ok()?;
+    verifying_key.verify(&signed_message(name, version, data), &signature).ok()?;
+    Some(version)
+}
+
+/// Version of `signature` (a signature file content), without verifying it
+pub fn signature_version(signature: &[u8]) -> Option<u64> {
+    split_signature(signature).map(|(version, _)| version)
+}
+
+/// Version to sign a file with, `previous` being the version of its last
+/// signature
+///
+/// Versions are milliseconds since the Unix epoch, so they keep increasing
+/// even if the last signature is lost, or `previous + 1` if the clock is late.
+pub fn next_version(previous: Option<u64>) -> u64 {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis() as u64);
+    match previous {
+        Some(previous) => now.max(previous.saturating_add(1)),
+        None => now,
+    }
+}
+
+/// Highest signature version verified for each file
+///
+/// Links share it between mirrors and workspaces save it between updates.
+#[derive(Default, Debug)]
+pub struct SignedVersions {
+    versions: Mutex<HashMap<String, u64>>,
+}
+
+impl SignedVersions {
+    /// Record the `version` of `name`, returns `false` if a more recent version
+    /// was already verified
+    pub fn check(&self, name: &str, version: u64) -> bool {
+        let mut versions = self.versions.lock();
+        match versions.get_mut(name) {
+            Some(seen) if *seen > version => false,
+            Some(seen) => {
+                *seen = version;
+                true
+            }
+            None => {
+                versions.insert(name.to_string(), version);
+                true
+            }
+        }
+    }
+
+    /// Reject files signed with an older version than `versions`
+    pub fn extend(&self, versions: &HashMap<String, u64>) {
+        let mut seen = self.versions.lock();
+        for (name, &version) in versions {
+            let seen = seen.entry(name.clone()).or_insert(version);
+            *seen = (*seen).max(version);
+        }
+    }
+
+    pub fn to_map(&self) -> HashMap<String, u64> {
+        self.versions.lock().clone()
+    }
+}
+
+fn key_bytes(key: &str, ctx: &str) -> io::Result<[u8; 32]> {
+    let bytes = STANDARD.decode(key.trim()).map_err(|err| {
+        io::Error::new(io::ErrorKind::InvalidInput, format!("bad {} encoding: {}", ctx, err))
+    })?;
+    bytes.try_into().map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("bad {} length, expected 32 bytes", ctx),
+        )
+    })
+}
+
+/// Parse a base64 encoded ed25519 secret key (32 bytes)
+pub fn parse_signing_key(key: &str) -> io::Result<SigningKey> {
+    Ok(SigningKey::from_bytes(&key_bytes(key, "signing key")?))
+}
+
+/// Parse a base64 encoded ed25519 public key (32 bytes)
+pub fn parse_verifying_key(key: &str) -> io::Result<VerifyingKey> {
+    VerifyingKey::from_bytes(&key_bytes(key, "public key")?)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err.to_string()))
+}
+
+/// Base64 encoding of `verifying_key`, as accepted by [`parse_verifying_key`]
+pub fn encode_verifying_key(verifying_key: &VerifyingKey) -> String {
+    STANDARD.encode(verifying_key.as_bytes())
+}
@@ -14,7 +14,7 @@ use tracing::{debug, info, warn};
 use super::updater::UpdateOptions;
 use crate::handlers::{ApplyHandler, ApplyOperation, HandlerContext};
 use crate::io;
-use crate::metadata::{self, v1, Operation};
+use crate::metadata::{self, v2, Operation};
 use crate::workspace::{UpdatePosition, WorkspaceFileManager};
 
 type Item = Result<ApplyPackageProgression, ApplyError>;
@@ -181,7 +181,7 @@ pub(crate) fn apply_package(
     update_options: UpdateOptions,
     file_manager: WorkspaceFileManager,
     package_name: &metadata::CleanName,
-    operations: Vec<(usize, Arc<v1::Operation>)>,
+    operations: Vec<(usize, Arc<v2::Operation>)>,
     i_available: AvailableForApply,
 ) -> ApplyStream {
     let done = Arc::new(AtomicUsize::new(0));
@@ -200,7 +200,7 @@ pub(crate) fn apply_package(
             update_options: &update_options,
         };
         let mut maybe_handler: Option<Box<dyn ApplyHandler>> = None;
-        let mut apply_operation = move |operation_idx, operation: &v1::Operation| {
+        let mut apply_operation = move |operation_idx, operation: &v2::Operation| {
             applied_data.operation_idx = operation_idx;
             applied_data.byte_idx = 0;
 
@@ -0,0 +1,385 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+use crate::io;
+use crate::link::{Conditional, RemoteRepository, RepositoryError, Validator};
+use crate::metadata::{self, CleanName, Package};
+use crate::signature;
+
+#[derive(Serialize, Deserialize)]
+struct CacheEntry<T> {
+    validator: Option<Validator>,
+    /// Package metadata hash published by `packages` when it was cached
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    metadata_hash: Option<metadata::Hash>,
+    /// Base64 encoded key the value signature was verified with
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    public_key: Option<String>,
+    value: T,
+}
+
+/// Repository metadata files cached in the workspace (`.update/cache`)
+///
+/// `current`, `versions` and `packages` are revalidated on each access, only
+/// downloaded again if they changed. Package metadata files are downloaded
+/// once per package build.
+///
+/// Entries remember the key their signature was verified with, they are
+/// ignored if the repository verifies signatures with another key (or if it
+/// starts verifying them).
+///
+/// The signature versions verified by the repository are saved too, so files
+/// signed with an older version are rejected by the next updates.
+pub(super) struct MetadataCache {
+    dir: PathBuf,
+}
+
+impl MetadataCache {
+    pub fn new(dir: PathBuf) -> Self {
+        MetadataCache { dir }
+    }
+
+    pub async fn current_version<R>(
+        &self,
+        repository: &R,
+        channel: Option<&CleanName>,
+    ) -> Result<metadata::Current, RepositoryError>
+    where
+        R: RemoteRepository,
+    {
+        let path = self.dir.join(metadata::Current::channel_filename(channel));
+        let public_key = public_key(repository);
+        let cached = read_entry(&path, &public_key);
+        let validator = cached.as_ref().and_then(|entry| entry.validator.as_ref());
+        let response = self
+            .with_signed_versions(
+                repository,
+                repository.current_version_if_modified(channel, validator),
+            )
+            .await?;
+        store_entry(path, public_key, cached, response)
+    }
+
+    pub async fn versions<R>(&self, repository: &R) -> Result<metadata::Versions, RepositoryError>
+    where
+        R: RemoteRepository,
+    {
+        let path = self.dir.join(metadata::Versions::filename());
+        let public_key = public_key(repository);
+        let cached = read_entry(&path, &public_key);
+        let validator = cached.as_ref().and_then(|entry| entry.validator.as_ref());
+        let response = self
+            .with_signed_versions(repository, repository.versions_if_modified(validator))
+            .await?;
+        store_entry(path, public_key, cached, response)
+    }
+
+    /// Repository packages, cached package metadata of packages that aren't
+    /// registered anymore are removed
+    pub async fn packages<R>(&self, repository: &R) -> Result<metadata::Packages, RepositoryError>
+    where
+        R: RemoteRepository,
+    {
+        let path = self.dir.join(metadata::Packages::filename());
+        let public_key = public_key(repository);
+        let cached = read_entry(&path, &public_key);
+        let validator = cached.as_ref().and_then(|entry| entry.validator.as_ref());
+        let response = self
+            .with_signed_versions(repository, repository.packages_if_modified(validator))
+            .await?;
+        let packages = store_entry(path, public_key, cached, response)?;
+        let names: HashSet<String> = packages
+            .as_slice()
+            .iter()
+            .map(|package| package.package_metadata_name().as_str().to_string())
+            .collect();
+        if let Err(err) = self.retain_package_metadata(&names) {
+            warn!("unable to clean the package metadata cache: {}", err);
+        }
+        Ok(packages)
+    }
+
+    /// Metadata of `package`, downloaded only if it isn't cached yet
+    ///
+    /// A cached file of another package build is replaced, builds are told
+    /// apart by the metadata hash published by `packages` or by the package
+    /// size if the repository doesn't publish it.
+    pub async fn package_metadata<R, P>(
+        &self,
+        repository: &R,
+        package: &P,
+    ) -> Result<metadata::PackageMetadata, RepositoryError>
+    where
+        R: RemoteRepository,
+        P: Package,
+    {
+        let package_name = package.package_metadata_name();
+        let path = self.package_metadata_dir().join(package_name.as_str());
+        let public_key = public_key(repository);
+        let cached: Option<CacheEntry<metadata::PackageMetadata>> = read_entry(&path, &public_key);
+        let same_build = |entry: &CacheEntry<metadata::PackageMetadata>| {
+            entry.value.size() == package.size()
+                && entry.metadata_hash.as_ref() == package.metadata_hash()
+        };
+        if let Some(entry) = cached.filter(same_build) {
+            debug!("package metadata {} is cached", package_name);
+            return Ok(entry.value);
+        }
+        let value = self
+            .with_signed_versions(repository, repository.package_metadata(package_name))
+            .await?;
+        let metadata_hash = package.metadata_hash().cloned();
+        let entry = CacheEntry { validator: None, metadata_hash, public_key, value };
+        write_entry(&path, &entry);
+        Ok(entry.value)
+    }
+
+    /// Run `request` rejecting files signed with an older version than the
+    /// ones verified by previous updates, then save the verified versions
+    async fn with_signed_versions<R, F, T>(
+        &self,
+        repository: &R,
+        request: F,
+    ) -> Result<T, RepositoryError>
+    where
+        R: RemoteRepository,
+        F: Future<Output = Result<T, RepositoryError>>,
+    {
+        let path = self.dir.join("signed_versions.json");
+        let saved = fs::File::open(&path).ok().and_then(|file| {
+            serde_json::from_reader::<_, HashMap<String, u64>>(io::BufReader::new(file))
+                .map_err(|err| warn!("ignore invalid signed versions {:?}: {}", path, err))
+                .ok()
+        });
+        if let Some(saved) = &saved {
+            repository.expect_signed_versions(saved);
+        }
+        let res = request.await;
+        let versions = repository.signed_versions();
+        if !versions.is_empty() && saved.as_ref() != Some(&versions) {
+            let res =
+                fs::create_dir_all(&self.dir).and_then(|_| io::atomic_write_json(&path, &versions));
+            if let Err(err) = res {
+                warn!("unable to save signed versions {:?}: {}", path, err);
+            }
+        }
+        res
+    }
+
+    fn package_metadata_dir(&self) -> PathBuf {
+        self.dir.join("metadata")
+    }
+
+    fn retain_package_metadata(&self, names: &HashSet<String>) -> io::Result<()> {
+        let entries = match fs::read_dir(self.package_metadata_dir()) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err),
+        };
+        for entry in entries {
+            let entry = entry?;
+            let known = entry.file_name().to_str().is_some_and(|name| names.contains(name));
+            if !known {
+                io::remove_file(entry.path())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Base64 encoded key `repository` verifies signatures with
+fn public_key<R>(repository: &R) -> Option<String>
+where
+    R: RemoteRepository,
+{
+    repository.public_key().as_ref().map(signature::encode_verifying_key)
+}
+
+/// Cached entry at `path`, unless it wasn't verified with `public_key`
+fn read_entry<T>(path: &Path, public_key: &Option<String>) -> Option<CacheEntry<T>>
+where
+    T: DeserializeOwned,
+{
+    let file = fs::File::open(path).ok()?;
+    match serde_json::from_reader::<_, CacheEntry<T>>(io::BufReader::new(file)) {
+        Ok(entry) if entry.public_key != *public_key => {
+            debug!("ignore cached metadata {:?} verified with another key", path);
+            None
+        }
+        Ok(entry) => Some(entry),
+        Err(err) => {
+            warn!("ignore invalid cached metadata {:?}: {}", path, err);
+            None
+        }
+    }
+}
+
+/// Cache the modified value of `response`, returns the value
+///
+/// Values without validator can't be revalidated, they aren't cached.
+fn store_entry<T>(
+    path: PathBuf,
+    public_key: Option<String>,
+    cached: Option<CacheEntry<T>>,
+    response: Conditional<T>,
+) -> Result<T, RepositoryError>
+where
+    T: Serialize,
+{
+    match (response, cached) {
+        (Conditional::NotModified, Some(entry)) => {
+            debug!("cached metadata {:?} is up to date", path);
+            Ok(entry.value)
+        }
+        (Conditional::NotModified, None) => Err(RepositoryError::file(
+            &path,
+            io::Error::new(io::ErrorKind::NotFound, "not modified but not cached"),
+        )),
+        (Conditional::Modified { value, validator: Some(validator) }, _) => {
+            let validator = Some(validator);
+            let entry = CacheEntry { validator, metadata_hash: None, public_key, value };
+            write_entry(&path, &entry);
+            Ok(entry.value)
+        }
+        (Conditional::Modified { value, validator: None }, _) => {
+            let _ = io::remove_file(&path);
+            Ok(value)
+        }
+    }
+}
+
+fn write_entry<T>(path: &Path, entry: &CacheEntry<T>)
+where
+    T: Serialize,
+{
+    let res = match path.parent() {
+        Some(dir) => fs::create_dir_all(dir),
+        None => Ok(()),
+    };
+    if let Err(err) = res.and_then(|_| io::atomic_write_json(path, entry)) {
+        warn!("unable to cache metadata {:?}: {}", path, err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use futures::TryStreamExt;
+
+    use crate::metadata::CleanName;
+    use crate::repository::{BuildOptions, PackageBuilder};
+    use crate::workspace::{UpdateError, UpdateOptions};
+    use crate::{Repository, Workspace};
+
+    #[test]
+    fn metadata_cache() {
+        use crate::link::{Conditional, RemoteRepository};
+
+        crate::tests::init();
+        let rt = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        let (v1, v2) = (CleanName::from_static_str("1"), CleanName::from_static_str("2"));
+        let path = crate::tests::tmp_dir("metadata_cache");
+        let source_directory = path.join("source");
+        fs::create_dir_all(&source_directory).unwrap();
+        fs::write(source_directory.join("file.txt"), "cached metadata").unwrap();
+
+        let mut repository = Repository::new(path.join("repository"));
+        fs::create_dir_all(repository.dir()).unwrap();
+        repository.init().unwrap();
+        let mut builder = PackageBuilder::new(path.join("build"), v1.clone(), source_directory);
+        builder.set_options(BuildOptions::raw());
+        crate::tests::publish_package(&mut repository, &builder);
+        crate::tests::register_version(&repository, &v2);
+
+        let link = repository.link();
+        let validator = match rt.block_on(link.packages_if_modified(None)).unwrap() {
+            Conditional::Modified { validator: Some(validator), .. } => validator,
+            _ => panic!("expected a validator"),
+        };
+        let res = rt.block_on(link.packages_if_modified(Some(&validator))).unwrap();
+        assert!(matches!(res, Conditional::NotModified));
+
+        let workspace_dir = path.join("workspace");
+        let mut workspace = Workspace::open(&workspace_dir).unwrap();
+        let update_stream = workspace.update(&link, None, UpdateOptions::default());
+        rt.block_on(update_stream.try_for_each(|_| async { Ok(()) })).unwrap();
+        let cache_dir = workspace_dir.join(".update/cache");
+        let package_metadata_name = builder.package_metadata_name();
+        for file_name in ["current", "versions", "packages"] {
+            assert!(cache_dir.join(file_name).is_file(), "{} isn't cached", file_name);
+        }
+        assert!(cache_dir.join("metadata").join(package_metadata_name.as_str()).is_file());
+
+        // package metadata are immutable, they are never downloaded again
+        fs::remove_file(repository.dir().join(package_metadata_name.as_str())).unwrap();
+        fs::remove_file(workspace_dir.join(".update/state.json")).unwrap();
+        fs::remove_file(workspace_dir.join("file.txt")).unwrap();
+        let mut workspace = Workspace::open(&workspace_dir).unwrap();
+        let update_stream = workspace.update(&link, None, UpdateOptions::default());
+        rt.block_on(update_stream.try_for_each(|_| async { Ok(()) })).unwrap();
+
+        // unless the package is built again, even with the same size
+        fs::write(path.join("source/file.txt"), "CACHED METADATA").unwrap();
+        fs::remove_file(repository.dir().join(builder.package_data_name().as_str())).unwrap();
+        let mut builder = PackageBuilder::new(path.join("build"), v1, path.join("source"));
+        builder.set_options(BuildOptions::raw());
+        crate::tests::publish_package(&mut repository, &builder);
+        fs::remove_file(workspace_dir.join(".update/state.json")).unwrap();
+        fs::remove_file(workspace_dir.join("file.txt")).unwrap();
+        let mut workspace = Workspace::open(&workspace_dir).unwrap();
+        let update_stream = workspace.update(&link, None, UpdateOptions::default());
+        rt.block_on(update_stream.try_for_each(|_| async { Ok(()) })).unwrap();
+
+        // the modified current version is downloaded again
+        repository.set_current_version(None, &v2).unwrap();
+        let update_stream = workspace.update(&link, None, UpdateOptions::default());
+        assert!(matches!(
+            rt.block_on(update_stream.try_for_each(|_| async { Ok(()) })),
+            Err(UpdateError::NoPath)
+        ));
+        workspace.remove_metadata().unwrap();
+        crate::tests::assert_fs_eq(&path.join("source"), &workspace_dir);
+    }
+
+    #[test]
+    fn cached_metadata_public_key() {
+        use crate::signature::SigningKey;
+
+        crate::tests::init();
+        let rt = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        let v1 = CleanName::from_static_str("1");
+        let path = crate::tests::tmp_dir("cached_metadata_public_key");
+        let source_directory = path.join("source");
+        fs::create_dir_all(&source_directory).unwrap();
+        fs::write(source_directory.join("file.txt"), "verified metadata").unwrap();
+
+        let mut repository = Repository::new(path.join("repository"));
+        fs::create_dir_all(repository.dir()).unwrap();
+        repository.init().unwrap();
+        let mut builder = PackageBuilder::new(path.join("build"), v1, source_directory);
+        builder.set_options(BuildOptions::raw());
+        crate::tests::publish_package(&mut repository, &builder);
+
+        let mut link = repository.link();
+        let workspace_dir = path.join("workspace");
+        let mut workspace = Workspace::open(&workspace_dir).unwrap();
+        let update_stream = workspace.update(&link, None, UpdateOptions::default());
+        rt.block_on(update_stream.try_for_each(|_| async { Ok(()) })).unwrap();
+
+        // unverified cached metadata aren't used once signatures are verified,
+        // the repository isn't signed
+        link.set_public_key(SigningKey::from_bytes(&[7u8; 32]).verifying_key());
+        fs::remove_file(workspace_dir.join(".update/state.json")).unwrap();
+        let mut workspace = Workspace::open(&workspace_dir).unwrap();
+        let update_stream = workspace.update(&link, None, UpdateOptions::default());
+        let res = rt.block_on(update_stream.try_for_each(|_| async { Ok(()) }));
+        assert!(matches!(res, Err(UpdateError::Repository(_))), "{:?}", res.map(|_| ()));
+    }
+}
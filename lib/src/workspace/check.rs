@@ -51,7 +51,7 @@ pub(crate) async fn check(
     let checks = file_manager.read_checks().map_err(CheckError::LocalCheckError)?;
 
     // Build list of operations to do
-    let operations: Vec<(usize, Arc<metadata::v1::Operation>)> = checks
+    let operations: Vec<(usize, Arc<metadata::v2::Operation>)> = checks
         .iter()
         .enumerate()
         .filter_map(|(idx, o)| o.as_check_operation().map(|o| (idx, Arc::new(o))))
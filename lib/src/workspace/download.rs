@@ -1,21 +1,41 @@
-use std::fs::OpenOptions;
+use std::fs::{self, OpenOptions};
+use std::io;
+use std::io::Read;
 use std::io::Seek;
 use std::io::SeekFrom;
 use std::io::Write;
 use std::ops::{Deref, Range};
+use std::path::Path;
 use std::sync::Arc;
+use std::time::Duration;
 use std::{cmp, pin::Pin};
 
 use futures::prelude::*;
-use tracing::{debug, info};
+use tokio::time::Instant;
+use tracing::{debug, info, warn};
 
 use super::updater::UpdateError;
+use crate::io::{Check, CheckHashSize, CheckReader};
 use crate::link::RemoteRepository;
 use crate::metadata::{self, Operation};
 use crate::workspace::{UpdatePosition, WorkspaceFileManager};
 
+/// Smallest range worth its own request when downloading concurrently
+const MIN_CONCURRENT_RANGE_SIZE: u64 = 1024 * 1024;
+
+/// Maximum number of ranges in a single request
+const MAX_RANGES_PER_REQUEST: usize = 32;
+
 /// Construct a list of ranges to downloads
-fn ranges<'a, L, I>(operations: L, offset: u64, merge_distance: u64) -> Vec<Range<u64>>
+///
+/// Operations are merged into ranges of at most `max_size` bytes, unless a
+/// single operation is bigger.
+fn ranges<'a, L, I>(
+    operations: L,
+    offset: u64,
+    merge_distance: u64,
+    max_size: u64,
+) -> Vec<Range<u64>>
 where
     L: Iterator<Item = &'a I>,
     I: Operation + 'a,
@@ -28,7 +48,8 @@ where
             offset = 0;
             let mut push = true;
             if let Some(last_range) = ranges.last_mut() {
-                push = last_range.end + merge_distance < start;
+                push = last_range.end + merge_distance < start
+                    || (range.end - last_range.start > max_size && range.start < range.end);
                 if !push {
                     last_range.end = range.end;
                 }
@@ -41,10 +62,209 @@ where
     ranges
 }
 
+/// Group consecutive `ranges` into requests of at most `max_size` bytes
+fn group_ranges(ranges: Vec<Range<u64>>, max_size: u64) -> Vec<Vec<Range<u64>>> {
+    let mut groups: Vec<Vec<Range<u64>>> = Vec::new();
+    let mut group_size = 0u64;
+    for range in ranges {
+        let size = range.end - range.start;
+        match groups.last_mut() {
+            Some(group)
+                if group.len() < MAX_RANGES_PER_REQUEST
+                    && group_size.saturating_add(size) <= max_size =>
+            {
+                group_size += size;
+                group.push(range);
+            }
+            _ => {
+                group_size = size;
+                groups.push(vec![range]);
+            }
+        }
+    }
+    groups
+}
+
+/// Split `operations` with data between the request `groups` downloading it
+fn split_operations<O>(
+    groups: &[Vec<Range<u64>>],
+    operations: Vec<(usize, Arc<O>)>,
+) -> Result<Vec<Vec<RangeOperation<O>>>, UpdateError>
+where
+    O: Operation,
+{
+    let mut range_operations: Vec<Vec<RangeOperation<O>>> =
+        groups.iter().map(|_| Vec::new()).collect();
+    let mut group_idx = 0;
+    for (operation_idx, o) in operations {
+        if let Some(range) = o.range() {
+            loop {
+                match groups.get(group_idx).map(|group| group.last()) {
+                    Some(Some(last)) if last.end >= range.end => break,
+                    Some(_) => group_idx += 1,
+                    None => {
+                        return Err(UpdateError::DownloadCache(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!(
+                                "operation#{} range {:?} isn't in any download range",
+                                operation_idx, range
+                            ),
+                        )))
+                    }
+                }
+            }
+            range_operations[group_idx].push((operation_idx, range, o));
+        }
+    }
+    Ok(range_operations)
+}
+
+/// Longest sleep before checking the limit again, so a new limit applies quickly
+const MAX_THROTTLE_SLEEP: Duration = Duration::from_millis(100);
+
+struct TokenBucket {
+    /// Bytes per second, `None` if unlimited
+    limit: Option<u64>,
+    /// Available bytes, negative if downloaded bytes are still to be paid
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl TokenBucket {
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.refilled_at).as_secs_f64();
+        self.refilled_at = now;
+        if let Some(limit) = self.limit {
+            // allow bursts of up to one second of download
+            self.tokens = (self.tokens + elapsed * limit as f64).min(limit as f64);
+        }
+    }
+}
+
+/// Download bandwidth limit, shared by clones
+///
+/// Downloads of every package range are paused while the limit is exceeded,
+/// the limit can be changed at any time.
+#[derive(Clone)]
+pub struct BandwidthLimiter {
+    bucket: Arc<parking_lot::Mutex<TokenBucket>>,
+}
+
+impl BandwidthLimiter {
+    /// Limit downloads to `limit` bytes per second, `None` or `Some(0)` mean
+    /// unlimited
+    pub fn new(limit: Option<u64>) -> Self {
+        let limit = limit.filter(|&limit| limit > 0);
+        let bucket = TokenBucket { limit, tokens: 0.0, refilled_at: Instant::now() };
+        BandwidthLimiter { bucket: Arc::new(parking_lot::Mutex::new(bucket)) }
+    }
+
+    /// Current limit in bytes per second, `None` if unlimited
+    pub fn limit(&self) -> Option<u64> {
+        self.bucket.lock().limit
+    }
+
+    /// Change the limit in bytes per second, `None` or `Some(0)` remove the
+    /// limit
+    pub fn set_limit(&self, limit: Option<u64>) {
+        let mut bucket = self.bucket.lock();
+        bucket.refill();
+        bucket.limit = limit.filter(|&limit| limit > 0);
+        if bucket.limit.is_none() {
+            bucket.tokens = 0.0;
+        }
+    }
+
+    /// Wait until `size` downloaded bytes fit in the limit
+    async fn consume(&self, size: u64) {
+        {
+            let mut bucket = self.bucket.lock();
+            if bucket.limit.is_none() {
+                return;
+            }
+            bucket.tokens -= size as f64;
+        }
+        loop {
+            let wait = {
+                let mut bucket = self.bucket.lock();
+                bucket.refill();
+                match bucket.limit {
+                    Some(limit) if bucket.tokens < 0.0 => {
+                        Duration::from_secs_f64(-bucket.tokens / limit as f64)
+                    }
+                    _ => return,
+                }
+            };
+            tokio::time::sleep(wait.min(MAX_THROTTLE_SLEEP)).await;
+        }
+    }
+}
+
+impl Default for BandwidthLimiter {
+    fn default() -> Self {
+        BandwidthLimiter::new(None)
+    }
+}
+
 pub struct DownloadPackageProgression {
     pub(super) available: UpdatePosition,
     pub delta_downloaded_files: usize,
     pub delta_downloaded_bytes: u64,
+    pub delta_download_retries: u64,
+    pub delta_redownloaded_bytes: u64,
+}
+
+/// Operation index and package data range
+type RangeOperation<O> = (usize, Range<u64>, Arc<O>);
+
+/// Operation being written to the download cache, its data is hashed on the fly
+struct DownloadingOperation<O> {
+    operation_idx: usize,
+    range: Range<u64>,
+    file: fs::File,
+    check: Option<CheckHashSize>,
+    operation: Arc<O>,
+}
+
+impl<O> DownloadingOperation<O>
+where
+    O: Operation,
+{
+    /// Start writing the data of `operation` from `pos`, bytes already
+    /// downloaded are hashed again
+    fn begin(
+        (operation_idx, range, file, operation): (usize, Range<u64>, io::Result<fs::File>, Arc<O>),
+        pos: u64,
+    ) -> io::Result<Self> {
+        let mut file = file?;
+        file.set_len(pos)?;
+        let mut check = operation.data_hash().map(|hash| CheckHashSize::new(hash.algorithm()));
+        if let Some(prefix_check) = check.take_if(|_| pos > 0) {
+            file.seek(SeekFrom::Start(0))?;
+            let mut reader = CheckReader::with_check((&mut file).take(pos), prefix_check);
+            io::copy(&mut reader, &mut io::sink())?;
+            check = Some(reader.check);
+        }
+        file.seek(SeekFrom::Start(pos))?;
+        Ok(DownloadingOperation { operation_idx, range, file, check, operation })
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.file.write_all(bytes)?;
+        if let Some(check) = &mut self.check {
+            check.check(bytes);
+        }
+        Ok(())
+    }
+
+    /// Returns true if the written data matches the operation data hash
+    fn is_valid(&mut self) -> bool {
+        match (&mut self.check, self.operation.data_hash()) {
+            (Some(check), Some(expected)) => &check.hash() == expected,
+            _ => true,
+        }
+    }
 }
 
 pub type DownloadStream<'a> =
@@ -52,70 +272,194 @@ pub type DownloadStream<'a> =
 
 /// Download package `package_name` from `repository` and returns a stream of progress
 ///
-/// Downloaded bytes are stored in `file_manager` download_operation_path files
+/// Downloaded bytes are stored in `file_manager` download_operation_path files.
+/// Ranges are grouped into multiple ranges requests, up to `concurrency`
+/// requests are downloaded at the same time within the bandwidth of `limiter`,
+/// the reported
+/// `available` position only moves forward once every previous operation is
+/// downloaded.
 pub(super) fn download_package<'a, R, O>(
     file_manager: WorkspaceFileManager,
     repository: &'a R,
     package_name: &metadata::CleanName,
     operations: Vec<(usize, Arc<O>)>,
     start_position: UpdatePosition,
+    concurrency: usize,
+    limiter: BandwidthLimiter,
 ) -> DownloadStream<'a>
 where
     R: RemoteRepository,
     O: Operation + 'a,
 {
-    // 1. Compute the list of ranges to download in the requested package
-    let ranges =
-        ranges(operations.iter().map(|(_, o)| o.deref()), start_position.byte_idx, 500 * 1024);
+    // 1. Compute the list of ranges to download in the requested package, split
+    // to keep `concurrency` requests busy
+    let concurrency = concurrency.max(1);
+    let total_size: u64 = operations.iter().map(|(_, o)| o.data_size()).sum();
+    let max_size = match concurrency {
+        1 => u64::MAX,
+        _ => total_size.div_ceil(concurrency as u64).max(MIN_CONCURRENT_RANGE_SIZE),
+    };
+    let ranges = ranges(
+        operations.iter().map(|(_, o)| o.deref()),
+        start_position.byte_idx,
+        500 * 1024,
+        max_size,
+    );
     let mut end_position = start_position;
     if let Some(&(last_op_idx, _)) = operations.last() {
         end_position.operation_idx = last_op_idx + 1;
     }
     debug!("download ranges: {:?}", ranges);
+    let groups = group_ranges(ranges, max_size);
 
-    // 2. Build operations file opener
-    let package_name_o = package_name.clone();
-    let mut operations_iter = operations.into_iter().filter_map(move |(operation_idx, o)| {
-        if let Some(range) = o.range() {
-            let data_file_path =
-                file_manager.download_operation_path(&package_name_o, operation_idx);
-            info!("downl data_file_path {:?} for {}", data_file_path, &o.path());
-            let file =
-                OpenOptions::new().write(true).create(true).truncate(true).open(data_file_path);
-            Some((operation_idx, range, file, o))
-        } else {
-            None
+    // 2. Split operations between requests
+    let range_operations = match split_operations(&groups, operations) {
+        Ok(range_operations) => range_operations,
+        Err(err) => return stream::once(future::err(err)).boxed_local(),
+    };
+    let range_ends: Vec<UpdatePosition> = range_operations
+        .iter()
+        .map(|operations| UpdatePosition {
+            operation_idx: operations.last().map_or(0, |&(idx, _, _)| idx + 1),
+            byte_idx: 0,
+        })
+        .collect();
+
+    // 3. Starts downloading ranges
+    // -> TryStream< (range_idx, DownloadPackageProgression) >
+    let package_name_r = package_name.clone();
+    let range_streams = groups.into_iter().zip(range_operations).enumerate().map(
+        move |(range_idx, (ranges, operations))| {
+            download_range(
+                file_manager.clone(),
+                repository,
+                package_name_r.clone(),
+                ranges,
+                operations,
+                start_position,
+                limiter.clone(),
+            )
+            .map_ok(move |progress| (range_idx, progress))
+            .boxed_local()
+        },
+    );
+    let package_name = package_name.clone();
+    let download_ranges = stream::iter(range_streams).flatten_unordered(concurrency);
+
+    // 4. Only report downloaded operations in order
+    let mut range_positions: Vec<Option<UpdatePosition>> = vec![None; range_ends.len()];
+    let mut next_range_idx = 0;
+    let mut available = start_position;
+    let mut download_retries = repository.download_retries();
+    let in_order = download_ranges.map_ok(move |(range_idx, progress)| {
+        range_positions[range_idx] = Some(progress.available);
+        while next_range_idx < range_ends.len()
+            && range_positions[next_range_idx] == Some(range_ends[next_range_idx])
+        {
+            available = available.max(range_ends[next_range_idx]);
+            next_range_idx += 1;
         }
+        if let Some(Some(position)) = range_positions.get(next_range_idx) {
+            available = available.max(*position);
+        }
+        let delta_download_retries = repository.download_retries() - download_retries;
+        download_retries += delta_download_retries;
+        DownloadPackageProgression { available, delta_download_retries, ..progress }
     });
 
-    // 2. Starts downloading ranges
-    // -> TryStream< (range_start: u64, Bytes) >
-    let package_name_r = package_name.clone();
-    let download_ranges = stream::iter(ranges.into_iter().map(move |range| {
-        let range_start = range.start;
-        repository.package(package_name_r.clone(), range).map_err(UpdateError::Download).map_ok(
-            move |chunks| {
-                chunks.map_ok(move |chunk| (range_start, chunk)).map_err(UpdateError::Download)
-            },
-        )
-    }))
-    .then(|fut| fut)
-    .try_flatten();
+    let done_stream = future::lazy(move |_| {
+        debug!("end download of {}", package_name);
+        Ok(stream::iter(std::iter::once(Ok(DownloadPackageProgression {
+            available: end_position,
+            delta_downloaded_files: 0,
+            delta_downloaded_bytes: 0,
+            delta_download_retries: 0,
+            delta_redownloaded_bytes: 0,
+        }))))
+    })
+    .try_flatten_stream();
 
-    // 3. Write downloaded ranges chunks
+    in_order.chain(done_stream).boxed_local()
+}
+
+/// Download `ranges` of package `package_name` into `operations` files
+///
+/// The data of each operation is hashed while written, operations with an
+/// unexpected data hash are downloaded again on their own.
+///
+/// The reported `available` position is the position in these ranges, the
+/// stream ends once every operation of the ranges is downloaded.
+fn download_range<'a, R, O>(
+    file_manager: WorkspaceFileManager,
+    repository: &'a R,
+    package_name: metadata::CleanName,
+    ranges: Vec<Range<u64>>,
+    operations: Vec<RangeOperation<O>>,
+    start_position: UpdatePosition,
+    limiter: BandwidthLimiter,
+) -> impl Stream<Item = Result<DownloadPackageProgression, UpdateError>> + 'a
+where
+    R: RemoteRepository,
+    O: Operation + 'a,
+{
+    let end_position = UpdatePosition {
+        operation_idx: operations.last().map_or(0, |&(idx, _, _)| idx + 1),
+        byte_idx: 0,
+    };
+
+    // 1. Build operations file opener
+    let package_name_o = package_name.clone();
+    let file_manager_o = file_manager.clone();
+    let mut operations_iter = operations.into_iter().map(move |(operation_idx, range, o)| {
+        let data_file_path = file_manager_o.download_operation_path(&package_name_o, operation_idx);
+        info!("downl data_file_path {:?} for {}", data_file_path, &o.path());
+        // truncated once the resume position is known
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(data_file_path);
+        (operation_idx, range, file, o)
+    });
+
+    // 2. Starts downloading the ranges
+    // -> TryStream< (u64, Bytes) >
+    let mut pos = ranges.first().map_or(0, |range| range.start);
+    let limiter_r = limiter.clone();
+    let download_range = repository
+        .package_ranges(package_name.clone(), ranges)
+        .map_err(UpdateError::Download)
+        .map_ok(|chunks| chunks.map_err(UpdateError::Download))
+        .try_flatten_stream()
+        .and_then(move |(offset, chunk)| {
+            let limiter = limiter_r.clone();
+            async move {
+                limiter.consume(chunk.len() as u64).await;
+                Ok((offset, chunk))
+            }
+        });
+
+    // 3. Write downloaded range chunks, then download corrupted operations again
     // -> TryStream< UpdatePosition >
     let mut position = start_position;
-    let mut current_operation = None;
-    let mut pos = 0;
-    let write_ranges = download_ranges.and_then(move |(range_start, chunk)| {
-        pos = pos.max(range_start);
+    let mut current_operation: Option<DownloadingOperation<O>> = None;
+    let write_range = download_range.and_then(move |(offset, chunk)| {
+        let mut corrupted: Vec<RangeOperation<O>> = Vec::new();
         let mut write_downloaded_chunk = || -> Result<DownloadPackageProgression, UpdateError> {
             let mut bytes: &[u8] = &chunk;
+            if offset < pos {
+                // already written bytes
+                bytes = &bytes[cmp::min(bytes.len() as u64, pos - offset) as usize..];
+            } else {
+                pos = offset;
+            }
             let mut delta_downloaded_files = 0;
             let mut delta_downloaded_bytes = 0;
             loop {
                 if current_operation.is_none() {
-                    if let Some((operation_idx, range, file, operation)) = operations_iter.next() {
+                    if let Some(next_operation) = operations_iter.next() {
+                        let (operation_idx, range, _, operation) = &next_operation;
                         debug!(
                             "begin download operation#{} {} [{}, {})",
                             operation_idx,
@@ -123,33 +467,41 @@ where
                             range.start,
                             range.end
                         );
-                        let mut file = file.map_err(UpdateError::DownloadCache)?;
-                        let pos = if operation_idx == start_position.operation_idx {
+                        let pos = if *operation_idx == start_position.operation_idx {
                             start_position.byte_idx
                         } else {
                             0
                         };
-                        file.set_len(pos).map_err(UpdateError::DownloadCache)?;
-                        file.seek(SeekFrom::Start(pos)).map_err(UpdateError::DownloadCache)?;
-                        position.operation_idx = operation_idx;
+                        let operation = DownloadingOperation::begin(next_operation, pos)
+                            .map_err(UpdateError::DownloadCache)?;
+                        position.operation_idx = operation.operation_idx;
                         position.byte_idx = pos;
-                        current_operation = Some((range, file));
+                        current_operation = Some(operation);
                     }
                 }
                 let done = match (bytes.len(), &mut current_operation) {
                     (0, _) => break,
                     (_, None) => break,
-                    (_, Some((range, file))) => {
+                    (_, Some(operation)) => {
+                        let range = &operation.range;
+                        let next = range.start + position.byte_idx;
+                        if pos > next && next < range.end {
+                            return Err(UpdateError::DownloadCache(io::Error::new(
+                                io::ErrorKind::UnexpectedEof,
+                                "missing package data",
+                            )));
+                        }
                         if range.start > pos {
                             // skip unwanted bytes
                             let ignore_len =
                                 cmp::min(bytes.len() as u64, range.start - pos) as usize;
                             bytes = &bytes[ignore_len..];
+                            pos += ignore_len as u64;
                         }
-                        let remaining = (range.end - pos) as usize;
+                        let remaining = range.end.saturating_sub(pos) as usize;
                         let cur_len = cmp::min(bytes.len(), remaining);
                         let cur_bytes = &bytes[0..cur_len];
-                        file.write_all(cur_bytes).map_err(UpdateError::DownloadCache)?;
+                        operation.write(cur_bytes).map_err(UpdateError::DownloadCache)?;
                         bytes = &bytes[cur_len..];
                         {
                             let cur_len = cur_len as u64;
@@ -165,27 +517,558 @@ where
                     delta_downloaded_files += 1;
                     position.operation_idx += 1;
                     position.byte_idx = 0;
-                    current_operation = None;
+                    if let Some(mut operation) = current_operation.take() {
+                        if !operation.is_valid() {
+                            let DownloadingOperation { operation_idx, range, operation, .. } =
+                                operation;
+                            corrupted.push((operation_idx, range, operation));
+                        }
+                    }
                 }
             }
             Ok(DownloadPackageProgression {
                 available: position,
                 delta_downloaded_files,
                 delta_downloaded_bytes,
+                delta_download_retries: 0,
+                delta_redownloaded_bytes: 0,
             })
         };
-        future::ready(write_downloaded_chunk())
+        let written = write_downloaded_chunk();
+        let (file_manager, package_name, limiter) =
+            (file_manager.clone(), package_name.clone(), limiter.clone());
+        async move {
+            let mut progress = written?;
+            for (operation_idx, range, operation) in corrupted {
+                let data_file_path =
+                    file_manager.download_operation_path(&package_name, operation_idx);
+                progress.delta_redownloaded_bytes += redownload_operation(
+                    repository,
+                    &package_name,
+                    &data_file_path,
+                    range,
+                    operation.deref(),
+                    &limiter,
+                )
+                .await?;
+            }
+            Ok(progress)
+        }
     });
 
-    let done_stream = future::lazy(move |_| {
-        debug!("end download");
-        Ok(stream::iter(std::iter::once(Ok(DownloadPackageProgression {
-            available: end_position,
-            delta_downloaded_files: 0,
-            delta_downloaded_bytes: 0,
-        }))))
-    })
-    .try_flatten_stream();
+    // stop reading once the last operation is downloaded
+    let mut finished = false;
+    let write_range = write_range.scan((), move |_, progress| {
+        if finished {
+            return future::ready(None);
+        }
+        finished = matches!(&progress, Ok(progress) if progress.available == end_position);
+        future::ready(Some(progress))
+    });
+
+    let done = DownloadPackageProgression {
+        available: end_position,
+        delta_downloaded_files: 0,
+        delta_downloaded_bytes: 0,
+        delta_download_retries: 0,
+        delta_redownloaded_bytes: 0,
+    };
+    write_range.chain(stream::once(future::ok(done)))
+}
+
+/// Download the data of `operation` again after a data hash mismatch
+///
+/// Returns the number of downloaded bytes, fails if the data still doesn't
+/// match.
+async fn redownload_operation<R, O>(
+    repository: &R,
+    package_name: &metadata::CleanName,
+    data_file_path: &Path,
+    range: Range<u64>,
+    operation: &O,
+    limiter: &BandwidthLimiter,
+) -> Result<u64, UpdateError>
+where
+    R: RemoteRepository,
+    O: Operation,
+{
+    let expected = match operation.data_hash() {
+        Some(expected) => expected,
+        None => return Ok(0),
+    };
+    warn!(
+        "{} data hash mismatch, downloading [{}, {}) again",
+        operation.path(),
+        range.start,
+        range.end
+    );
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(data_file_path)
+        .map_err(UpdateError::DownloadCache)?;
+    let mut check = CheckHashSize::new(expected.algorithm());
+    let size = range.end - range.start;
+    let mut chunks =
+        repository.package(package_name.clone(), range).await.map_err(UpdateError::Download)?;
+    // repositories might send bytes past the range end
+    while check.bytes < size {
+        let chunk = match chunks.try_next().await.map_err(UpdateError::Download)? {
+            Some(chunk) => chunk,
+            None => break,
+        };
+        let chunk = &chunk[..cmp::min(chunk.len() as u64, size - check.bytes) as usize];
+        limiter.consume(chunk.len() as u64).await;
+        file.write_all(chunk).map_err(UpdateError::DownloadCache)?;
+        check.check(chunk);
+    }
+    let found = check.hash();
+    if &found != expected {
+        return Err(UpdateError::DataHash {
+            path: operation.path().clone(),
+            expected: expected.clone(),
+            found,
+        });
+    }
+    Ok(check.bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use futures::TryStreamExt;
+
+    use super::*;
+    use crate::link;
+    use crate::metadata::CleanName;
+    use crate::repository::{BuildOptions, PackageBuilder};
+    use crate::workspace::UpdateOptions;
+    use crate::{Repository, Workspace};
+
+    /// Repository delegating every request to `inner`, package downloads go
+    /// through `hooks`
+    struct HookedRepository<H> {
+        inner: link::FileRepository,
+        hooks: H,
+    }
+
+    /// Package download hooks of a [`HookedRepository`]
+    #[async_trait::async_trait]
+    trait DownloadHooks: Sync {
+        /// Called before requesting `ranges` of the package data
+        async fn request(&self, ranges: &[Range<u64>]) {
+            let _ = ranges;
+        }
+
+        /// Chunks downloaded by a request, with their package data offset
+        fn chunks(
+            &self,
+            chunks: link::RepositoryStream<(u64, bytes::Bytes)>,
+        ) -> link::RepositoryStream<(u64, bytes::Bytes)> {
+            chunks
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl<H: DownloadHooks> link::RemoteRepository for HookedRepository<H> {
+        async fn current_version(&self) -> Result<metadata::Current, link::RepositoryError> {
+            self.inner.current_version().await
+        }
+
+        async fn current_version_for(
+            &self,
+            channel: &CleanName,
+        ) -> Result<metadata::Current, link::RepositoryError> {
+            self.inner.current_version_for(channel).await
+        }
+
+        async fn versions(&self) -> Result<metadata::Versions, link::RepositoryError> {
+            self.inner.versions().await
+        }
+
+        async fn packages(&self) -> Result<metadata::Packages, link::RepositoryError> {
+            self.inner.packages().await
+        }
+
+        async fn package_metadata(
+            &self,
+            package_name: CleanName,
+        ) -> Result<metadata::PackageMetadata, link::RepositoryError> {
+            self.inner.package_metadata(package_name).await
+        }
+
+        async fn package(
+            &self,
+            package_name: CleanName,
+            range: Range<u64>,
+        ) -> Result<link::RepositoryStream<bytes::Bytes>, link::RepositoryError> {
+            self.hooks.request(std::slice::from_ref(&range)).await;
+            let stream = self.inner.package(package_name, range.clone()).await?;
+            let chunks = stream.scan(range.start, |offset, chunk| {
+                let chunk = chunk.map(|chunk| {
+                    let chunk_offset = *offset;
+                    *offset += chunk.len() as u64;
+                    (chunk_offset, chunk)
+                });
+                future::ready(Some(chunk))
+            });
+            let chunks = self.hooks.chunks(chunks.boxed_local());
+            Ok(chunks.map_ok(|(_, chunk)| chunk).boxed_local())
+        }
+
+        async fn package_ranges(
+            &self,
+            package_name: CleanName,
+            ranges: Vec<Range<u64>>,
+        ) -> Result<link::RepositoryStream<(u64, bytes::Bytes)>, link::RepositoryError> {
+            self.hooks.request(&ranges).await;
+            let chunks = self.inner.package_ranges(package_name, ranges).await?;
+            Ok(self.hooks.chunks(chunks))
+        }
+    }
+
+    /// Hooks flipping the first byte of the next `corruptions` package
+    /// requests
+    struct Corruptions(std::sync::atomic::AtomicUsize);
+
+    impl DownloadHooks for Corruptions {
+        fn chunks(
+            &self,
+            chunks: link::RepositoryStream<(u64, bytes::Bytes)>,
+        ) -> link::RepositoryStream<(u64, bytes::Bytes)> {
+            use std::sync::atomic::Ordering;
+
+            let mut corrupt = self
+                .0
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| n.checked_sub(1))
+                .is_ok();
+            chunks
+                .map_ok(move |(offset, chunk)| {
+                    match std::mem::take(&mut corrupt) && !chunk.is_empty() {
+                        true => {
+                            let mut chunk = chunk.to_vec();
+                            chunk[0] ^= 0xff;
+                            (offset, bytes::Bytes::from(chunk))
+                        }
+                        false => (offset, chunk),
+                    }
+                })
+                .boxed_local()
+        }
+    }
+
+    /// Operation downloading `range` of the package data
+    struct DataOperation {
+        path: metadata::CleanPath,
+        range: Option<Range<u64>>,
+    }
+
+    impl DataOperation {
+        fn new(range: Option<Range<u64>>) -> (usize, Arc<DataOperation>) {
+            let path = metadata::CleanPath::from_static_str("data.bin");
+            (0, Arc::new(DataOperation { path, range }))
+        }
+    }
+
+    impl Operation for DataOperation {
+        fn kind(&self) -> metadata::OperationKind {
+            metadata::OperationKind::Add
+        }
+        fn path(&self) -> &metadata::CleanPath {
+            &self.path
+        }
+        fn slice(&self) -> Option<&metadata::CleanPath> {
+            None
+        }
+        fn slice_handler(&self) -> Option<&CleanName> {
+            None
+        }
+        fn range(&self) -> Option<Range<u64>> {
+            self.range.clone()
+        }
+        fn check_size(&self) -> u64 {
+            0
+        }
+        fn data_size(&self) -> u64 {
+            self.range.as_ref().map_or(0, |range| range.end - range.start)
+        }
+        fn final_size(&self) -> u64 {
+            self.data_size()
+        }
+        fn data_hash(&self) -> Option<&metadata::Hash> {
+            None
+        }
+        fn set_data_offset(&mut self, _offset: u64) {}
+    }
+
+    /// Operations of `ranges` numbered in order
+    fn data_operations(ranges: Vec<Option<Range<u64>>>) -> Vec<(usize, Arc<DataOperation>)> {
+        let operations = ranges.into_iter().map(DataOperation::new);
+        operations.enumerate().map(|(idx, (_, operation))| (idx, operation)).collect()
+    }
+
+    #[test]
+    fn group_ranges_by_size() {
+        let ranges = vec![0..4, 4..8, 10..12, 12..30, 30..31];
+        let groups = group_ranges(ranges.clone(), 10);
+        // a range larger than the maximum size gets its own group
+        assert_eq!(groups.iter().map(Vec::len).collect::<Vec<_>>(), [3, 1, 1]);
+        assert_eq!(groups.concat(), ranges);
+
+        let ranges: Vec<Range<u64>> = (0..40).map(|idx| idx * 2..idx * 2 + 1).collect();
+        let groups = group_ranges(ranges.clone(), u64::MAX);
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0], ranges[..MAX_RANGES_PER_REQUEST]);
+        assert_eq!(groups[1], ranges[MAX_RANGES_PER_REQUEST..]);
+
+        assert!(group_ranges(Vec::new(), 10).is_empty());
+    }
+
+    #[test]
+    fn split_operations_by_group() {
+        let groups = [vec![0..4, 6..8], vec![8..10, 10..12]];
+        let operations = data_operations(vec![
+            Some(0..4),
+            None,
+            Some(6..8),
+            Some(8..10),
+            // empty files have an empty range
+            Some(10..10),
+            Some(10..12),
+        ]);
+        let range_operations = split_operations(&groups, operations).unwrap();
+        let split: Vec<Vec<(usize, Range<u64>)>> = range_operations
+            .into_iter()
+            .map(|operations| operations.into_iter().map(|(idx, range, _)| (idx, range)).collect())
+            .collect();
+        assert_eq!(split, [vec![(0, 0..4), (2, 6..8)], vec![(3, 8..10), (4, 10..10), (5, 10..12)]]);
+
+        // an operation after the last group isn't downloaded
+        let operations = data_operations(vec![Some(0..4), Some(12..16)]);
+        let res = split_operations(&groups, operations);
+        assert!(matches!(res, Err(UpdateError::DownloadCache(_))));
+    }
+
+    /// Hooks holding back the request of the ranges starting at `delayed`
+    /// until `other_bytes` bytes of other ranges are downloaded
+    struct DelayedRanges {
+        delayed: u64,
+        other_bytes: u64,
+        downloaded: Arc<std::sync::atomic::AtomicU64>,
+        released: std::sync::atomic::AtomicBool,
+    }
+
+    #[async_trait::async_trait]
+    impl DownloadHooks for DelayedRanges {
+        async fn request(&self, ranges: &[Range<u64>]) {
+            use std::sync::atomic::Ordering;
+
+            if ranges.first().is_some_and(|range| range.start == self.delayed) {
+                while self.downloaded.load(Ordering::Relaxed) < self.other_bytes {
+                    tokio::time::sleep(Duration::from_millis(1)).await;
+                }
+                self.released.store(true, Ordering::Relaxed);
+            }
+        }
+
+        fn chunks(
+            &self,
+            chunks: link::RepositoryStream<(u64, bytes::Bytes)>,
+        ) -> link::RepositoryStream<(u64, bytes::Bytes)> {
+            use std::sync::atomic::Ordering;
 
-    write_ranges.chain(done_stream).boxed_local()
+            let downloaded = self.downloaded.clone();
+            let chunks = chunks.inspect_ok(move |(_, chunk)| {
+                downloaded.fetch_add(chunk.len() as u64, Ordering::Relaxed);
+            });
+            chunks.boxed_local()
+        }
+    }
+
+    #[test]
+    fn delayed_range_download() {
+        use std::sync::atomic::Ordering;
+
+        crate::tests::init();
+        let rt = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        let path = crate::tests::tmp_dir("delayed_range_download");
+        let repository_dir = path.join("repository");
+        fs::create_dir_all(&repository_dir).unwrap();
+        let operation_size = MIN_CONCURRENT_RANGE_SIZE;
+        let package: Vec<u8> = (0..4 * operation_size).map(|idx| (idx / 251) as u8).collect();
+        fs::write(repository_dir.join("package"), &package).unwrap();
+        let package_name = CleanName::from_static_str("package");
+
+        // each operation is downloaded by its own request, the first one is
+        // only sent once the others are downloaded
+        let operations = data_operations(
+            (0..4).map(|idx| Some(idx * operation_size..(idx + 1) * operation_size)).collect(),
+        );
+        let link = HookedRepository {
+            inner: link::FileRepository::new(repository_dir),
+            hooks: DelayedRanges {
+                delayed: 0,
+                other_bytes: 3 * operation_size,
+                downloaded: Default::default(),
+                released: Default::default(),
+            },
+        };
+        let workspace = Workspace::open(&path.join("workspace")).unwrap();
+        let file_manager = workspace.file_manager();
+        file_manager.create_update_dirs().unwrap();
+        let operation_path = |idx| file_manager.download_operation_path(&package_name, idx);
+
+        let download_stream = download_package(
+            file_manager.clone(),
+            &link,
+            &package_name,
+            operations,
+            UpdatePosition::new(),
+            4,
+            BandwidthLimiter::default(),
+        );
+        let mut held_back = 0;
+        let mut available = UpdatePosition::new();
+        rt.block_on(download_stream.try_for_each(|progress| {
+            assert!(progress.available >= available);
+            available = progress.available;
+            if !link.hooks.released.load(Ordering::Relaxed) {
+                assert_eq!(available, UpdatePosition::new());
+                held_back += 1;
+            }
+            // every operation before the available position is downloaded
+            for idx in 0..available.operation_idx {
+                let len = fs::metadata(operation_path(idx)).unwrap().len();
+                assert_eq!(len, operation_size, "operation#{} isn't downloaded", idx);
+            }
+            async { Ok(()) }
+        }))
+        .unwrap();
+        assert!(held_back > 0);
+        assert_eq!(available, UpdatePosition { operation_idx: 4, byte_idx: 0 });
+        let downloaded: Vec<u8> =
+            (0..4).flat_map(|idx| fs::read(operation_path(idx)).unwrap()).collect();
+        assert_eq!(downloaded, package);
+    }
+
+    #[test]
+    fn concurrent_download() {
+        crate::tests::init();
+        let rt = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        let v1 = CleanName::from_static_str("1");
+        let path = crate::tests::tmp_dir("concurrent_download");
+        let source_directory = path.join("source");
+        fs::create_dir_all(&source_directory).unwrap();
+        let mut seed = 1u32;
+        for name in ["a.bin", "b.bin", "c.bin", "d.bin"] {
+            let content: Vec<u8> = (0..1536 * 1024)
+                .map(|_| {
+                    seed = seed.wrapping_mul(1664525).wrapping_add(1013904223);
+                    (seed >> 24) as u8
+                })
+                .collect();
+            fs::write(source_directory.join(name), content).unwrap();
+        }
+
+        let mut repository = Repository::new(path.join("repository"));
+        fs::create_dir_all(repository.dir()).unwrap();
+        repository.init().unwrap();
+        let mut builder = PackageBuilder::new(path.join("build"), v1.clone(), source_directory);
+        builder.set_options(BuildOptions::raw());
+        crate::tests::publish_package(&mut repository, &builder);
+
+        let workspace_dir = path.join("workspace");
+        let mut workspace = Workspace::open(&workspace_dir).unwrap();
+        let link = repository.link();
+        let update_options = UpdateOptions { download_concurrency: 3, ..UpdateOptions::default() };
+        let update_stream = workspace.update(&link, Some(v1), update_options);
+        rt.block_on(update_stream.try_for_each(|_| async { Ok(()) })).unwrap();
+        workspace.remove_metadata().unwrap();
+        crate::tests::assert_fs_eq(&path.join("source"), &workspace_dir);
+    }
+
+    #[test]
+    fn throttled_download() {
+        crate::tests::init();
+        let v1 = CleanName::from_static_str("1");
+        let path = crate::tests::tmp_dir("throttled_download");
+        let source_directory = path.join("source");
+        fs::create_dir_all(&source_directory).unwrap();
+        fs::write(source_directory.join("a.bin"), vec![7u8; 2 * 1024 * 1024]).unwrap();
+
+        let mut repository = Repository::new(path.join("repository"));
+        fs::create_dir_all(repository.dir()).unwrap();
+        repository.init().unwrap();
+        let mut builder = PackageBuilder::new(path.join("build"), v1.clone(), source_directory);
+        builder.set_options(BuildOptions::raw());
+        crate::tests::publish_package(&mut repository, &builder);
+
+        // time only moves forward when the limiter sleeps
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .start_paused(true)
+            .build()
+            .unwrap();
+        let workspace_dir = path.join("workspace");
+        let mut workspace = Workspace::open(&workspace_dir).unwrap();
+        let link = repository.link();
+        let elapsed = rt.block_on(async {
+            let start = Instant::now();
+            let (update_stream, limiter) =
+                workspace.update_with_limiter(&link, Some(v1), UpdateOptions::default());
+            limiter.set_limit(Some(4 * 1024 * 1024));
+            update_stream.try_for_each(|_| async { Ok(()) }).await.unwrap();
+            start.elapsed()
+        });
+        // 2 MiB at 4 MiB/s
+        assert!(elapsed >= Duration::from_millis(490), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_millis(1000), "{:?}", elapsed);
+        workspace.remove_metadata().unwrap();
+        crate::tests::assert_fs_eq(&path.join("source"), &workspace_dir);
+    }
+
+    #[test]
+    fn corrupted_download() {
+        crate::tests::init();
+        let rt = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        let v1 = CleanName::from_static_str("1");
+        let path = crate::tests::tmp_dir("corrupted_download");
+        let source_directory = path.join("source");
+        fs::create_dir_all(&source_directory).unwrap();
+        fs::write(source_directory.join("a.bin"), vec![1u8; 96 * 1024]).unwrap();
+        fs::write(source_directory.join("b.bin"), vec![2u8; 64 * 1024]).unwrap();
+
+        let mut repository = Repository::new(path.join("repository"));
+        fs::create_dir_all(repository.dir()).unwrap();
+        repository.init().unwrap();
+        let mut builder = PackageBuilder::new(path.join("build"), v1.clone(), source_directory);
+        builder.set_options(BuildOptions::raw());
+        crate::tests::publish_package(&mut repository, &builder);
+
+        // the first download is corrupted, the operation is downloaded again
+        let link = HookedRepository {
+            inner: repository.link(),
+            hooks: Corruptions(std::sync::atomic::AtomicUsize::new(1)),
+        };
+        let workspace_dir = path.join("workspace");
+        let mut workspace = Workspace::open(&workspace_dir).unwrap();
+        let update_stream = workspace.update(&link, Some(v1.clone()), UpdateOptions::default());
+        let progress = rt.block_on(update_stream.try_collect::<Vec<_>>()).unwrap();
+        let redownloaded_bytes = progress.last().unwrap().borrow().redownloaded_bytes;
+        assert!(redownloaded_bytes > 0 && redownloaded_bytes < 160 * 1024);
+        workspace.remove_metadata().unwrap();
+        crate::tests::assert_fs_eq(&path.join("source"), &workspace_dir);
+
+        // every download is corrupted
+        let link = HookedRepository {
+            inner: repository.link(),
+            hooks: Corruptions(std::sync::atomic::AtomicUsize::new(usize::MAX)),
+        };
+        let mut workspace = Workspace::open(&path.join("workspace_corrupted")).unwrap();
+        let update_stream = workspace.update(&link, Some(v1), UpdateOptions::default());
+        let res = rt.block_on(update_stream.try_for_each(|_| async { Ok(()) }));
+        assert!(matches!(res, Err(UpdateError::DataHash { .. })));
+    }
 }
@@ -1,5 +1,6 @@
 //! Tools to manage a workspace (update, check, status, ...)
 mod apply;
+mod cache;
 mod check;
 mod download;
 pub mod progress;
@@ -15,6 +16,7 @@ use serde_json;
 
 pub use self::check::CheckError;
 pub use self::check::GlobalCheckStream;
+pub use self::download::BandwidthLimiter;
 pub use self::updater::GlobalProgressStream;
 pub use self::updater::UpdateError;
 pub use self::updater::UpdateOptions;
@@ -79,6 +81,10 @@ impl WorkspaceFileManager {
         self.metadata_dir().join("state.json")
     }
 
+    pub fn cache_dir(&self) -> PathBuf {
+        self.metadata_dir().join("cache")
+    }
+
     pub fn check_path(&self) -> PathBuf {
         self.metadata_dir().join("check.json")
     }
@@ -203,6 +209,21 @@ impl Workspace {
             .boxed_local()
     }
 
+    /// Same as [`update`](Self::update), with a handle to change the download
+    /// bandwidth limit while updating
+    pub fn update_with_limiter<'a, R>(
+        &'a mut self,
+        repository: &'a R,
+        goal_version: Option<CleanName>,
+        update_options: UpdateOptions,
+    ) -> (GlobalProgressStream<'a>, BandwidthLimiter)
+    where
+        R: RemoteRepository,
+    {
+        let limiter = update_options.download_limiter.clone();
+        (self.update(repository, goal_version, update_options), limiter)
+    }
+
     pub fn check(&mut self) -> GlobalCheckStream<'_> {
         self::check::check(self).try_flatten_stream().boxed_local()
     }
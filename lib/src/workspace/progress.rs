@@ -177,6 +177,10 @@ pub struct UpdateProgress {
     pub download_files: usize,
     /// Number of bytes to download
     pub download_bytes: u64,
+    /// Number of interrupted downloads retried
+    pub download_retries: u64,
+    /// Number of bytes downloaded again because their data hash didn't match
+    pub redownloaded_bytes: u64,
 
     /// Number of files to install
     pub apply_files: usize,
@@ -209,6 +213,8 @@ impl UpdateProgress {
             stage: UpdateStage::FindingUpdatePath,
             download_files: 0,
             download_bytes: 0,
+            download_retries: 0,
+            redownloaded_bytes: 0,
             apply_files: 0,
             apply_input_bytes: 0,
             apply_output_bytes: 0,
@@ -11,7 +11,8 @@ use futures::prelude::*;
 use tracing::{debug, error, info, warn};
 
 use super::apply::{apply_package, ApplyError, ApplyStream, AvailableForApply};
-use super::download::{download_package, DownloadStream};
+use super::cache::MetadataCache;
+use super::download::{download_package, BandwidthLimiter, DownloadStream};
 use super::progress::{Progression, SharedUpdateProgress, UpdateStage};
 use crate::link::{RemoteRepository, RepositoryError};
 use crate::metadata::v1::{State, StateUpdating};
@@ -27,6 +28,7 @@ pub enum UpdateError {
     NoPath,
     Download(RepositoryError),
     DownloadCache(std::io::Error),
+    DataHash { path: metadata::CleanPath, expected: metadata::Hash, found: metadata::Hash },
     Failed { files: usize },
     PoisonError,
 }
@@ -41,6 +43,10 @@ impl fmt::Display for UpdateError {
             UpdateError::NoPath => write!(f, "repository error: no update path found"),
             UpdateError::Download(err) => write!(f, "download error: {}", err),
             UpdateError::DownloadCache(err) => write!(f, "download cache error: {}", err),
+            UpdateError::DataHash { path, expected, found } => {
+                // operation data downloaded again still doesn't match its hash
+                write!(f, "download error: {} data hash is {}, expected {}", path, found, expected)
+            }
             UpdateError::Failed { files } => write!(f, "update failed for {} files", files),
             UpdateError::PoisonError => write!(f, "internal error: mutex poisonned"),
         }
@@ -65,6 +71,28 @@ pub struct UpdateOptions {
     ///
     /// Default to `5s`.
     pub save_state_interval: Duration,
+    /// Release channel to follow when no goal version is given (i.e. `beta`)
+    ///
+    /// Default to `None`, the repository `current` version.
+    pub channel: Option<metadata::CleanName>,
+    /// Platform of this workspace (i.e. `win64`, `linux`)
+    ///
+    /// Only platform independent packages and packages of this platform are
+    /// considered. Default to `None`, only platform independent packages.
+    pub platform: Option<metadata::CleanName>,
+    /// Cost of each package, the update path with the lowest total cost is used
+    ///
+    /// Default to the package size.
+    pub path_cost: Arc<dyn metadata::PathCost>,
+    /// Maximum number of package ranges downloaded at the same time
+    ///
+    /// Default to `1`, a single sequential download.
+    pub download_concurrency: usize,
+    /// Download bandwidth limit, clones of the limiter can change it while
+    /// updating
+    ///
+    /// Default to unlimited.
+    pub download_limiter: BandwidthLimiter,
 }
 
 impl Default for UpdateOptions {
@@ -74,6 +102,11 @@ impl Default for UpdateOptions {
             strict_meta: true,
             strict_fs: false,
             save_state_interval: Duration::from_secs(5),
+            channel: None,
+            platform: None,
+            path_cost: Arc::new(metadata::WeightedPathCost::default()),
+            download_concurrency: 1,
+            download_limiter: BandwidthLimiter::default(),
         }
     }
 }
@@ -93,7 +126,7 @@ impl<'a> UpdatePackageStream<'a> {
         file_manager: WorkspaceFileManager,
         repository: &'a R,
         package_name: &metadata::CleanName,
-        operations: Vec<(usize, Arc<metadata::v1::Operation>)>,
+        operations: Vec<(usize, Arc<metadata::v2::Operation>)>,
     ) -> Result<UpdatePackageStream<'a>, UpdateError>
     where
         R: RemoteRepository,
@@ -116,6 +149,8 @@ impl<'a> UpdatePackageStream<'a> {
         file_manager.create_update_dirs().map_err(UpdateError::LocalWorkspaceError)?;
 
         let i_available = AvailableForApply::new(available);
+        let download_concurrency = update_options.download_concurrency;
+        let download_limiter = update_options.download_limiter.clone();
         let apply_stream = apply_package(
             update_options,
             file_manager.clone(),
@@ -129,6 +164,8 @@ impl<'a> UpdatePackageStream<'a> {
             package_name,
             download_operations,
             available,
+            download_concurrency,
+            download_limiter,
         );
 
         Ok(UpdatePackageStream { state, shared_state, download_stream, apply_stream })
@@ -163,6 +200,8 @@ impl Stream for UpdatePackageStream<'_> {
 
                     let mut state = this.shared_state.borrow_mut();
                     state.downloading_operation_idx = download_progress.available.operation_idx;
+                    state.download_retries += download_progress.delta_download_retries;
+                    state.redownloaded_bytes += download_progress.delta_redownloaded_bytes;
                     delta.downloaded_files = download_progress.delta_downloaded_files;
                     delta.downloaded_bytes = download_progress.delta_downloaded_bytes;
                     this.apply_stream.notify(download_progress.available);
@@ -214,17 +253,58 @@ impl UpdateFilter {
         Self { failures: Vec::new() }
     }
 
-    pub(super) fn filter(&self, o: &metadata::v1::Operation) -> bool {
+    pub(super) fn filter(&self, o: &metadata::v2::Operation) -> bool {
         self.failures.is_empty()
             || self.failures.binary_search_by_key(&o.path(), |f| f.path()).is_ok()
+            || self.is_parent_dir(o)
+    }
+
+    /// True if `o` is a directory operation on a parent of a repaired file,
+    /// the file might be in a directory removed by a later version
+    fn is_parent_dir(&self, o: &metadata::v2::Operation) -> bool {
+        use metadata::v2::Operation;
+
+        matches!(o, Operation::MkDir { .. } | Operation::RmDir { .. })
+            && self.failures.iter().any(|f| {
+                f.path().strip_prefix(o.path().as_str()).is_some_and(|rest| rest.starts_with('/'))
+            })
+    }
+
+    /// Also repair the files failed copies read from
+    ///
+    /// Repairs start from no version, the source of a copy has the content
+    /// the copy was built for only if its operations are applied too.
+    fn add_copy_sources(&mut self, packages_metadata: &[Arc<metadata::PackageMetadata>]) {
+        if self.failures.is_empty() {
+            return;
+        }
+        loop {
+            let mut sources = Vec::new();
+            for o in packages_metadata.iter().flat_map(|package_metadata| package_metadata.iter()) {
+                if let metadata::v2::Operation::Copy(copy) = o {
+                    let is_repaired =
+                        |path| self.failures.binary_search_by_key(&path, |f| f.path()).is_ok();
+                    if is_repaired(&copy.common.path) && !is_repaired(&copy.from_path) {
+                        sources.push(metadata::v1::Failure::Path { path: copy.from_path.clone() });
+                    }
+                }
+            }
+            if sources.is_empty() {
+                return;
+            }
+            self.failures.extend(sources);
+            self.failures.sort();
+            self.failures.dedup();
+        }
     }
 
-    fn filter_map(&self, o: &metadata::v1::Operation) -> Option<metadata::v1::Operation> {
+    fn filter_map(&self, o: &metadata::v2::Operation) -> Option<metadata::v2::Operation> {
         if self.failures.is_empty()
             || self
                 .failures
                 .binary_search_by_key(&(o.path(), o.slice()), |f| (f.path(), f.slice()))
                 .is_ok()
+            || self.is_parent_dir(o)
         {
             Some(o.clone())
         } else if o.slice().is_some()
@@ -250,6 +330,7 @@ struct UpdateArg<'a, R> {
     goal_version: metadata::CleanName,
     filter: UpdateFilter,
     main_stage: UpdateStage,
+    yanked: Arc<[metadata::CleanName]>,
 }
 
 // get -> stream of bytes -> write -> progression
@@ -264,12 +345,12 @@ pub(crate) async fn update<'a, R>(
 where
     R: RemoteRepository,
 {
-    let goal_version = if let Some(goal_version) = goal_version {
-        goal_version
-    } else {
-        let current_version = repository.current_version().map_err(UpdateError::Repository).await?;
-        current_version.version().clone()
-    };
+    // keep the options out of the returned future while waiting for the repository
+    let update_options = Box::new(update_options);
+    let cache = MetadataCache::new(workspace.file_manager().cache_dir());
+    let (goal_version, yanked) =
+        Box::pin(resolve_goal_version(repository, cache, goal_version, &update_options)).await?;
+    let update_options = *update_options;
     info!("update to {}", goal_version);
 
     // Load current workspace state
@@ -345,6 +426,7 @@ where
         goal_version: goal_version_n,
         filter: UpdateFilter::allows_all(),
         main_stage: UpdateStage::Updating,
+        yanked: yanked.clone(),
     };
 
     // 1. try to the update normally
@@ -360,6 +442,7 @@ where
         goal_version: goal_version_r,
         filter: UpdateFilter { failures },
         main_stage: UpdateStage::Repairing,
+        yanked,
     };
     // 2. try to repair update errors
     let repair_stream = future::lazy(move |_| {
@@ -416,17 +499,56 @@ where
     Ok(Either::Left(final_stream))
 }
 
+/// Resolve the version to update to and the list of yanked versions
+///
+/// A yanked goal version is replaced by the current version.
+async fn resolve_goal_version<R>(
+    repository: &R,
+    cache: MetadataCache,
+    goal_version: Option<metadata::CleanName>,
+    update_options: &UpdateOptions,
+) -> Result<(metadata::CleanName, Arc<[metadata::CleanName]>), UpdateError>
+where
+    R: RemoteRepository,
+{
+    let versions = cache.versions(repository).await.map_err(UpdateError::Repository)?;
+    let yanked: Arc<[metadata::CleanName]> = versions
+        .iter()
+        .filter(|version| version.yanked().is_some())
+        .map(|version| version.revision().clone())
+        .collect();
+    let goal_version = match goal_version {
+        Some(goal_version) if !yanked.contains(&goal_version) => goal_version,
+        goal_version => {
+            if let Some(goal_version) = goal_version {
+                warn!("version {} is yanked, update to the current version instead", goal_version);
+            }
+            let current_version = cache
+                .current_version(repository, update_options.channel.as_ref())
+                .await
+                .map_err(UpdateError::Repository)?;
+            current_version.version().clone()
+        }
+    };
+    Ok((goal_version, yanked))
+}
+
 async fn update_path<R>(
     initial_state: State,
     repository: &R,
+    cache: &MetadataCache,
     goal_version: &metadata::CleanName,
-    check: bool,
+    update_options: &UpdateOptions,
+    yanked: &[metadata::CleanName],
 ) -> Result<Option<(Vec<Arc<metadata::PackageMetadata>>, StateUpdating)>, UpdateError>
 where
     R: RemoteRepository,
 {
-    let packages = repository.packages().map_err(UpdateError::Repository).await?;
-    let maybe_path = shortest_path(initial_state, packages.as_slice(), goal_version, check)?;
+    let packages = cache.packages(repository).map_err(UpdateError::Repository).await?;
+    // never go through a yanked version
+    let packages: Vec<_> =
+        packages.as_slice().iter().filter(|p| !yanked.contains(&p.to)).cloned().collect();
+    let maybe_path = shortest_path(initial_state, &packages, goal_version, update_options)?;
     let (path, first_package_state) = match maybe_path {
         Some(x) => x,
         None => return Ok(None),
@@ -437,8 +559,8 @@ where
 
     info!("found update path {:?}", package_names);
 
-    let packages_metadata = stream::iter(package_names.into_iter())
-        .map(|package_name| repository.package_metadata(package_name))
+    let packages_metadata = stream::iter(path.into_iter())
+        .map(|package| cache.package_metadata(repository, package))
         .buffered(4)
         .map_ok(Arc::new)
         .try_collect()
@@ -449,20 +571,25 @@ where
 }
 
 async fn update_internal<'a, R>(
-    update_arg: UpdateArg<'a, R>,
+    mut update_arg: UpdateArg<'a, R>,
 ) -> Result<impl Stream<Item = Result<SharedUpdateProgress, UpdateError>> + 'a, UpdateError>
 where
     R: RemoteRepository,
 {
+    let cache = MetadataCache::new(update_arg.file_manager.cache_dir());
     let maybe_path = update_path(
         update_arg.initial_state,
         update_arg.repository,
+        &cache,
         &update_arg.goal_version,
-        update_arg.update_options.check,
+        &update_arg.update_options,
+        &update_arg.yanked,
     )
     .await?;
     let packages_metadata = match maybe_path {
         Some((packages_metadata, first_package_state)) => {
+            update_arg.filter.add_copy_sources(&packages_metadata);
+
             // Update global progress with objectives
             update_arg.global_progression.borrow_mut().push_steps(
                 &packages_metadata,
@@ -490,6 +617,7 @@ where
             let state = &mut *state_p.borrow_mut();
             state.from = package_metadata.from().cloned();
             state.to = package_metadata.to().clone();
+            state.platform = package_metadata.platform().cloned();
             debug!(
                 "begin {} package = {}, available = {:?}, applied = {:?}",
                 if state.check_only { "check" } else { "update" },
@@ -501,7 +629,7 @@ where
         };
 
         // Build list of operations to do
-        let operations: Vec<(usize, Arc<metadata::v1::Operation>)> = package_metadata
+        let operations: Vec<(usize, Arc<metadata::v2::Operation>)> = package_metadata
             .iter()
             .enumerate()
             .filter_map(|(idx, o)| {
@@ -520,9 +648,10 @@ where
 
         // Write package check file
         {
-            let check_operations: Vec<metadata::v1::Operation> =
+            let check_operations: Vec<metadata::v2::Operation> =
                 package_metadata.iter().filter_map(|o| o.as_check_operation()).collect();
-            let checks = metadata::WorkspaceChecks::V1 { operations: check_operations };
+            let checks =
+                metadata::WorkspaceChecks::new(package_metadata.hash_algorithm(), check_operations);
             update_arg.file_manager.write_checks(&checks).map_err(UpdateError::LocalCheckError)?;
         }
 
@@ -561,21 +690,25 @@ fn shortest_path<'a, P>(
     working_state: State,
     packages: &'a [P],
     goal_version: &metadata::CleanName,
-    check: bool,
+    update_options: &UpdateOptions,
 ) -> Result<Option<(Vec<&'a P>, StateUpdating)>, UpdateError>
 where
     P: Package,
 {
+    let platform = update_options.platform.as_ref();
     let mut path = Vec::new();
     let (start, maybe_state) = match working_state {
         State::New => (None, None),
         State::Stable { version } => (Some(version), None),
         State::Corrupted { version, .. } => (Some(version), None),
         State::Updating(state) => {
-            match packages
-                .iter()
-                .find(|&package| package.from() == state.from.as_ref() && package.to() == &state.to)
-            {
+            // resume the package being applied, not the cheapest one
+            match packages.iter().find(|&package| {
+                package.is_for_platform(platform)
+                    && package.from() == state.from.as_ref()
+                    && package.to() == &state.to
+                    && package.platform() == state.platform.as_ref()
+            }) {
                 Some(package) => {
                     path.push(package);
                     (Some(state.to.clone()), Some(state))
@@ -585,7 +718,8 @@ where
         }
     };
     if start.as_ref() != Some(goal_version) {
-        match metadata::shortest_path(start.as_ref(), goal_version, packages) {
+        let path_cost = &*update_options.path_cost;
+        match metadata::shortest_path(start.as_ref(), goal_version, packages, platform, path_cost) {
             Some(ref mut npath) => path.append(npath),
             _ => return Err(UpdateError::NoPath),
         }
@@ -594,7 +728,7 @@ where
         let p0 = path[0];
         let from = p0.from().cloned();
         let to = p0.to().clone();
-        let state = match maybe_state {
+        let mut state = match maybe_state {
             Some(mut state) => {
                 state.from = from;
                 state.to = to;
@@ -602,9 +736,12 @@ where
             }
             None => StateUpdating::new(from, to, Vec::new()),
         };
+        state.platform = p0.platform().cloned();
         Ok(Some((path, state)))
-    } else if check {
-        match packages.iter().find(|p| p.to() == goal_version) {
+    } else if update_options.check {
+        let path_cost = &*update_options.path_cost;
+        let to_goal = packages.iter().filter(|p| p.to() == goal_version);
+        match metadata::cheapest_package(to_goal, platform, path_cost) {
             Some(p0) => {
                 let path = vec![p0];
                 let mut state = StateUpdating::new(
@@ -612,6 +749,7 @@ where
                     goal_version.clone(),
                     Vec::new(),
                 );
+                state.platform = p0.platform().cloned();
                 state.check_only = true;
                 Ok(Some((path, state)))
             }
@@ -638,4 +776,156 @@ mod tests {
         let update_ret_size = size_of_fn4_ret(update::<AutoRepository>);
         assert!(update_ret_size < 256, "update_ret_size = {} < 128", update_ret_size);
     }
+
+    #[test]
+    fn shortest_path_platform() {
+        let v1 = metadata::CleanName::from_static_str("1");
+        let v2 = metadata::CleanName::from_static_str("2");
+        let win64 = metadata::CleanName::from_static_str("win64");
+        let linux = metadata::CleanName::from_static_str("linux");
+        let package = |from: Option<&metadata::CleanName>, to: &metadata::CleanName, platform| {
+            metadata::v1::Package {
+                from: from.cloned(),
+                to: to.clone(),
+                size: 10,
+                platform,
+                hints: Default::default(),
+                metadata_hash: None,
+            }
+        };
+        let packages = vec![
+            package(None, &v1, None),
+            package(Some(&v1), &v2, Some(win64.clone())),
+            package(None, &v2, Some(linux.clone())),
+        ];
+        let state = State::Stable { version: v1.clone() };
+
+        let mut update_options = UpdateOptions { platform: Some(win64), ..Default::default() };
+        let (path, _) =
+            shortest_path(state.clone(), &packages, &v2, &update_options).unwrap().unwrap();
+        assert_eq!(path.len(), 1);
+        assert_eq!(path[0].from.as_ref(), Some(&v1));
+
+        update_options.platform = Some(linux.clone());
+        let (path, _) =
+            shortest_path(state.clone(), &packages, &v2, &update_options).unwrap().unwrap();
+        assert_eq!(path.len(), 1);
+        assert_eq!(path[0].platform.as_ref(), Some(&linux));
+
+        update_options.platform = None;
+        assert!(shortest_path(state, &packages, &v2, &update_options).is_err());
+    }
+
+    #[test]
+    fn shortest_path_cheapest_package() {
+        let v1 = metadata::CleanName::from_static_str("1");
+        let v2 = metadata::CleanName::from_static_str("2");
+        let win64 = metadata::CleanName::from_static_str("win64");
+        let package = |size, platform| metadata::v1::Package {
+            from: Some(v1.clone()),
+            to: v2.clone(),
+            size,
+            platform,
+            hints: Default::default(),
+            metadata_hash: None,
+        };
+        let state = State::Stable { version: v1.clone() };
+        let update_options = UpdateOptions { platform: Some(win64.clone()), ..Default::default() };
+
+        // the returned package is the one the path cost was computed with
+        let packages = vec![package(10, None), package(50, Some(win64.clone()))];
+        let (path, _) =
+            shortest_path(state.clone(), &packages, &v2, &update_options).unwrap().unwrap();
+        assert_eq!(path.len(), 1);
+        assert_eq!(path[0].size, 10);
+
+        // platform specific packages win ties
+        let packages = vec![package(10, None), package(10, Some(win64.clone()))];
+        let (path, _) = shortest_path(state, &packages, &v2, &update_options).unwrap().unwrap();
+        assert_eq!(path[0].platform.as_ref(), Some(&win64));
+
+        // the cheapest package is checked too
+        let packages = vec![package(50, Some(win64.clone())), package(10, None)];
+        let state = State::Stable { version: v2.clone() };
+        let update_options = UpdateOptions { check: true, ..update_options };
+        let (path, state) = shortest_path(state, &packages, &v2, &update_options).unwrap().unwrap();
+        assert!(state.check_only);
+        assert_eq!(path[0].size, 10);
+    }
+
+    #[test]
+    fn shortest_path_resume() {
+        let v1 = metadata::CleanName::from_static_str("1");
+        let v2 = metadata::CleanName::from_static_str("2");
+        let win64 = metadata::CleanName::from_static_str("win64");
+        let package = |size, platform| metadata::v1::Package {
+            from: Some(v1.clone()),
+            to: v2.clone(),
+            size,
+            platform,
+            hints: Default::default(),
+            metadata_hash: None,
+        };
+        let packages = vec![package(10, None), package(50, Some(win64.clone()))];
+        let update_options = UpdateOptions { platform: Some(win64.clone()), ..Default::default() };
+
+        // the package being applied is resumed even if it isn't the cheapest one
+        for (platform, size) in [(Some(win64.clone()), 50), (None, 10)] {
+            let mut state = StateUpdating::new(Some(v1.clone()), v2.clone(), Vec::new());
+            state.platform = platform.clone();
+            let (path, state) =
+                shortest_path(State::Updating(state), &packages, &v2, &update_options)
+                    .unwrap()
+                    .unwrap();
+            assert_eq!(path.len(), 1);
+            assert_eq!(path[0].size, size);
+            assert_eq!(state.platform, platform);
+        }
+    }
+
+    #[test]
+    fn shortest_path_cost() {
+        let versions: Vec<_> = ["1", "2", "3", "4", "5"]
+            .into_iter()
+            .map(metadata::CleanName::from_static_str)
+            .collect();
+        let mut packages: Vec<_> = versions
+            .windows(2)
+            .map(|w| metadata::v1::Package {
+                from: Some(w[0].clone()),
+                to: w[1].clone(),
+                size: 20,
+                platform: None,
+                hints: Default::default(),
+                metadata_hash: None,
+            })
+            .collect();
+        packages.push(metadata::v1::Package {
+            from: None,
+            to: versions[4].clone(),
+            size: 100,
+            platform: None,
+            hints: Default::default(),
+            metadata_hash: None,
+        });
+        let state = State::Stable { version: versions[0].clone() };
+        let goal = &versions[4];
+
+        let mut update_options = UpdateOptions::default();
+        let (path, _) =
+            shortest_path(state.clone(), &packages, goal, &update_options).unwrap().unwrap();
+        assert_eq!(path.len(), 4);
+
+        let per_package = metadata::WeightedPathCost { per_package: 50, ..Default::default() };
+        update_options.path_cost = Arc::new(per_package);
+        let (path, _) =
+            shortest_path(state.clone(), &packages, goal, &update_options).unwrap().unwrap();
+        assert_eq!(path.len(), 1);
+
+        packages[4].hints.cached = true;
+        let cached = metadata::WeightedPathCost { cached_byte_percent: 50, ..Default::default() };
+        update_options.path_cost = Arc::new(cached);
+        let (path, _) = shortest_path(state, &packages, goal, &update_options).unwrap().unwrap();
+        assert!(path[0].is_standalone());
+    }
 }
@@ -101,29 +101,29 @@ impl Repo for RemoteRepository {
     ) -> Result<Response<Self::StatusStream>, Status> {
         let inner = request.into_inner();
         let repo_request = inner.path.clone();
-        let repo_watch = inner.path.clone();
         let platforms = inner.platforms;
         let options = inner
             .options
             .unwrap_or(Options { build_path: ".".to_string(), upload_path: ".".to_string() });
 
-        let mut subfolders = Vec::new();
-
-        for host in platforms.clone() {
-            match Platforms::try_from(host) {
-                Ok(Platforms::Win64) => subfolders.push("/win64"),
-                Ok(Platforms::MacosX8664) => subfolders.push("/macos_x86_64"),
-                Ok(Platforms::MacosArm64) => subfolders.push("/macos_arm64"),
-                Ok(Platforms::Linux) => subfolders.push("/linux"),
-                _ => {}
-            }
+        // one status per requested platform, packages of other platforms
+        // aren't listed
+        let mut platforms: Vec<(String, Option<CleanName>)> = platforms
+            .into_iter()
+            .filter_map(|platform| Platforms::try_from(platform).ok())
+            .map(|platform| platform_repository(&repo_request, platform))
+            .collect();
+        if platforms.is_empty() {
+            platforms.push((repo_request, None));
         }
+        let mut repo_paths: Vec<String> = platforms.iter().map(|(path, _)| path.clone()).collect();
+        repo_paths.dedup();
 
         let request_future = async move {
             let mut state = RepoStatusOutput { status: Vec::new() };
-            for folder in subfolders.clone() {
+            for (repo_path, platform) in platforms.iter() {
                 state.status.push(
-                    match repo_state(repo_request.clone() + "/" + folder, options.clone()) {
+                    match repo_state(repo_path.clone(), platform.as_ref(), options.clone()) {
                         Ok(local_state) => local_state,
                         Err(err) => return Err(Status::internal(err)),
                     },
@@ -148,31 +148,31 @@ impl Repo for RemoteRepository {
             )
             .unwrap();
 
-            for folder in subfolders.clone() {
-                if Path::new(&(repo_request.clone() + folder + "/current")).exists() {
+            for repo_path in repo_paths {
+                if Path::new(&(repo_path.clone() + "/current")).exists() {
                     watcher
                         .watch(
-                            Path::new(&(repo_request.clone() + folder + "/current")),
+                            Path::new(&(repo_path.clone() + "/current")),
                             RecursiveMode::NonRecursive,
                         )
                         .unwrap();
                 }
                 watcher
                     .watch(
-                        Path::new(&(repo_request.clone() + folder + "/packages")),
+                        Path::new(&(repo_path.clone() + "/packages")),
                         RecursiveMode::NonRecursive,
                     )
                     .unwrap();
                 watcher
                     .watch(
-                        Path::new(&(repo_request.clone() + folder + "/versions")),
+                        Path::new(&(repo_path.clone() + "/versions")),
                         RecursiveMode::NonRecursive,
                     )
                     .unwrap();
-                if Path::new(&(repo_request.clone() + folder + &options.build_path)).exists() {
+                if Path::new(&(repo_path.clone() + &options.build_path)).exists() {
                     watcher
                         .watch(
-                            Path::new(&(repo_request.clone() + folder + "/.build")),
+                            Path::new(&(repo_path.clone() + "/.build")),
                             RecursiveMode::NonRecursive,
                         )
                         .unwrap();
@@ -184,8 +184,8 @@ impl Repo for RemoteRepository {
             tokio::task::spawn(async move {
                 let _watcher = watcher;
                 while let Some(Ok(_)) = local_rx.recv().await {
-                    for folder in subfolders.clone() {
-                        match repo_state(repo_watch.clone() + folder, options.clone()) {
+                    for (repo_path, platform) in platforms.iter() {
+                        match repo_state(repo_path.clone(), platform.as_ref(), options.clone()) {
                             Ok(new_state) => {
                                 repo_array.status.push(new_state);
                             }
@@ -217,7 +217,7 @@ impl Repo for RemoteRepository {
 
         let repository_path = inner.path;
         let repo = Repository::new(PathBuf::from(repository_path));
-        match repo.current_version() {
+        match repo.current_version(None) {
             Ok(version) => {
                 let reply = CurrentVersion { version: version.version().to_string() };
                 Ok(Response::new(reply))
@@ -238,7 +238,7 @@ impl Repo for RemoteRepository {
         let version_string = CleanName::new(inner.version).unwrap();
 
         let reply = Empty {};
-        match repo.set_current_version(&version_string) {
+        match repo.set_current_version(None, &version_string) {
             Ok(_) => {
                 tracing::info!(
                     "{} is now the current version for {}",
@@ -268,7 +268,7 @@ impl Repo for RemoteRepository {
 
         let description = inner.description;
         let description = description.unwrap_or_default();
-        let version = v1::Version { revision: version_string.clone(), description };
+        let version = v1::Version { revision: version_string.clone(), description, yanked: None };
         let reply = Empty {};
         match repo.register_version(&version) {
             Ok(_) => {
@@ -414,6 +414,14 @@ impl Repo for RemoteRepository {
         if let Some(num_threads) = inner.num_threads {
             builder.set_num_threads(num_threads.try_into().unwrap());
         }
+        if let Some(platform) = inner.platform {
+            match CleanName::new(platform) {
+                Ok(platform) => builder.set_platform(Some(platform)),
+                Err(err) => {
+                    return Err(Status::invalid_argument(err.to_string()));
+                }
+            }
+        }
         let mut options = BuildOptions::default();
         if let Some(compressors) = Some(inner.compressors) {
             options.compressors = compressors
@@ -524,7 +532,51 @@ impl Repo for RemoteRepository {
     }
 }
 
-fn repo_state(path: String, options: Options) -> Result<RepoStatus, String> {
+/// Name of `platform` in package names (i.e. `win64`)
+fn platform_name(platform: Platforms) -> CleanName {
+    CleanName::from_static_str(match platform {
+        Platforms::Win64 => "win64",
+        Platforms::MacosX8664 => "macos_x86_64",
+        Platforms::MacosArm64 => "macos_arm64",
+        Platforms::Linux => "linux",
+    })
+}
+
+/// Repository path and package platform filter of `platform` in the
+/// repository at `path`
+///
+/// Older deployments keep one repository per platform in a `<path>/<platform>`
+/// subfolder (i.e. `repo/win64`) with untagged packages, it is used if it
+/// exists. Otherwise packages of the `path` repository are filtered by their
+/// platform tag.
+fn platform_repository(path: &str, platform: Platforms) -> (String, Option<CleanName>) {
+    let platform = platform_name(platform);
+    let legacy_path = format!("{}/{}", path, platform);
+    match Path::new(&legacy_path).join("versions").exists() {
+        true => (legacy_path, None),
+        false => (path.to_string(), Some(platform)),
+    }
+}
+
+/// `content` without the platform subfolders of older deployments
+///
+/// Their tokens are scoped to the repository path, without platform
+/// subfolder, and grant access to every platform.
+fn without_platform_folders(content: &str) -> String {
+    [Platforms::Win64, Platforms::MacosX8664, Platforms::MacosArm64, Platforms::Linux]
+        .into_iter()
+        .fold(content.to_string(), |content, platform| {
+            content.replace(&format!("/{}", platform_name(platform)), "")
+        })
+}
+
+/// Status of the repository at `path`, only packages installable on
+/// `platform` are listed if it is set
+fn repo_state(
+    path: String,
+    platform: Option<&CleanName>,
+    options: Options,
+) -> Result<RepoStatus, String> {
     let repo = Repository::new(PathBuf::from(path.clone()));
     let mut list_versions: Vec<Versions> = Vec::new();
     match repo.versions() {
@@ -540,19 +592,28 @@ fn repo_state(path: String, options: Options) -> Result<RepoStatus, String> {
         Err(error) => return Err("Versions : ".to_owned() + &error.to_string()),
     }
 
-    let current_version = match repo.current_version() {
+    let current_version = match repo.current_version(None) {
         Ok(value) => value.version().to_string(),
         Err(_) => "-".to_string(),
     };
 
     let mut list_packages = Vec::new();
+    let mut platforms = Vec::new();
 
     let size = match repo.packages() {
         Ok(value) => {
-            for val in value.iter() {
+            let is_listed = |p: &&dyn libspeedupdate::metadata::Package| {
+                platform.is_none() || p.is_for_platform(platform)
+            };
+            for val in value.iter().filter(is_listed) {
                 list_packages.push(val.package_data_name().to_string());
+                if let Some(platform) = val.platform() {
+                    if !platforms.contains(&platform.to_string()) {
+                        platforms.push(platform.to_string());
+                    }
+                }
             }
-            value.iter().map(|p| p.size()).sum::<u64>()
+            value.iter().filter(is_listed).map(|p| p.size()).sum::<u64>()
         }
         Err(error) => return Err("Packages: ".to_owned() + &error.to_string()),
     };
@@ -586,6 +647,7 @@ fn repo_state(path: String, options: Options) -> Result<RepoStatus, String> {
         packages: list_packages,
         available_packages,
         available_binaries,
+        platforms,
     };
 
     Ok(state)
@@ -710,13 +772,8 @@ where
             let content_without_ascii: Vec<_> =
                 content_string.chars().filter(|&c| !(c as u32 > 0x001F)).collect();
             let content_string_without_ascii: String = content_without_ascii.into_iter().collect();
-            let content_without_path = content_string_without_ascii
-                .replace("/win64", "")
-                .replace("/macos_arm64", "")
-                .replace("/macos_x86_64", "")
-                .replace("/linux", "");
 
-            tracing::info!("content : {:?}", content_without_path);
+            tracing::info!("content : {:?}", content_string_without_ascii);
 
             match parts.headers.get("authorization") {
                 Some(t) => {
@@ -725,8 +782,13 @@ where
                     let t_string = t.to_str().unwrap().replace("Bearer ", "");
                     match decode::<Claims>(&t_string, decoding_key, validation) {
                         Ok(token_data) => {
-                            // Compare body with scope
-                            if token_data.claims.scope == content_without_path {
+                            // Compare body with scope, platform subfolders
+                            // of older deployments are ignored
+                            let scope = &token_data.claims.scope;
+                            if *scope == content_string_without_ascii
+                                || without_platform_folders(scope)
+                                    == without_platform_folders(&content_string_without_ascii)
+                            {
                                 let body = AxumBody::from(content);
                                 let response = inner
                                     .call(http::Request::from_parts(parts, body))